@@ -0,0 +1,77 @@
+extern crate rustyxchange;
+
+use rustyxchange::client::{Feed, MarketDataClient};
+use rustyxchange::types::{MarketData, OrderEvent, PriceLevel};
+use std::{env, thread, time};
+
+// prints every update from a running exchange's market data feed.
+// takes the address of the feed, 127.0.0.1:4567 by default, `depth`, `trades` or `orders` to print the depth of book,
+// trade or order by order feed instead of the top of book, and the tickers to print, every symbol if there are none
+fn main() {
+    let addr = env::args().nth(1).unwrap_or_else(|| "127.0.0.1:4567".to_string());
+    let feed = match env::args().nth(2).as_deref() {
+        Some("depth") => Feed::Depth,
+        Some("trades") => Feed::Trades,
+        Some("orders") => Feed::Orders,
+        _ => Feed::TopOfBook,
+    };
+    let tickers: Vec<String> = env::args().skip(3).collect();
+    let tickers: Vec<&str> = tickers.iter().map(String::as_str).collect();
+    let mut client = MarketDataClient::connect_to(&addr, feed, &tickers).expect("[ERROR] failed to connect to market data feed");
+    println!("[INFO] listening to {:?} market data on {}", feed, addr);
+
+    loop {
+        for update in client.poll().expect("[ERROR] market data feed failed") {
+            match update {
+                MarketData::TopOfBook(price_info) => println!(
+                    "{}: {} x {} / {} x {}",
+                    price_info.get_symbol().ticker(),
+                    price_info.best_bid,
+                    price_info.bid_size,
+                    price_info.best_ask,
+                    price_info.ask_size
+                ),
+                MarketData::Depth(depth_info) => println!(
+                    "{}: bids {} / asks {}",
+                    depth_info.get_symbol().ticker(),
+                    levels(&depth_info.bids),
+                    levels(&depth_info.asks)
+                ),
+                MarketData::Trade(trade) => println!(
+                    "{}: {:?} {} @ {}",
+                    trade.get_symbol().ticker(),
+                    trade.aggressor_side,
+                    trade.quantity,
+                    trade.price
+                ),
+                MarketData::Order(order_event_info) => {
+                    let ticker = order_event_info.get_symbol().ticker();
+                    match order_event_info.event {
+                        OrderEvent::Added(order_ref, side, price, quantity) => println!("{}: add #{} {:?} {} @ {}", ticker, order_ref, side, quantity, price),
+                        OrderEvent::Executed(order_ref, quantity, _) => println!("{}: execute #{} {}", ticker, order_ref, quantity),
+                        OrderEvent::Cancelled(order_ref, quantity) => println!("{}: cancel #{} {}", ticker, order_ref, quantity),
+                        OrderEvent::Replaced(order_ref, new_order_ref, price, quantity) => {
+                            println!("{}: replace #{} with #{} {} @ {}", ticker, order_ref, new_order_ref, quantity, price)
+                        }
+                    }
+                }
+                MarketData::Orders(resting_orders) => println!(
+                    "{}: {} resting bids / {} resting asks",
+                    resting_orders.get_symbol().ticker(),
+                    resting_orders.bids.values().map(|order_lst| order_lst.len()).sum::<usize>(),
+                    resting_orders.asks.values().map(|order_lst| order_lst.len()).sum::<usize>()
+                ),
+            }
+        }
+        thread::sleep(time::Duration::from_millis(1));
+    }
+}
+
+// prints price levels best first, as price x quantity
+fn levels(levels: &[PriceLevel]) -> String {
+    levels
+        .iter()
+        .map(|level| format!("{} x {}", level.price, level.quantity))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -0,0 +1,536 @@
+// the python module takes the crate's name, so the crate itself goes by another
+extern crate rustyxchange as exchange;
+
+use pyo3::exceptions::{PyConnectionError, PyPermissionError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+use std::{thread, time};
+
+use exchange::client::{self, ClientError, Feed};
+use exchange::types::*;
+
+// python bindings of the client library. orders, statuses and enums use the same names as the json protocol,
+// so `place("GOOG", "Buy", 10, order_type="Limit", price=100500)` sends what
+// `{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Limit", "price": 100500, "quantity": 10}` would
+
+/// turns a client error into the closest python exception
+fn to_py_err(e: ClientError) -> PyErr {
+    match e {
+        ClientError::Io(e) => e.into(),
+        ClientError::AuthRejected(reason) => PyPermissionError::new_err(reason),
+        e => PyConnectionError::new_err(e.to_string()),
+    }
+}
+
+fn required_price(price: Option<u64>, name: &str) -> PyResult<u64> {
+    price.ok_or_else(|| PyValueError::new_err(format!("{} is needed by this order type", name)))
+}
+
+fn parse_order_type(name: &str, price: Option<u64>, stop_price: Option<u64>) -> PyResult<OrderType> {
+    match name {
+        "Market" => Ok(OrderType::Market),
+        "Limit" => Ok(OrderType::Limit(required_price(price, "price")?)),
+        "Stop" => Ok(OrderType::Stop(required_price(stop_price, "stop_price")?)),
+        "StopLimit" => Ok(OrderType::StopLimit(required_price(stop_price, "stop_price")?, required_price(price, "price")?)),
+        _ => Err(PyValueError::new_err(format!("unknown order type {}", name))),
+    }
+}
+
+fn parse_side(name: &str) -> PyResult<OrderSide> {
+    match name {
+        "Buy" => Ok(OrderSide::Buy),
+        "Sell" => Ok(OrderSide::Sell),
+        _ => Err(PyValueError::new_err(format!("unknown side {}", name))),
+    }
+}
+
+fn parse_time_in_force(name: &str) -> PyResult<TimeInForce> {
+    match name {
+        "GoodTillCancel" => Ok(TimeInForce::GoodTillCancel),
+        "Day" => Ok(TimeInForce::Day),
+        "ImmediateOrCancel" => Ok(TimeInForce::ImmediateOrCancel),
+        "FillOrKill" => Ok(TimeInForce::FillOrKill),
+        _ => Err(PyValueError::new_err(format!("unknown time in force {}", name))),
+    }
+}
+
+fn parse_self_trade_prevention(name: Option<&str>) -> PyResult<Option<SelfTradePrevention>> {
+    match name {
+        None => Ok(None),
+        Some("CancelNewest") => Ok(Some(SelfTradePrevention::CancelNewest)),
+        Some("CancelOldest") => Ok(Some(SelfTradePrevention::CancelOldest)),
+        Some("CancelBoth") => Ok(Some(SelfTradePrevention::CancelBoth)),
+        Some("DecrementAndCancel") => Ok(Some(SelfTradePrevention::DecrementAndCancel)),
+        Some(name) => Err(PyValueError::new_err(format!("unknown self trade prevention mode {}", name))),
+    }
+}
+
+/// returns the order a status or cancel names, which has to be by exactly one of its ids
+fn parse_order_ref(order_id: Option<u32>, client_order_id: Option<u64>) -> PyResult<OrderRef> {
+    match (order_id, client_order_id) {
+        (Some(order_id), None) => Ok(OrderRef::Exchange(order_id)),
+        (None, Some(client_order_id)) => {
+            check_client_order_id(client_order_id).map_err(|reason| PyValueError::new_err(reason.message()))?;
+            Ok(OrderRef::Client(client_order_id))
+        }
+        _ => Err(PyValueError::new_err("exactly one of order_id and client_order_id is needed")),
+    }
+}
+
+/// turns a status into a dict with the same fields as its json version
+fn status_to_dict<'py>(py: Python<'py>, status_report: StatusReport) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    if let Some(client_order_id) = status_report.client_order_id {
+        dict.set_item("client_order_id", client_order_id)?;
+    }
+    match status_report.status {
+        OrderStatus::Filled(order_id, cost) => {
+            dict.set_item("status", "Filled")?;
+            dict.set_item("order_id", order_id)?;
+            dict.set_item("cost", cost)?;
+        }
+        OrderStatus::PartiallyFilled(order_id, filled_quantity, cost) => {
+            dict.set_item("status", "PartiallyFilled")?;
+            dict.set_item("order_id", order_id)?;
+            dict.set_item("filled_quantity", filled_quantity)?;
+            dict.set_item("cost", cost)?;
+        }
+        OrderStatus::Waiting(order_id) => {
+            dict.set_item("status", "Waiting")?;
+            dict.set_item("order_id", order_id)?;
+        }
+        OrderStatus::Rejected(order_id, reason) => {
+            dict.set_item("status", "Rejected")?;
+            dict.set_item("order_id", order_id)?;
+            dict.set_item("code", reason.code())?;
+            dict.set_item("reason", reason.message())?;
+        }
+        OrderStatus::Canceled(order_id) => {
+            dict.set_item("status", "Canceled")?;
+            dict.set_item("order_id", order_id)?;
+        }
+        OrderStatus::Replaced(order_id, price, quantity) => {
+            dict.set_item("status", "Replaced")?;
+            dict.set_item("order_id", order_id)?;
+            dict.set_item("price", price)?;
+            dict.set_item("quantity", quantity)?;
+        }
+        OrderStatus::SelfTradePrevented(order_id, quantity) => {
+            dict.set_item("status", "SelfTradePrevented")?;
+            dict.set_item("order_id", order_id)?;
+            dict.set_item("quantity", quantity)?;
+        }
+        OrderStatus::Execution(report) => {
+            dict.set_item("status", "Execution")?;
+            dict.set_item("order_id", report.order_id)?;
+            dict.set_item("execution_id", report.execution_id)?;
+            dict.set_item("price", report.price)?;
+            dict.set_item("quantity", report.quantity)?;
+            dict.set_item("leaves_quantity", report.leaves_quantity)?;
+            dict.set_item("cumulative_quantity", report.cumulative_quantity)?;
+            dict.set_item("average_price", report.average_price)?;
+            dict.set_item(
+                "liquidity",
+                match report.liquidity {
+                    Liquidity::Maker => "Maker",
+                    Liquidity::Taker => "Taker",
+                },
+            )?;
+            dict.set_item("timestamp", report.timestamp)?;
+        }
+    }
+    Ok(dict)
+}
+
+/// a logged in connection to the order entry gateway
+#[pyclass]
+struct OrderClient {
+    client: client::OrderClient,
+}
+
+#[pymethods]
+impl OrderClient {
+    /// connects to the gateway at `addr`, e.g. "127.0.0.1:8888", and logs in
+    #[new]
+    fn new(py: Python, addr: &str, username: &str, password: &str) -> PyResult<Self> {
+        let client = py.allow_threads(|| client::OrderClient::connect(addr, username, password)).map_err(to_py_err)?;
+        Ok(OrderClient { client })
+    }
+
+    /// id of the account the client logged in to
+    #[getter]
+    fn account_id(&self) -> u32 {
+        self.client.account_id()
+    }
+
+    /// sends a new order. `price` is the limit price of Limit and StopLimit orders,
+    /// `stop_price` the stop price of Stop and StopLimit orders. `client_order_id` is echoed in every status of the order,
+    /// and has to be unique among the account's orders
+    #[pyo3(signature = (symbol, side, quantity, order_type="Market", price=None, stop_price=None, time_in_force="GoodTillCancel", self_trade_prevention=None, client_order_id=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn place(
+        &mut self,
+        symbol: &str,
+        side: &str,
+        quantity: u64,
+        order_type: &str,
+        price: Option<u64>,
+        stop_price: Option<u64>,
+        time_in_force: &str,
+        self_trade_prevention: Option<&str>,
+        client_order_id: Option<u64>,
+    ) -> PyResult<()> {
+        let order_type = parse_order_type(order_type, price, stop_price)?;
+        let side = parse_side(side)?;
+        let time_in_force = parse_time_in_force(time_in_force)?;
+        let self_trade_prevention = parse_self_trade_prevention(self_trade_prevention)?;
+        if let Some(client_order_id) = client_order_id {
+            check_client_order_id(client_order_id).map_err(|reason| PyValueError::new_err(reason.message()))?;
+        }
+        self.client
+            .execute(symbol, order_type, side, time_in_force, self_trade_prevention, quantity, client_order_id)
+            .map_err(to_py_err)
+    }
+
+    /// changes the price and/or quantity of an open order, None leaves that field unchanged
+    #[pyo3(signature = (order_id, price=None, quantity=None))]
+    fn replace(&mut self, order_id: u32, price: Option<u64>, quantity: Option<u64>) -> PyResult<()> {
+        self.client.replace(order_id, price, quantity).map_err(to_py_err)
+    }
+
+    /// asks for the status of an order, named by either its order id or client order id.
+    /// the status arrives through `next_status`
+    #[pyo3(signature = (order_id=None, client_order_id=None))]
+    fn status(&mut self, order_id: Option<u32>, client_order_id: Option<u64>) -> PyResult<()> {
+        let order = parse_order_ref(order_id, client_order_id)?;
+        self.client.status(order).map_err(to_py_err)
+    }
+
+    /// cancels an open order, named by either its order id or client order id
+    #[pyo3(signature = (order_id=None, client_order_id=None))]
+    fn cancel(&mut self, order_id: Option<u32>, client_order_id: Option<u64>) -> PyResult<()> {
+        let order = parse_order_ref(order_id, client_order_id)?;
+        self.client.cancel(order).map_err(to_py_err)
+    }
+
+    /// waits for the next status from the exchange, returned as a dict. raises TimeoutError
+    /// if none arrives within `timeout` seconds, or waits forever without one
+    #[pyo3(signature = (timeout=None))]
+    fn next_status<'py>(&mut self, py: Python<'py>, timeout: Option<f64>) -> PyResult<Bound<'py, PyDict>> {
+        let timeout = match timeout {
+            Some(seconds) if seconds <= 0.0 => return Err(PyValueError::new_err("timeout must be positive")),
+            Some(seconds) => Some(Duration::from_secs_f64(seconds)),
+            None => None,
+        };
+        self.client.set_timeout(timeout).map_err(to_py_err)?;
+
+        let client = &mut self.client;
+        let status_report = py.allow_threads(|| client.recv_status()).map_err(|e| match e {
+            ClientError::Io(ref io_error) if io_error.kind() == io::ErrorKind::WouldBlock => {
+                PyTimeoutError::new_err("no status arrived in time")
+            }
+            e => to_py_err(e),
+        })?;
+        status_to_dict(py, status_report)
+    }
+}
+
+/// the top of the book of a symbol
+#[pyclass(get_all)]
+struct Quote {
+    symbol: String,
+    best_bid: u64,
+    bid_size: u64,
+    best_ask: u64,
+    ask_size: u64,
+}
+
+#[pymethods]
+impl Quote {
+    fn __repr__(&self) -> String {
+        format!(
+            "Quote(symbol={:?}, best_bid={}, bid_size={}, best_ask={}, ask_size={})",
+            self.symbol, self.best_bid, self.bid_size, self.best_ask, self.ask_size
+        )
+    }
+}
+
+/// the best price levels of both sides of the book of a symbol, as (price, quantity) pairs, best price first
+#[pyclass(get_all)]
+struct Depth {
+    symbol: String,
+    bids: Vec<(u64, u64)>,
+    asks: Vec<(u64, u64)>,
+}
+
+#[pymethods]
+impl Depth {
+    fn __repr__(&self) -> String {
+        format!("Depth(symbol={:?}, bids={:?}, asks={:?})", self.symbol, self.bids, self.asks)
+    }
+}
+
+/// a trade of a symbol, with the side of the order that took liquidity and its time in nanoseconds since the unix epoch
+#[pyclass(get_all)]
+struct Trade {
+    symbol: String,
+    trade_id: u64,
+    price: u64,
+    quantity: u64,
+    aggressor_side: &'static str,
+    timestamp: u64,
+}
+
+#[pymethods]
+impl Trade {
+    fn __repr__(&self) -> String {
+        format!(
+            "Trade(symbol={:?}, trade_id={}, price={}, quantity={}, aggressor_side={:?}, timestamp={})",
+            self.symbol, self.trade_id, self.price, self.quantity, self.aggressor_side, self.timestamp
+        )
+    }
+}
+
+/// a change to an order resting in the book of a symbol: "Added", "Executed", "Cancelled" or "Replaced".
+/// fields that don't belong to the event are None: `side` is only set on added orders, `trade_id` on executions
+/// and `new_order_ref` on replaces. `quantity` is the quantity traded or taken off, or the new quantity
+/// of added and replaced orders
+#[pyclass(get_all)]
+struct OrderEvent {
+    symbol: String,
+    event: &'static str,
+    order_ref: u64,
+    new_order_ref: Option<u64>,
+    side: Option<&'static str>,
+    price: Option<u64>,
+    quantity: u64,
+    trade_id: Option<u64>,
+}
+
+#[pymethods]
+impl OrderEvent {
+    fn __repr__(&self) -> String {
+        format!(
+            "OrderEvent(symbol={:?}, event={:?}, order_ref={}, new_order_ref={:?}, side={:?}, price={:?}, quantity={}, trade_id={:?})",
+            self.symbol, self.event, self.order_ref, self.new_order_ref, self.side, self.price, self.quantity, self.trade_id
+        )
+    }
+}
+
+/// every order resting in the book of a symbol as (order_ref, price, quantity), in priority order
+#[pyclass(get_all)]
+struct RestingOrders {
+    symbol: String,
+    bids: Vec<(u64, u64, u64)>,
+    asks: Vec<(u64, u64, u64)>,
+}
+
+#[pymethods]
+impl RestingOrders {
+    fn __repr__(&self) -> String {
+        format!("RestingOrders(symbol={:?}, bids={:?}, asks={:?})", self.symbol, self.bids, self.asks)
+    }
+}
+
+/// an update of any feed, handed to python as a `Quote`, `Depth`, `Trade`, `OrderEvent` or `RestingOrders`
+#[derive(IntoPyObject)]
+enum Update {
+    Quote(Quote),
+    Depth(Depth),
+    Trade(Trade),
+    OrderEvent(OrderEvent),
+    RestingOrders(RestingOrders),
+}
+
+fn parse_feed(name: &str) -> PyResult<Feed> {
+    match name {
+        "TopOfBook" => Ok(Feed::TopOfBook),
+        "Depth" => Ok(Feed::Depth),
+        "Trades" => Ok(Feed::Trades),
+        "Orders" => Ok(Feed::Orders),
+        _ => Err(PyValueError::new_err(format!("unknown feed {}", name))),
+    }
+}
+
+/// a subscription to a market data feed. iterating over it waits for each update in turn,
+/// a `Quote` for the top of book feed, a `Depth` for the depth of book feed, a `Trade` for the trade feed
+/// and an `OrderEvent` for the order by order feed, which starts each symbol with its `RestingOrders`
+#[pyclass(unsendable)]
+struct MarketDataClient {
+    client: client::MarketDataClient,
+    // updates received but not yet handed out
+    pending: VecDeque<MarketData>,
+}
+
+#[pymethods]
+impl MarketDataClient {
+    /// subscribes to `feed`, "TopOfBook", "Depth", "Trades" or "Orders", of the market data provider at `addr`, e.g. "127.0.0.1:4567",
+    /// for the tickers in `symbols`, or for every symbol if it's None
+    #[new]
+    #[pyo3(signature = (addr, feed="TopOfBook", symbols=None))]
+    fn new(addr: &str, feed: &str, symbols: Option<Vec<String>>) -> PyResult<Self> {
+        let tickers: Vec<&str> = symbols.iter().flatten().map(String::as_str).collect();
+        Ok(MarketDataClient {
+            client: client::MarketDataClient::connect_to(addr, parse_feed(feed)?, &tickers).map_err(to_py_err)?,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// adds the tickers in `symbols` to the subscription, each starting with a snapshot
+    fn subscribe(&mut self, symbols: Vec<String>) {
+        let tickers: Vec<&str> = symbols.iter().map(String::as_str).collect();
+        self.client.subscribe(&tickers);
+    }
+
+    /// takes the tickers in `symbols` off the subscription
+    fn unsubscribe(&mut self, symbols: Vec<String>) {
+        let tickers: Vec<&str> = symbols.iter().map(String::as_str).collect();
+        self.client.unsubscribe(&tickers);
+    }
+
+    /// returns every update received since the last call, without waiting for more
+    fn poll(&mut self) -> PyResult<Vec<Update>> {
+        self.fill()?;
+        Ok(self.pending.drain(..).map(update).collect())
+    }
+
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Update> {
+        loop {
+            if let Some(market_data) = self.pending.pop_front() {
+                return Ok(update(market_data));
+            }
+            // lets ctrl-c stop a loop over the feed
+            py.check_signals()?;
+            self.fill()?;
+            if self.pending.is_empty() {
+                py.allow_threads(|| thread::sleep(time::Duration::from_millis(1)));
+            }
+        }
+    }
+}
+
+impl MarketDataClient {
+    fn fill(&mut self) -> PyResult<()> {
+        let updates = self.client.poll().map_err(to_py_err)?;
+        self.pending.extend(updates);
+        Ok(())
+    }
+}
+
+fn levels(levels: &[PriceLevel]) -> Vec<(u64, u64)> {
+    levels.iter().map(|level| (level.price, level.quantity)).collect()
+}
+
+fn orders<'a, I: Iterator<Item = (&'a u64, &'a VecDeque<RestingOrder>)>>(levels: I) -> Vec<(u64, u64, u64)> {
+    levels
+        .flat_map(|(price, order_lst)| order_lst.iter().map(move |order| (order.order_ref, *price, order.quantity)))
+        .collect()
+}
+
+fn side_name(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "Buy",
+        OrderSide::Sell => "Sell",
+    }
+}
+
+fn update(market_data: MarketData) -> Update {
+    match market_data {
+        MarketData::TopOfBook(price_info) => Update::Quote(Quote {
+            symbol: price_info.get_symbol().ticker().to_string(),
+            best_bid: price_info.best_bid,
+            bid_size: price_info.bid_size,
+            best_ask: price_info.best_ask,
+            ask_size: price_info.ask_size,
+        }),
+        MarketData::Depth(depth_info) => Update::Depth(Depth {
+            symbol: depth_info.get_symbol().ticker().to_string(),
+            bids: levels(&depth_info.bids),
+            asks: levels(&depth_info.asks),
+        }),
+        MarketData::Trade(trade) => Update::Trade(Trade {
+            symbol: trade.get_symbol().ticker().to_string(),
+            trade_id: trade.trade_id,
+            price: trade.price,
+            quantity: trade.quantity,
+            aggressor_side: side_name(trade.aggressor_side),
+            timestamp: trade.timestamp,
+        }),
+        MarketData::Order(order_event_info) => {
+            let (event, order_ref, new_order_ref, side, price, quantity, trade_id) = match order_event_info.event {
+                exchange::types::OrderEvent::Added(order_ref, side, price, quantity) => ("Added", order_ref, None, Some(side_name(side)), Some(price), quantity, None),
+                exchange::types::OrderEvent::Executed(order_ref, quantity, trade_id) => ("Executed", order_ref, None, None, None, quantity, Some(trade_id)),
+                exchange::types::OrderEvent::Cancelled(order_ref, quantity) => ("Cancelled", order_ref, None, None, None, quantity, None),
+                exchange::types::OrderEvent::Replaced(order_ref, new_order_ref, price, quantity) => {
+                    ("Replaced", order_ref, Some(new_order_ref), None, Some(price), quantity, None)
+                }
+            };
+            Update::OrderEvent(OrderEvent {
+                symbol: order_event_info.get_symbol().ticker().to_string(),
+                event,
+                order_ref,
+                new_order_ref,
+                side,
+                price,
+                quantity,
+                trade_id,
+            })
+        }
+        MarketData::Orders(resting_orders) => Update::RestingOrders(RestingOrders {
+            symbol: resting_orders.get_symbol().ticker().to_string(),
+            bids: orders(resting_orders.bids.iter().rev()),
+            asks: orders(resting_orders.asks.iter()),
+        }),
+    }
+}
+
+/// order entry and market data clients for the exchange
+#[pymodule]
+fn rustyxchange(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<OrderClient>()?;
+    m.add_class::<MarketDataClient>()?;
+    m.add_class::<Quote>()?;
+    m.add_class::<Depth>()?;
+    m.add_class::<Trade>()?;
+    m.add_class::<OrderEvent>()?;
+    m.add_class::<RestingOrders>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orders_use_json_names() {
+        assert_eq!(parse_order_type("StopLimit", Some(94000), Some(95000)).unwrap(), OrderType::StopLimit(95000, 94000));
+        assert_eq!(parse_order_type("Market", Some(94000), None).unwrap(), OrderType::Market);
+        assert!(parse_order_type("Limit", None, Some(95000)).is_err());
+        assert!(parse_order_type("Iceberg", None, None).is_err());
+
+        assert_eq!(parse_side("Sell").unwrap(), OrderSide::Sell);
+        assert!(parse_side("sell").is_err());
+        assert_eq!(parse_time_in_force("FillOrKill").unwrap(), TimeInForce::FillOrKill);
+        assert_eq!(parse_self_trade_prevention(None).unwrap(), None);
+        assert_eq!(parse_self_trade_prevention(Some("CancelBoth")).unwrap(), Some(SelfTradePrevention::CancelBoth));
+        assert!(parse_self_trade_prevention(Some("CancelAll")).is_err());
+
+        assert_eq!(parse_order_ref(Some(3), None).unwrap(), OrderRef::Exchange(3));
+        assert_eq!(parse_order_ref(None, Some(11)).unwrap(), OrderRef::Client(11));
+        assert!(parse_order_ref(None, Some(0)).is_err());
+        assert!(parse_order_ref(Some(3), Some(11)).is_err());
+        assert!(parse_order_ref(None, None).is_err());
+
+        assert_eq!(parse_feed("Depth").unwrap(), Feed::Depth);
+        assert_eq!(parse_feed("Trades").unwrap(), Feed::Trades);
+        assert_eq!(parse_feed("Orders").unwrap(), Feed::Orders);
+        assert!(parse_feed("L3").is_err());
+    }
+}
@@ -1,25 +1,105 @@
 use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
 
-/// a struct tracking all currently active accounts
-struct Accountant {
-    accounts: HashMap<u32, Account>
+use super::ACCOUNTS;
+use types::*;
+
+/// a struct tracking all currently active accounts, which owns their balances and positions
+/// and keeps them up to date with every fill coming out of the matching engine
+pub struct Accountant {
+    accounts: HashMap<u32, Account>,
+    receiver: Receiver<Fill>,
 }
 
 impl Accountant {
-    /// creates a new Accountant and initializes the account map
-    pub fn new() -> Accountant {
-        Accountant {
-            accounts: HashMap<u32, Account>::new()
+    /// creates a new Accountant with every account loaded at startup registered
+    ///
+    /// # Parameters
+    ///
+    /// * `receiver` - the channel fills from the matching engine arrive on
+    pub fn new(receiver: Receiver<Fill>) -> Accountant {
+        let mut accountant = Accountant {
+            accounts: HashMap::new(),
+            receiver: receiver,
+        };
+        for account in ACCOUNTS.values() {
+            accountant.register(account.clone());
         }
+        accountant
     }
 
     /// registers an account with the Accountant
-    /// 
+    ///
     /// # Parameters
-    /// 
+    ///
     /// * `account` - the account to register
     pub fn register(&mut self, account: Account) {
-        // FIXME: does self need to be mutable?
-        self.accounts.insert(account.id, account);
+        self.accounts.insert(account.id(), account);
+    }
+
+    /// updates the balance and position of the account on one side of a trade
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        match self.accounts.get_mut(&fill.account_id) {
+            Some(account) => {
+                account.apply_fill(fill);
+                println!(
+                    "[INFO] account {} has a balance of {} and {} shares of {}",
+                    account.username(),
+                    account.balance(),
+                    account.position(fill.symbol.ticker()),
+                    fill.symbol.ticker()
+                );
+            }
+            None => println!("[ERROR] fill for unknown account id = {}", fill.account_id),
+        }
     }
-}
\ No newline at end of file
+
+    /// applies fills as they come in, until the matching engine goes away
+    pub fn run(&mut self) {
+        while let Ok(fill) = self.receiver.recv() {
+            self.apply_fill(&fill);
+        }
+        println!("[INFO] accountant stopped, fill channel closed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::SYMBOLS;
+    use std::sync::mpsc::channel;
+
+    fn fill(account_id: u32, side: OrderSide, price: u64, quantity: u64) -> Fill {
+        Fill {
+            account_id: account_id,
+            symbol: SYMBOLS.get("GOOG").unwrap(),
+            side: side,
+            price: price,
+            quantity: quantity,
+        }
+    }
+
+    #[test]
+    fn test_accounts_are_loaded() {
+        let accountant = Accountant::new(channel().1);
+        let alice = &accountant.accounts[&1];
+        assert_eq!(alice.username(), "alice");
+        assert_eq!(alice.balance(), 100000000);
+        assert_eq!(alice.position("GOOG"), 100);
+        assert_eq!(alice.position("AAPL"), 0);
+        assert_eq!(accountant.accounts[&3].self_trade_prevention(), Some(SelfTradePrevention::CancelOldest));
+    }
+
+    #[test]
+    fn test_fills_move_cash_and_shares() {
+        let mut accountant = Accountant::new(channel().1);
+        accountant.apply_fill(&fill(1, OrderSide::Sell, 100, 150));
+        accountant.apply_fill(&fill(2, OrderSide::Buy, 100, 150));
+        // unknown accounts are ignored
+        accountant.apply_fill(&fill(42, OrderSide::Buy, 100, 150));
+
+        let (alice, bob) = (&accountant.accounts[&1], &accountant.accounts[&2]);
+        assert_eq!((alice.balance(), alice.position("GOOG")), (100000000 + 150 * 100, -50));
+        assert_eq!((bob.balance(), bob.position("GOOG")), (50000000 - 150 * 100, 150));
+    }
+}
@@ -0,0 +1,679 @@
+use reliudp::{MessageType, RUdpSocket, SocketEvent};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use std::fmt;
+
+use codec::market_data::{decode_packet, encode_feed_request, Delta, FeedRequest, OrderUpdate, Packet};
+use codec::request::{encode_auth, encode_request, Auth, Request};
+use codec::response::{decode_response, Response};
+use codec::{read_message, DecodeError};
+use types::*;
+
+// largest response the exchange sends, anything longer means the stream is out of step
+const MAX_RESPONSE_SIZE: usize = 1024;
+
+/// the ways talking to the exchange can fail
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Decode(DecodeError),
+    // the exchange refused the username and password, with its reason
+    AuthRejected(String),
+    // the exchange sent a response that doesn't belong at this point of the session
+    UnexpectedResponse,
+    // the market data feed ended, was aborted or timed out
+    Disconnected,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref e) => write!(f, "connection failed: {}", e),
+            ClientError::Decode(ref e) => write!(f, "malformed message from the exchange: {}", e),
+            ClientError::AuthRejected(ref reason) => write!(f, "login rejected: {}", reason),
+            ClientError::UnexpectedResponse => write!(f, "unexpected response from the exchange"),
+            ClientError::Disconnected => write!(f, "market data feed disconnected"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ClientError::Io(ref e) => Some(e),
+            ClientError::Decode(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(e: DecodeError) -> Self {
+        ClientError::Decode(e)
+    }
+}
+
+/// a logged in binary connection to the order entry gateway.
+/// commands are sent right away, and the statuses they lead to are read with `recv_status`
+pub struct OrderClient {
+    account_id: u32,
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl OrderClient {
+    /// connects to the gateway and logs in
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - address of the order entry gateway
+    /// * `username` - the account to log in to
+    /// * `password` - the account's password
+    pub fn connect<A: ToSocketAddrs>(addr: A, username: &str, password: &str) -> Result<OrderClient, ClientError> {
+        let stream = TcpStream::connect(addr)?;
+        let mut client = OrderClient {
+            account_id: 0,
+            reader: BufReader::new(stream.try_clone()?),
+            stream: stream,
+        };
+
+        client.send(&encode_auth(&Auth {
+            username: username,
+            password: password,
+        }))?;
+        let data = read_message(&mut client.reader, MAX_RESPONSE_SIZE)?;
+        match decode_response(&data)? {
+            Response::Authenticated(account_id) => client.account_id = account_id,
+            Response::AuthRejected(reason) => return Err(ClientError::AuthRejected(reason.to_string())),
+            Response::Status(_) => return Err(ClientError::UnexpectedResponse),
+        }
+        Ok(client)
+    }
+
+    /// returns the id of the account the client logged in to
+    pub fn account_id(&self) -> u32 {
+        self.account_id
+    }
+
+    /// sends a new order
+    ///
+    /// # Parameters
+    ///
+    /// * `ticker` - symbol to trade
+    /// * `order_type` - the kind of order, with its prices
+    /// * `side` - whether to buy or sell
+    /// * `time_in_force` - how long the order stays active
+    /// * `self_trade_prevention` - what to do if the order would trade with the same account, None uses the account's mode
+    /// * `quantity` - number of shares
+    /// * `client_order_id` - id echoed in every status of the order, which has to be unique among the account's orders and not 0
+    #[allow(clippy::too_many_arguments)]
+    pub fn execute(
+        &mut self,
+        ticker: &str,
+        order_type: OrderType,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        self_trade_prevention: Option<SelfTradePrevention>,
+        quantity: u64,
+        client_order_id: Option<u64>,
+    ) -> Result<(), ClientError> {
+        let request = Request::Execute {
+            account_id: self.account_id,
+            ticker: ticker,
+            order_type: order_type,
+            side: side,
+            time_in_force: time_in_force,
+            self_trade_prevention: self_trade_prevention,
+            quantity: quantity,
+            client_order_id: client_order_id,
+        };
+        self.send(&encode_request(&request))
+    }
+
+    /// changes the price and/or quantity of an open order, None leaves that field unchanged
+    pub fn replace(&mut self, order_id: u32, price: Option<u64>, quantity: Option<u64>) -> Result<(), ClientError> {
+        let request = Request::Replace {
+            account_id: self.account_id,
+            order_id: order_id,
+            price: price,
+            quantity: quantity,
+        };
+        self.send(&encode_request(&request))
+    }
+
+    /// asks for the status of an order, named by its order id or client order id
+    pub fn status(&mut self, order: OrderRef) -> Result<(), ClientError> {
+        let request = Request::Status {
+            account_id: self.account_id,
+            order: order,
+        };
+        self.send(&encode_request(&request))
+    }
+
+    /// cancels an open order, named by its order id or client order id
+    pub fn cancel(&mut self, order: OrderRef) -> Result<(), ClientError> {
+        let request = Request::Cancel {
+            account_id: self.account_id,
+            order: order,
+        };
+        self.send(&encode_request(&request))
+    }
+
+    /// waits for the next status from the exchange, for as long as the timeout allows
+    pub fn recv_status(&mut self) -> Result<StatusReport, ClientError> {
+        let data = read_message(&mut self.reader, MAX_RESPONSE_SIZE)?;
+        match decode_response(&data)? {
+            Response::Status(report) => Ok(report),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// sets how long `recv_status` waits before failing with a `WouldBlock` or `TimedOut` error, None waits forever
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        self.stream.set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), ClientError> {
+        self.stream.write_all(frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+// the feeds a `MarketDataClient` can listen to
+pub use types::Feed;
+
+/// the depth of a symbol, rebuilt from a snapshot and the deltas after it
+struct Book {
+    seq: u64,
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+    // set once a delta is missed, until a newer snapshot arrives, so a snapshot is only asked for once
+    is_recovering: bool,
+}
+
+impl Book {
+    fn new(seq: u64, bids: &[PriceLevel], asks: &[PriceLevel]) -> Book {
+        Book {
+            seq: seq,
+            bids: bids.iter().map(|level| (level.price, level.quantity)).collect(),
+            asks: asks.iter().map(|level| (level.price, level.quantity)).collect(),
+            is_recovering: false,
+        }
+    }
+
+    fn apply(&mut self, delta: &Delta) {
+        for update in &delta.updates {
+            let levels = match update.side {
+                OrderSide::Buy => &mut self.bids,
+                OrderSide::Sell => &mut self.asks,
+            };
+            if update.quantity == 0 {
+                levels.remove(&update.price);
+            } else {
+                levels.insert(update.price, update.quantity);
+            }
+        }
+        self.seq = delta.seq;
+    }
+
+    fn depth_info(&self, symbol: &'static Symbol) -> DepthInfo {
+        let level = |(price, quantity): (&u64, &u64)| PriceLevel::new(*price, *quantity);
+        DepthInfo::new(symbol, self.bids.iter().rev().map(level).collect(), self.asks.iter().map(level).collect())
+    }
+}
+
+/// the resting orders of a symbol, rebuilt from a snapshot and the order events after it
+struct Orders {
+    seq: u64,
+    resting_orders: RestingOrders,
+    // set once an event is missed, until a newer snapshot arrives, so a snapshot is only asked for once
+    is_recovering: bool,
+}
+
+/// a subscription to a feed of the market data provider, for every symbol or only some.
+/// updates are sequenced per symbol: stale ones are dropped, and a missed depth delta or order event makes the client
+/// ask for a snapshot of the symbol, staying quiet about it until the snapshot arrives
+pub struct MarketDataClient {
+    socket: RUdpSocket,
+    feed: Feed,
+    // PriceInfo holds a symbol that lives forever, so every ticker seen gets one, kept here
+    symbols: HashMap<String, &'static Symbol>,
+    // sequence number of the latest quote or trade of every symbol, whichever the feed sends
+    seqs: HashMap<String, u64>,
+    // depth of every symbol a snapshot has arrived for
+    books: HashMap<String, Book>,
+    // resting orders of every symbol a snapshot has arrived for
+    orders: HashMap<String, Orders>,
+    // requests made before the connection was, sent once it is
+    pending_requests: Vec<Arc<[u8]>>,
+}
+
+impl MarketDataClient {
+    /// subscribes to `feed` of every symbol of the market data provider at `addr`.
+    /// the connection is made on the first calls to `poll`
+    pub fn connect<A: ToSocketAddrs>(addr: A, feed: Feed) -> Result<MarketDataClient, ClientError> {
+        MarketDataClient::connect_to(addr, feed, &[])
+    }
+
+    /// subscribes to `feed` of `tickers`, or of every symbol if it's empty, of the market data provider at `addr`.
+    /// the connection is made on the first calls to `poll`
+    pub fn connect_to<A: ToSocketAddrs>(addr: A, feed: Feed, tickers: &[&str]) -> Result<MarketDataClient, ClientError> {
+        let mut client = MarketDataClient {
+            socket: RUdpSocket::connect(addr)?,
+            feed: feed,
+            symbols: HashMap::new(),
+            seqs: HashMap::new(),
+            books: HashMap::new(),
+            orders: HashMap::new(),
+            pending_requests: Vec::new(),
+        };
+        client.subscribe(tickers);
+        Ok(client)
+    }
+
+    /// adds `tickers`, or every symbol if it's empty, to the subscription. each starts with a snapshot
+    pub fn subscribe(&mut self, tickers: &[&str]) {
+        self.request(&FeedRequest::Subscribe(self.feed, tickers.to_vec()));
+    }
+
+    /// takes `tickers`, or every symbol if it's empty, off the subscription, forgetting what was known about them
+    pub fn unsubscribe(&mut self, tickers: &[&str]) {
+        self.request(&FeedRequest::Unsubscribe(self.feed, tickers.to_vec()));
+        if tickers.is_empty() {
+            self.seqs.clear();
+            self.books.clear();
+            self.orders.clear();
+        }
+        for ticker in tickers {
+            self.seqs.remove(*ticker);
+            self.books.remove(*ticker);
+            self.orders.remove(*ticker);
+        }
+    }
+
+    /// returns every update of the feed received since the last call, without waiting for more.
+    /// fails once the feed has ended, been aborted or timed out
+    pub fn poll(&mut self) -> Result<Vec<MarketData>, ClientError> {
+        self.socket.next_tick()?;
+        let events: Vec<SocketEvent> = self.socket.drain_events().collect();
+
+        let mut updates: Vec<MarketData> = Vec::new();
+        for event in events {
+            match event {
+                SocketEvent::Data(data) => match (decode_packet(&data)?, self.feed) {
+                    (Packet::Quotes(quotes), Feed::TopOfBook) => {
+                        for quote in quotes {
+                            if !self.is_new(quote.ticker, quote.seq) {
+                                continue;
+                            }
+                            let symbol = self.symbol(quote.ticker);
+                            updates.push(MarketData::TopOfBook(PriceInfo::new(symbol, quote.best_bid, quote.bid_size, quote.best_ask, quote.ask_size)));
+                        }
+                    }
+                    (Packet::Depth(books), Feed::Depth) => {
+                        for book in books {
+                            if self.books.get(book.ticker).is_some_and(|known| book.seq <= known.seq) {
+                                continue;
+                            }
+                            let new_book = Book::new(book.seq, &book.bids, &book.asks);
+                            let symbol = self.symbol(book.ticker);
+                            updates.push(MarketData::Depth(new_book.depth_info(symbol)));
+                            self.books.insert(book.ticker.to_string(), new_book);
+                        }
+                    }
+                    (Packet::Delta(deltas), Feed::Depth) => {
+                        for delta in deltas {
+                            if let Some(update) = self.apply(&delta) {
+                                updates.push(update);
+                            }
+                        }
+                    }
+                    (Packet::Trades(prints), Feed::Trades) => {
+                        for print in prints {
+                            if !self.is_new(print.ticker, print.seq) {
+                                continue;
+                            }
+                            let symbol = self.symbol(print.ticker);
+                            updates.push(MarketData::Trade(Trade::new(symbol, print.trade_id, print.price, print.quantity, print.aggressor_side, print.timestamp)));
+                        }
+                    }
+                    (Packet::OrderBook(books), Feed::Orders) => {
+                        for book in books {
+                            if self.orders.get(book.ticker).is_some_and(|known| book.seq <= known.seq) {
+                                continue;
+                            }
+                            let mut resting_orders = RestingOrders::new(self.symbol(book.ticker));
+                            for event in &book.orders {
+                                resting_orders.apply(event);
+                            }
+                            updates.push(MarketData::Orders(resting_orders.clone()));
+                            self.orders.insert(book.ticker.to_string(), Orders {
+                                seq: book.seq,
+                                resting_orders: resting_orders,
+                                is_recovering: false,
+                            });
+                        }
+                    }
+                    (Packet::Orders(order_updates), Feed::Orders) => {
+                        for order_update in order_updates {
+                            if let Some(update) = self.apply_order_update(&order_update) {
+                                updates.push(update);
+                            }
+                        }
+                    }
+                    // packets of the other feeds
+                    _ => {}
+                },
+                SocketEvent::Connected => {
+                    for data in self.pending_requests.drain(..) {
+                        self.socket.send_data(data, MessageType::KeyMessage);
+                    }
+                }
+                SocketEvent::Aborted | SocketEvent::Ended | SocketEvent::Timeout => return Err(ClientError::Disconnected),
+            }
+        }
+        Ok(updates)
+    }
+
+    /// returns true if `seq` is past the last sequence number seen for `ticker`, which it then becomes
+    fn is_new(&mut self, ticker: &str, seq: u64) -> bool {
+        if self.seqs.get(ticker).is_some_and(|last_seq| seq <= *last_seq) {
+            return false;
+        }
+        self.seqs.insert(ticker.to_string(), seq);
+        true
+    }
+
+    /// applies a delta to the book of its symbol, returning the new depth.
+    /// deltas without a book to apply to are dropped, and deltas after a gap ask for a snapshot
+    fn apply(&mut self, delta: &Delta) -> Option<MarketData> {
+        let is_gap = {
+            let book = self.books.get_mut(delta.ticker)?;
+            if delta.seq <= book.seq {
+                return None;
+            }
+            if delta.seq == book.seq + 1 {
+                book.apply(delta);
+                false
+            } else if !book.is_recovering {
+                book.is_recovering = true;
+                true
+            } else {
+                return None;
+            }
+        };
+
+        if is_gap {
+            println!("[INFO] missed depth of {}, asking for a snapshot", delta.ticker);
+            self.request(&FeedRequest::Snapshot(vec![delta.ticker]));
+            return None;
+        }
+        let symbol = self.symbol(delta.ticker);
+        Some(MarketData::Depth(self.books[delta.ticker].depth_info(symbol)))
+    }
+
+    /// applies an order event to the resting orders of its symbol, returning the event.
+    /// events without resting orders to apply to are dropped, and events after a gap ask for a snapshot
+    fn apply_order_update(&mut self, order_update: &OrderUpdate) -> Option<MarketData> {
+        let is_gap = {
+            let orders = self.orders.get_mut(order_update.ticker)?;
+            if order_update.seq <= orders.seq {
+                return None;
+            }
+            if order_update.seq == orders.seq + 1 {
+                orders.resting_orders.apply(&order_update.event);
+                orders.seq = order_update.seq;
+                false
+            } else if !orders.is_recovering {
+                orders.is_recovering = true;
+                true
+            } else {
+                return None;
+            }
+        };
+
+        if is_gap {
+            println!("[INFO] missed order events of {}, asking for a snapshot", order_update.ticker);
+            self.request(&FeedRequest::Snapshot(vec![order_update.ticker]));
+            return None;
+        }
+        let symbol = self.symbol(order_update.ticker);
+        Some(MarketData::Order(OrderEventInfo::new(symbol, order_update.event)))
+    }
+
+    fn request(&mut self, request: &FeedRequest) {
+        let data: Arc<[u8]> = Arc::from(encode_feed_request(request).into_boxed_slice());
+        if self.socket.status().is_connected() {
+            self.socket.send_data(data, MessageType::KeyMessage);
+        } else {
+            self.pending_requests.push(data);
+        }
+    }
+
+    fn symbol(&mut self, ticker: &str) -> &'static Symbol {
+        if let Some(symbol) = self.symbols.get(ticker) {
+            return symbol;
+        }
+        // the exchange only has a handful of symbols, so leaking one per ticker is fine
+        let symbol: &'static Symbol = Box::leak(Box::new(Symbol::new(ticker.to_string())));
+        self.symbols.insert(ticker.to_string(), symbol);
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::market_data::{
+        decode_feed_request, encode_deltas, encode_depth, encode_order_books, encode_order_updates, encode_prints, encode_quotes, Depth, OrderBook,
+        Print, Quote,
+    };
+    use codec::request::{decode_auth, decode_request};
+    use codec::response::encode_response;
+    use reliudp::RUdpServer;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    /// reads the auth request a connection starts with
+    fn read_auth(stream: &mut TcpStream) -> (String, String) {
+        let data = read_message(stream, MAX_RESPONSE_SIZE).unwrap();
+        let auth = decode_auth(&data).unwrap();
+        (auth.username.to_string(), auth.password.to_string())
+    }
+
+    /// runs a stand in for the exchange on its own thread, which answers logins with `auth_response`
+    /// and then hands the connection to `session`
+    fn fake_exchange<F>(auth_response: Response<'static>, session: F) -> (String, thread::JoinHandle<(String, String)>)
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let credentials = read_auth(&mut stream);
+            stream.write_all(&encode_response(&auth_response)).unwrap();
+            session(stream);
+            credentials
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_order_client() {
+        let (addr, exchange) = fake_exchange(Response::Authenticated(7), |mut stream| {
+            let data = read_message(&mut stream, MAX_RESPONSE_SIZE).unwrap();
+            assert_eq!(
+                decode_request(&data),
+                Ok(Request::Execute {
+                    account_id: 7,
+                    ticker: "GOOG",
+                    order_type: OrderType::Limit(100500),
+                    side: OrderSide::Buy,
+                    time_in_force: TimeInForce::Day,
+                    self_trade_prevention: None,
+                    quantity: 10,
+                    client_order_id: Some(11),
+                })
+            );
+            let waiting = StatusReport::new(OrderStatus::Waiting(3), Some(11));
+            stream.write_all(&encode_response(&Response::Status(waiting))).unwrap();
+
+            let data = read_message(&mut stream, MAX_RESPONSE_SIZE).unwrap();
+            assert_eq!(decode_request(&data), Ok(Request::Cancel { account_id: 7, order: OrderRef::Client(11) }));
+            let canceled = StatusReport::new(OrderStatus::Canceled(3), Some(11));
+            stream.write_all(&encode_response(&Response::Status(canceled))).unwrap();
+        });
+
+        let mut client = OrderClient::connect(addr, "alice", "alice-password").unwrap();
+        assert_eq!(client.account_id(), 7);
+        client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+            .execute("GOOG", OrderType::Limit(100500), OrderSide::Buy, TimeInForce::Day, None, 10, Some(11))
+            .unwrap();
+        assert_eq!(client.recv_status().unwrap(), StatusReport::new(OrderStatus::Waiting(3), Some(11)));
+        client.cancel(OrderRef::Client(11)).unwrap();
+        assert_eq!(client.recv_status().unwrap(), StatusReport::new(OrderStatus::Canceled(3), Some(11)));
+
+        let credentials = exchange.join().unwrap();
+        assert_eq!(credentials, ("alice".to_string(), "alice-password".to_string()));
+    }
+
+    #[test]
+    fn test_rejected_login() {
+        let (addr, exchange) = fake_exchange(Response::AuthRejected("invalid username or password"), |_| {});
+        match OrderClient::connect(addr, "alice", "bob-password") {
+            Err(ClientError::AuthRejected(reason)) => assert_eq!(reason, "invalid username or password"),
+            _ => panic!("expected the login to be rejected"),
+        }
+        exchange.join().unwrap();
+    }
+
+    #[test]
+    fn test_market_data_client() {
+        let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+        let addr = server.udp_socket().local_addr().unwrap();
+        let mut top_of_book_client = MarketDataClient::connect(addr, Feed::TopOfBook).unwrap();
+        let mut depth_client = MarketDataClient::connect(addr, Feed::Depth).unwrap();
+        let mut trades_client = MarketDataClient::connect_to(addr, Feed::Trades, &["GOOG"]).unwrap();
+        let mut orders_client = MarketDataClient::connect(addr, Feed::Orders).unwrap();
+
+        let goog: &'static Symbol = Box::leak(Box::new(Symbol::new("GOOG".to_string())));
+        let buy = |price, quantity| LevelUpdate::new(OrderSide::Buy, price, quantity);
+        let quotes: Arc<[u8]> = Arc::from(encode_quotes(vec![Quote {
+            ticker: goog.ticker(),
+            seq: 1,
+            best_bid: 100500,
+            bid_size: 10,
+            best_ask: 101000,
+            ask_size: 20,
+        }]));
+        let depth: Arc<[u8]> = Arc::from(encode_depth(vec![Depth {
+            ticker: goog.ticker(),
+            seq: 1,
+            bids: vec![PriceLevel::new(100500, 10)],
+            asks: vec![],
+        }]));
+        // the delta with sequence number 3 is never sent
+        let deltas: Arc<[u8]> = Arc::from(encode_deltas(vec![
+            Delta { ticker: goog.ticker(), seq: 2, updates: vec![buy(100000, 5)] },
+            Delta { ticker: goog.ticker(), seq: 4, updates: vec![buy(100500, 0)] },
+        ]));
+
+        let trade = Trade::new(goog, 9, 100500, 3, OrderSide::Sell, 1);
+        let prints: Arc<[u8]> = Arc::from(encode_prints(vec![Print::new(&trade, 1)]));
+        let order_book: Arc<[u8]> = Arc::from(encode_order_books(vec![OrderBook {
+            ticker: goog.ticker(),
+            seq: 1,
+            orders: vec![OrderEvent::Added(5, OrderSide::Buy, 100500, 10)],
+        }]));
+        // the order event with sequence number 3 is never sent
+        let order_updates: Arc<[u8]> = Arc::from(encode_order_updates(vec![
+            OrderUpdate { ticker: goog.ticker(), seq: 2, event: OrderEvent::Executed(5, 3, 9) },
+            OrderUpdate { ticker: goog.ticker(), seq: 4, event: OrderEvent::Cancelled(5, 7) },
+        ]));
+
+        let start = Instant::now();
+        let mut prices: Vec<MarketData> = Vec::new();
+        let mut books: Vec<MarketData> = Vec::new();
+        let mut trades: Vec<MarketData> = Vec::new();
+        let mut order_events: Vec<MarketData> = Vec::new();
+        let mut requests: Vec<String> = Vec::new();
+        while (books.len() < 2 || trades.is_empty() || order_events.len() < 2 || requests.len() < 6) && start.elapsed() < Duration::from_secs(5) {
+            server.next_tick().unwrap();
+            for (_, event) in server.drain_events() {
+                if let SocketEvent::Data(data) = event {
+                    requests.push(format!("{:?}", decode_feed_request(&data).unwrap()));
+                }
+            }
+            // packets that were already seen are dropped by their sequence numbers
+            for packet in &[&quotes, &depth, &deltas, &prints, &order_book, &order_updates] {
+                server.send_data(packet, MessageType::KeyMessage);
+            }
+            prices.extend(top_of_book_client.poll().unwrap());
+            books.extend(depth_client.poll().unwrap());
+            trades.extend(trades_client.poll().unwrap());
+            order_events.extend(orders_client.poll().unwrap());
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // each client only gets the feed it asked for
+        assert_eq!(prices.len(), 1);
+        match &prices[0] {
+            MarketData::TopOfBook(price_info) => {
+                assert_eq!(price_info.get_symbol(), goog);
+                assert_eq!((price_info.best_bid, price_info.bid_size, price_info.best_ask, price_info.ask_size), (100500, 10, 101000, 20));
+            }
+            update => panic!("expected a top of book update, got {:?}", update),
+        }
+        assert_eq!(books.len(), 2);
+        match &books[1] {
+            MarketData::Depth(depth_info) => {
+                assert_eq!(depth_info.get_symbol(), goog);
+                assert_eq!(depth_info.bids, vec![PriceLevel::new(100500, 10), PriceLevel::new(100000, 5)]);
+            }
+            update => panic!("expected a depth update, got {:?}", update),
+        }
+        assert_eq!(trades.len(), 1);
+        match &trades[0] {
+            MarketData::Trade(received) => assert_eq!(received, &trade),
+            update => panic!("expected a trade, got {:?}", update),
+        }
+
+        assert_eq!(order_events.len(), 2);
+        match (&order_events[0], &order_events[1]) {
+            (MarketData::Orders(resting_orders), MarketData::Order(order_event_info)) => {
+                assert_eq!(resting_orders.events(), vec![OrderEvent::Added(5, OrderSide::Buy, 100500, 10)]);
+                assert_eq!(order_event_info.event, OrderEvent::Executed(5, 3, 9));
+            }
+            updates => panic!("expected resting orders and an order event, got {:?}", updates),
+        }
+
+        // every client subscribes to its feed when it connects, and each gap asks for a snapshot of GOOG, once
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec![
+                r#"Snapshot(["GOOG"])"#,
+                r#"Snapshot(["GOOG"])"#,
+                "Subscribe(Depth, [])",
+                "Subscribe(Orders, [])",
+                "Subscribe(TopOfBook, [])",
+                r#"Subscribe(Trades, ["GOOG"])"#,
+            ]
+        );
+    }
+}
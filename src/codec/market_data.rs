@@ -0,0 +1,739 @@
+use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
+use std::str;
+
+use super::{write_ticker, DecodeError};
+use types::*;
+
+// packets sent by the market data provider. a packet is a whole udp message made of its type (1 byte)
+// followed by a body with one entry per symbol, each starting with the ticker (4 bytes) and the sequence number
+// (u64) of the symbol's feed at the time of the entry:
+//   quotes: ticker, sequence number, best bid u64, bid size u64, best ask u64, ask size u64
+//   depth: ticker, sequence number, bid level count u16, ask level count u16, then every bid level followed by
+//          every ask level, each a price u64 and quantity u64, best price first
+//   delta: ticker, sequence number, update count u16, then every update, each a side u8 (Buy(0), Sell(1)),
+//          price u64 and the new quantity u64 at the price, 0 when the level is gone
+//   trades: ticker, sequence number, trade id u64, price u64, quantity u64, aggressor side u8, timestamp u64
+//   orders: ticker, sequence number, event type u8, then the fields of the event:
+//           added (1): order reference u64, side u8, price u64, quantity u64
+//           executed (2): order reference u64, quantity u64, trade id u64
+//           cancelled (3): order reference u64, quantity u64
+//           replaced (4): order reference u64, new order reference u64, price u64, quantity u64
+//   order book: ticker, sequence number, order count u32, then every resting order, each an order reference u64,
+//               side u8, price u64 and quantity u64, bids then asks, best price first and oldest first within a price
+//
+// peers send requests to the provider, laid out the same way:
+//   snapshot request: tickers (4 bytes each) the peer wants a snapshot of, none for every symbol
+//   subscribe: feed u8 (TopOfBook(0), Depth(1), Trades(2), Orders(3)), then the tickers (4 bytes each) the peer wants
+//              that feed of, none for every symbol
+//   unsubscribe: feed u8, then the tickers (4 bytes each) the peer no longer wants that feed of, none for every symbol
+
+pub const QUOTES: u8 = 1;
+pub const DEPTH: u8 = 2;
+pub const DELTA: u8 = 3;
+pub const TRADES: u8 = 4;
+pub const ORDERS: u8 = 5;
+pub const ORDER_BOOK: u8 = 6;
+pub const SNAPSHOT_REQUEST: u8 = 128;
+pub const SUBSCRIBE: u8 = 129;
+pub const UNSUBSCRIBE: u8 = 130;
+
+// size of a single quote in a packet
+pub const QUOTE_SIZE: usize = 44;
+// size of the ticker, sequence number and level counts every depth entry starts with
+pub const DEPTH_HEADER_SIZE: usize = 16;
+// size of a single price level in a depth entry
+pub const LEVEL_SIZE: usize = 16;
+// size of the ticker, sequence number and update count every delta entry starts with
+pub const DELTA_HEADER_SIZE: usize = 14;
+// size of a single level update in a delta entry
+pub const LEVEL_UPDATE_SIZE: usize = 17;
+// size of a single trade in a packet
+pub const PRINT_SIZE: usize = 45;
+// size of the ticker, sequence number and event type every order event entry starts with
+pub const ORDER_EVENT_HEADER_SIZE: usize = 13;
+// size of the ticker, sequence number and order count every order book entry starts with
+pub const ORDER_BOOK_HEADER_SIZE: usize = 16;
+// size of a single order in an order book entry
+pub const RESTING_ORDER_SIZE: usize = 25;
+// size of a ticker in a request
+pub const TICKER_SIZE: usize = 4;
+
+/// the top of the book of a symbol, borrowing its ticker from the packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quote<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub best_bid: u64,
+    pub bid_size: u64,
+    pub best_ask: u64,
+    pub ask_size: u64,
+}
+
+impl<'a> Quote<'a> {
+    /// creates a quote of the prices of a symbol, as of sequence number `seq` of its top of book feed
+    pub fn new(price_info: &'a PriceInfo, seq: u64) -> Quote<'a> {
+        Quote {
+            ticker: price_info.get_symbol().ticker(),
+            seq: seq,
+            best_bid: price_info.best_bid,
+            bid_size: price_info.bid_size,
+            best_ask: price_info.best_ask,
+            ask_size: price_info.ask_size,
+        }
+    }
+}
+
+/// the best price levels of both sides of the book of a symbol, borrowing its ticker from the packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depth<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl<'a> Depth<'a> {
+    /// creates a depth entry of the book of a symbol, as of sequence number `seq` of its depth feed
+    pub fn new(depth_info: &'a DepthInfo, seq: u64) -> Depth<'a> {
+        Depth {
+            ticker: depth_info.get_symbol().ticker(),
+            seq: seq,
+            bids: depth_info.bids.clone(),
+            asks: depth_info.asks.clone(),
+        }
+    }
+}
+
+/// the levels that changed in the depth of the book of a symbol, borrowing its ticker from the packet.
+/// applying it to the depth at sequence number `seq - 1` gives the depth at `seq`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub updates: Vec<LevelUpdate>,
+}
+
+/// a trade of a symbol, borrowing its ticker from the packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Print<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub trade_id: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub aggressor_side: OrderSide,
+    pub timestamp: u64,
+}
+
+impl<'a> Print<'a> {
+    /// creates a print of a trade, as sequence number `seq` of its symbol's trade feed
+    pub fn new(trade: &'a Trade, seq: u64) -> Print<'a> {
+        Print {
+            ticker: trade.get_symbol().ticker(),
+            seq: seq,
+            trade_id: trade.trade_id,
+            price: trade.price,
+            quantity: trade.quantity,
+            aggressor_side: trade.aggressor_side,
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+/// an event of the order by order feed of a symbol, borrowing its ticker from the packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderUpdate<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub event: OrderEvent,
+}
+
+impl<'a> OrderUpdate<'a> {
+    /// creates an update of an order event, as sequence number `seq` of its symbol's order by order feed
+    pub fn new(order_event_info: &'a OrderEventInfo, seq: u64) -> OrderUpdate<'a> {
+        OrderUpdate {
+            ticker: order_event_info.get_symbol().ticker(),
+            seq: seq,
+            event: order_event_info.event,
+        }
+    }
+}
+
+/// every order resting in the book of a symbol, borrowing its ticker from the packet.
+/// `orders` are the events adding them to an empty book, in priority order, as given by `RestingOrders::events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBook<'a> {
+    pub ticker: &'a str,
+    pub seq: u64,
+    pub orders: Vec<OrderEvent>,
+}
+
+/// a decoded market data packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Quotes(Vec<Quote<'a>>),
+    Depth(Vec<Depth<'a>>),
+    Delta(Vec<Delta<'a>>),
+    Trades(Vec<Print<'a>>),
+    Orders(Vec<OrderUpdate<'a>>),
+    OrderBook(Vec<OrderBook<'a>>),
+}
+
+/// a request from a peer to the market data provider, borrowing its tickers from the message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedRequest<'a> {
+    // a snapshot of the subscribed feeds of these tickers, or of every symbol when empty
+    Snapshot(Vec<&'a str>),
+    // start sending a feed of these tickers, or of every symbol when empty
+    Subscribe(Feed, Vec<&'a str>),
+    // stop sending a feed of these tickers, or of every symbol when empty
+    Unsubscribe(Feed, Vec<&'a str>),
+}
+
+fn write_u64(data: &mut Vec<u8>, value: u64) {
+    data.write_u64::<NetworkEndian>(value).expect("[ERROR] writing to a vec can't fail");
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    data.write_u32::<NetworkEndian>(value).expect("[ERROR] writing to a vec can't fail");
+}
+
+fn write_u16(data: &mut Vec<u8>, value: u16) {
+    data.write_u16::<NetworkEndian>(value).expect("[ERROR] writing to a vec can't fail");
+}
+
+fn write_side(data: &mut Vec<u8>, side: OrderSide) {
+    data.push(match side {
+        OrderSide::Buy => 0,
+        OrderSide::Sell => 1,
+    });
+}
+
+fn write_feed(data: &mut Vec<u8>, feed: Feed) {
+    data.push(match feed {
+        Feed::TopOfBook => 0,
+        Feed::Depth => 1,
+        Feed::Trades => 2,
+        Feed::Orders => 3,
+    });
+}
+
+fn read_feed(feed: u8) -> Result<Feed, DecodeError> {
+    match feed {
+        0 => Ok(Feed::TopOfBook),
+        1 => Ok(Feed::Depth),
+        2 => Ok(Feed::Trades),
+        3 => Ok(Feed::Orders),
+        feed => Err(DecodeError::UnknownFeed(feed)),
+    }
+}
+
+fn read_side(side: u8) -> Result<OrderSide, DecodeError> {
+    match side {
+        0 => Ok(OrderSide::Buy),
+        1 => Ok(OrderSide::Sell),
+        side => Err(DecodeError::UnknownSide(side)),
+    }
+}
+
+/// turns quotes into a packet
+pub fn encode_quotes<'a, I: IntoIterator<Item = Quote<'a>>>(quotes: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![QUOTES];
+    for quote in quotes {
+        write_ticker(&mut data, quote.ticker);
+        for value in &[quote.seq, quote.best_bid, quote.bid_size, quote.best_ask, quote.ask_size] {
+            write_u64(&mut data, *value);
+        }
+    }
+    data
+}
+
+/// turns the depth of books into a packet. sides with more than `u16::MAX` levels are cut short
+pub fn encode_depth<'a, I: IntoIterator<Item = Depth<'a>>>(books: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![DEPTH];
+    for book in books {
+        let bids = &book.bids[..book.bids.len().min(u16::MAX as usize)];
+        let asks = &book.asks[..book.asks.len().min(u16::MAX as usize)];
+        write_ticker(&mut data, book.ticker);
+        write_u64(&mut data, book.seq);
+        write_u16(&mut data, bids.len() as u16);
+        write_u16(&mut data, asks.len() as u16);
+        for level in bids.iter().chain(asks) {
+            write_u64(&mut data, level.price);
+            write_u64(&mut data, level.quantity);
+        }
+    }
+    data
+}
+
+/// turns depth deltas into a packet. deltas with more than `u16::MAX` updates are cut short
+pub fn encode_deltas<'a, I: IntoIterator<Item = Delta<'a>>>(deltas: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![DELTA];
+    for delta in deltas {
+        let updates = &delta.updates[..delta.updates.len().min(u16::MAX as usize)];
+        write_ticker(&mut data, delta.ticker);
+        write_u64(&mut data, delta.seq);
+        write_u16(&mut data, updates.len() as u16);
+        for update in updates {
+            write_side(&mut data, update.side);
+            write_u64(&mut data, update.price);
+            write_u64(&mut data, update.quantity);
+        }
+    }
+    data
+}
+
+/// turns trades into a packet
+pub fn encode_prints<'a, I: IntoIterator<Item = Print<'a>>>(prints: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![TRADES];
+    for print in prints {
+        write_ticker(&mut data, print.ticker);
+        for value in &[print.seq, print.trade_id, print.price, print.quantity] {
+            write_u64(&mut data, *value);
+        }
+        write_side(&mut data, print.aggressor_side);
+        write_u64(&mut data, print.timestamp);
+    }
+    data
+}
+
+/// turns order events into a packet
+pub fn encode_order_updates<'a, I: IntoIterator<Item = OrderUpdate<'a>>>(updates: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![ORDERS];
+    for update in updates {
+        write_ticker(&mut data, update.ticker);
+        write_u64(&mut data, update.seq);
+        match update.event {
+            OrderEvent::Added(order_ref, side, price, quantity) => {
+                data.push(1);
+                write_u64(&mut data, order_ref);
+                write_side(&mut data, side);
+                write_u64(&mut data, price);
+                write_u64(&mut data, quantity);
+            }
+            OrderEvent::Executed(order_ref, quantity, trade_id) => {
+                data.push(2);
+                for value in &[order_ref, quantity, trade_id] {
+                    write_u64(&mut data, *value);
+                }
+            }
+            OrderEvent::Cancelled(order_ref, quantity) => {
+                data.push(3);
+                write_u64(&mut data, order_ref);
+                write_u64(&mut data, quantity);
+            }
+            OrderEvent::Replaced(order_ref, new_order_ref, price, quantity) => {
+                data.push(4);
+                for value in &[order_ref, new_order_ref, price, quantity] {
+                    write_u64(&mut data, *value);
+                }
+            }
+        }
+    }
+    data
+}
+
+/// turns the resting orders of books into a packet. events other than added orders are left out
+pub fn encode_order_books<'a, I: IntoIterator<Item = OrderBook<'a>>>(books: I) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![ORDER_BOOK];
+    for book in books {
+        let orders: Vec<(u64, OrderSide, u64, u64)> = book.orders
+            .iter()
+            .filter_map(|event| match *event {
+                OrderEvent::Added(order_ref, side, price, quantity) => Some((order_ref, side, price, quantity)),
+                _ => None,
+            })
+            .collect();
+        write_ticker(&mut data, book.ticker);
+        write_u64(&mut data, book.seq);
+        write_u32(&mut data, orders.len() as u32);
+        for (order_ref, side, price, quantity) in orders {
+            write_u64(&mut data, order_ref);
+            write_side(&mut data, side);
+            write_u64(&mut data, price);
+            write_u64(&mut data, quantity);
+        }
+    }
+    data
+}
+
+/// decodes a packet of any type
+pub fn decode_packet<'a>(data: &'a [u8]) -> Result<Packet<'a>, DecodeError> {
+    if data.is_empty() {
+        return Err(DecodeError::TooShort);
+    }
+
+    match data[0] {
+        QUOTES => Ok(Packet::Quotes(decode_quotes(&data[1..])?)),
+        DEPTH => Ok(Packet::Depth(decode_depth(&data[1..])?)),
+        DELTA => Ok(Packet::Delta(decode_deltas(&data[1..])?)),
+        TRADES => Ok(Packet::Trades(decode_prints(&data[1..])?)),
+        ORDERS => Ok(Packet::Orders(decode_order_updates(&data[1..])?)),
+        ORDER_BOOK => Ok(Packet::OrderBook(decode_order_books(&data[1..])?)),
+        packet_type => Err(DecodeError::UnknownType(packet_type)),
+    }
+}
+
+/// turns a request to the provider into a message
+pub fn encode_feed_request(request: &FeedRequest) -> Vec<u8> {
+    let (mut data, tickers) = match *request {
+        FeedRequest::Snapshot(ref tickers) => (vec![SNAPSHOT_REQUEST], tickers),
+        FeedRequest::Subscribe(feed, ref tickers) => {
+            let mut data: Vec<u8> = vec![SUBSCRIBE];
+            write_feed(&mut data, feed);
+            (data, tickers)
+        }
+        FeedRequest::Unsubscribe(feed, ref tickers) => {
+            let mut data: Vec<u8> = vec![UNSUBSCRIBE];
+            write_feed(&mut data, feed);
+            (data, tickers)
+        }
+    };
+    for ticker in tickers {
+        write_ticker(&mut data, ticker);
+    }
+    data
+}
+
+/// decodes a request a peer sent to the provider
+pub fn decode_feed_request<'a>(data: &'a [u8]) -> Result<FeedRequest<'a>, DecodeError> {
+    if data.is_empty() {
+        return Err(DecodeError::TooShort);
+    }
+
+    match data[0] {
+        SNAPSHOT_REQUEST => Ok(FeedRequest::Snapshot(read_tickers(&data[1..])?)),
+        SUBSCRIBE | UNSUBSCRIBE if data.len() < 2 => Err(DecodeError::TooShort),
+        SUBSCRIBE => Ok(FeedRequest::Subscribe(read_feed(data[1])?, read_tickers(&data[2..])?)),
+        UNSUBSCRIBE => Ok(FeedRequest::Unsubscribe(read_feed(data[1])?, read_tickers(&data[2..])?)),
+        request_type => Err(DecodeError::UnknownType(request_type)),
+    }
+}
+
+fn read_ticker(data: &[u8]) -> Result<&str, DecodeError> {
+    // tickers shorter than 4 bytes are padded with 0s
+    Ok(str::from_utf8(&data[0..4]).map_err(|_| DecodeError::InvalidTicker)?.trim_end_matches('\0'))
+}
+
+fn read_tickers(body: &[u8]) -> Result<Vec<&str>, DecodeError> {
+    if !body.len().is_multiple_of(TICKER_SIZE) {
+        return Err(DecodeError::WrongLength);
+    }
+    body.chunks(TICKER_SIZE).map(read_ticker).collect()
+}
+
+fn decode_quotes<'a>(body: &'a [u8]) -> Result<Vec<Quote<'a>>, DecodeError> {
+    if !body.len().is_multiple_of(QUOTE_SIZE) {
+        return Err(DecodeError::WrongLength);
+    }
+
+    body.chunks(QUOTE_SIZE)
+        .map(|quote| {
+            Ok(Quote {
+                ticker: read_ticker(quote)?,
+                seq: NetworkEndian::read_u64(&quote[4..12]),
+                best_bid: NetworkEndian::read_u64(&quote[12..20]),
+                bid_size: NetworkEndian::read_u64(&quote[20..28]),
+                best_ask: NetworkEndian::read_u64(&quote[28..36]),
+                ask_size: NetworkEndian::read_u64(&quote[36..44]),
+            })
+        })
+        .collect()
+}
+
+fn decode_depth<'a>(mut body: &'a [u8]) -> Result<Vec<Depth<'a>>, DecodeError> {
+    let mut books: Vec<Depth<'a>> = Vec::new();
+    while !body.is_empty() {
+        if body.len() < DEPTH_HEADER_SIZE {
+            return Err(DecodeError::WrongLength);
+        }
+        let bid_count = NetworkEndian::read_u16(&body[12..14]) as usize;
+        let ask_count = NetworkEndian::read_u16(&body[14..16]) as usize;
+        let size = DEPTH_HEADER_SIZE + (bid_count + ask_count) * LEVEL_SIZE;
+        if body.len() < size {
+            return Err(DecodeError::WrongLength);
+        }
+
+        let mut levels = body[DEPTH_HEADER_SIZE..size]
+            .chunks(LEVEL_SIZE)
+            .map(|level| PriceLevel::new(NetworkEndian::read_u64(&level[0..8]), NetworkEndian::read_u64(&level[8..16])));
+        books.push(Depth {
+            ticker: read_ticker(body)?,
+            seq: NetworkEndian::read_u64(&body[4..12]),
+            bids: levels.by_ref().take(bid_count).collect(),
+            asks: levels.collect(),
+        });
+        body = &body[size..];
+    }
+    Ok(books)
+}
+
+fn decode_deltas<'a>(mut body: &'a [u8]) -> Result<Vec<Delta<'a>>, DecodeError> {
+    let mut deltas: Vec<Delta<'a>> = Vec::new();
+    while !body.is_empty() {
+        if body.len() < DELTA_HEADER_SIZE {
+            return Err(DecodeError::WrongLength);
+        }
+        let update_count = NetworkEndian::read_u16(&body[12..14]) as usize;
+        let size = DELTA_HEADER_SIZE + update_count * LEVEL_UPDATE_SIZE;
+        if body.len() < size {
+            return Err(DecodeError::WrongLength);
+        }
+
+        let updates = body[DELTA_HEADER_SIZE..size]
+            .chunks(LEVEL_UPDATE_SIZE)
+            .map(|update| {
+                Ok(LevelUpdate::new(read_side(update[0])?, NetworkEndian::read_u64(&update[1..9]), NetworkEndian::read_u64(&update[9..17])))
+            })
+            .collect::<Result<Vec<LevelUpdate>, DecodeError>>()?;
+        deltas.push(Delta {
+            ticker: read_ticker(body)?,
+            seq: NetworkEndian::read_u64(&body[4..12]),
+            updates: updates,
+        });
+        body = &body[size..];
+    }
+    Ok(deltas)
+}
+
+fn decode_prints<'a>(body: &'a [u8]) -> Result<Vec<Print<'a>>, DecodeError> {
+    if !body.len().is_multiple_of(PRINT_SIZE) {
+        return Err(DecodeError::WrongLength);
+    }
+
+    body.chunks(PRINT_SIZE)
+        .map(|print| {
+            Ok(Print {
+                ticker: read_ticker(print)?,
+                seq: NetworkEndian::read_u64(&print[4..12]),
+                trade_id: NetworkEndian::read_u64(&print[12..20]),
+                price: NetworkEndian::read_u64(&print[20..28]),
+                quantity: NetworkEndian::read_u64(&print[28..36]),
+                aggressor_side: read_side(print[36])?,
+                timestamp: NetworkEndian::read_u64(&print[37..45]),
+            })
+        })
+        .collect()
+}
+
+fn decode_order_updates<'a>(mut body: &'a [u8]) -> Result<Vec<OrderUpdate<'a>>, DecodeError> {
+    let mut updates: Vec<OrderUpdate<'a>> = Vec::new();
+    while !body.is_empty() {
+        if body.len() < ORDER_EVENT_HEADER_SIZE {
+            return Err(DecodeError::WrongLength);
+        }
+        let event_type = body[12];
+        let size = ORDER_EVENT_HEADER_SIZE + match event_type {
+            1 => 25,
+            2 => 24,
+            3 => 16,
+            4 => 32,
+            event_type => return Err(DecodeError::UnknownType(event_type)),
+        };
+        if body.len() < size {
+            return Err(DecodeError::WrongLength);
+        }
+
+        let fields = &body[ORDER_EVENT_HEADER_SIZE..size];
+        let read_u64 = |at: usize| NetworkEndian::read_u64(&fields[at..at + 8]);
+        let event = match event_type {
+            1 => OrderEvent::Added(read_u64(0), read_side(fields[8])?, read_u64(9), read_u64(17)),
+            2 => OrderEvent::Executed(read_u64(0), read_u64(8), read_u64(16)),
+            3 => OrderEvent::Cancelled(read_u64(0), read_u64(8)),
+            _ => OrderEvent::Replaced(read_u64(0), read_u64(8), read_u64(16), read_u64(24)),
+        };
+        updates.push(OrderUpdate {
+            ticker: read_ticker(body)?,
+            seq: NetworkEndian::read_u64(&body[4..12]),
+            event: event,
+        });
+        body = &body[size..];
+    }
+    Ok(updates)
+}
+
+fn decode_order_books<'a>(mut body: &'a [u8]) -> Result<Vec<OrderBook<'a>>, DecodeError> {
+    let mut books: Vec<OrderBook<'a>> = Vec::new();
+    while !body.is_empty() {
+        if body.len() < ORDER_BOOK_HEADER_SIZE {
+            return Err(DecodeError::WrongLength);
+        }
+        let order_count = NetworkEndian::read_u32(&body[12..16]) as usize;
+        let size = ORDER_BOOK_HEADER_SIZE + order_count * RESTING_ORDER_SIZE;
+        if body.len() < size {
+            return Err(DecodeError::WrongLength);
+        }
+
+        let orders = body[ORDER_BOOK_HEADER_SIZE..size]
+            .chunks(RESTING_ORDER_SIZE)
+            .map(|order| {
+                Ok(OrderEvent::Added(
+                    NetworkEndian::read_u64(&order[0..8]),
+                    read_side(order[8])?,
+                    NetworkEndian::read_u64(&order[9..17]),
+                    NetworkEndian::read_u64(&order[17..25]),
+                ))
+            })
+            .collect::<Result<Vec<OrderEvent>, DecodeError>>()?;
+        books.push(OrderBook {
+            ticker: read_ticker(body)?,
+            seq: NetworkEndian::read_u64(&body[4..12]),
+            orders: orders,
+        });
+        body = &body[size..];
+    }
+    Ok(books)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(ticker: &str) -> Quote<'_> {
+        Quote {
+            ticker: ticker,
+            seq: 3,
+            best_bid: 100500,
+            bid_size: 10,
+            best_ask: 101000,
+            ask_size: 20,
+        }
+    }
+
+    fn depth(ticker: &str) -> Depth<'_> {
+        Depth {
+            ticker: ticker,
+            seq: 7,
+            bids: vec![PriceLevel::new(100500, 10), PriceLevel::new(100000, 5)],
+            asks: vec![PriceLevel::new(101000, 20)],
+        }
+    }
+
+    fn delta(ticker: &str) -> Delta<'_> {
+        Delta {
+            ticker: ticker,
+            seq: 8,
+            updates: vec![LevelUpdate::new(OrderSide::Buy, 100500, 0), LevelUpdate::new(OrderSide::Sell, 100900, 4)],
+        }
+    }
+
+    #[test]
+    fn test_quotes_round_trip() {
+        let goog: &'static Symbol = Box::leak(Box::new(Symbol::new("GOOG".to_string())));
+        let price_info = PriceInfo::new(goog, 100500, 10, 101000, 20);
+        assert_eq!(Quote::new(&price_info, 3), quote("GOOG"));
+
+        let data = encode_quotes(vec![quote("GOOG"), quote("AAPL")]);
+        assert_eq!(data.len(), 1 + 2 * QUOTE_SIZE);
+        assert_eq!(&data[0..13], b"\x01GOOG\x00\x00\x00\x00\x00\x00\x00\x03");
+        assert_eq!(decode_packet(&data), Ok(Packet::Quotes(vec![quote("GOOG"), quote("AAPL")])));
+        assert_eq!(decode_packet(&encode_quotes(vec![quote("GE")])), Ok(Packet::Quotes(vec![quote("GE")])));
+        assert_eq!(decode_packet(&[QUOTES]), Ok(Packet::Quotes(vec![])));
+    }
+
+    #[test]
+    fn test_depth_round_trip() {
+        let goog: &'static Symbol = Box::leak(Box::new(Symbol::new("GOOG".to_string())));
+        let depth_info = DepthInfo::new(goog, depth("GOOG").bids, depth("GOOG").asks);
+        assert_eq!(Depth::new(&depth_info, 7), depth("GOOG"));
+
+        let empty = Depth { ticker: "GE", seq: 0, bids: vec![], asks: vec![] };
+        let data = encode_depth(vec![depth("GOOG"), empty.clone()]);
+        assert_eq!(data.len(), 1 + 2 * DEPTH_HEADER_SIZE + 3 * LEVEL_SIZE);
+        assert_eq!(&data[0..17], b"\x02GOOG\x00\x00\x00\x00\x00\x00\x00\x07\x00\x02\x00\x01");
+        assert_eq!(decode_packet(&data), Ok(Packet::Depth(vec![depth("GOOG"), empty])));
+    }
+
+    #[test]
+    fn test_deltas_round_trip() {
+        let data = encode_deltas(vec![delta("GOOG"), delta("GE")]);
+        assert_eq!(data.len(), 1 + 2 * (DELTA_HEADER_SIZE + 2 * LEVEL_UPDATE_SIZE));
+        assert_eq!(&data[13..16], b"\x00\x02\x00");
+        assert_eq!(decode_packet(&data), Ok(Packet::Delta(vec![delta("GOOG"), delta("GE")])));
+    }
+
+    #[test]
+    fn test_prints_round_trip() {
+        let goog: &'static Symbol = Box::leak(Box::new(Symbol::new("GOOG".to_string())));
+        let trade = Trade::new(goog, 12, 100500, 7, OrderSide::Sell, 1_600_000_000_000_000_000);
+        let print = Print::new(&trade, 2);
+        assert_eq!((print.ticker, print.seq, print.trade_id, print.aggressor_side), ("GOOG", 2, 12, OrderSide::Sell));
+
+        let data = encode_prints(vec![print, Print { ticker: "GE", ..print }]);
+        assert_eq!(data.len(), 1 + 2 * PRINT_SIZE);
+        assert_eq!(data[1 + 36], 1);
+        assert_eq!(decode_packet(&data), Ok(Packet::Trades(vec![print, Print { ticker: "GE", ..print }])));
+        assert_eq!(decode_packet(&data[..PRINT_SIZE]), Err(DecodeError::WrongLength));
+    }
+
+    #[test]
+    fn test_order_updates_round_trip() {
+        let updates: Vec<OrderUpdate> = vec![
+            OrderEvent::Added(1, OrderSide::Sell, 100500, 10),
+            OrderEvent::Executed(1, 4, 12),
+            OrderEvent::Cancelled(1, 2),
+            OrderEvent::Replaced(1, 2, 100600, 4),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(seq, event)| OrderUpdate { ticker: "GOOG", seq: seq as u64, event: event })
+        .collect();
+        let data = encode_order_updates(updates.clone());
+        assert_eq!(data.len(), 1 + 4 * ORDER_EVENT_HEADER_SIZE + 25 + 24 + 16 + 32);
+        assert_eq!(&data[13..15], b"\x01\x00");
+        assert_eq!(decode_packet(&data), Ok(Packet::Orders(updates)));
+        assert_eq!(decode_packet(&data[..data.len() - 1]), Err(DecodeError::WrongLength));
+
+        let books = vec![
+            OrderBook { ticker: "GOOG", seq: 3, orders: vec![OrderEvent::Added(4, OrderSide::Buy, 100000, 5), OrderEvent::Added(2, OrderSide::Sell, 100500, 1)] },
+            OrderBook { ticker: "GE", seq: 0, orders: vec![] },
+        ];
+        let data = encode_order_books(books.clone());
+        assert_eq!(data.len(), 1 + 2 * ORDER_BOOK_HEADER_SIZE + 2 * RESTING_ORDER_SIZE);
+        assert_eq!(decode_packet(&data), Ok(Packet::OrderBook(books)));
+    }
+
+    #[test]
+    fn test_feed_requests_round_trip() {
+        let request = FeedRequest::Snapshot(vec!["GOOG", "GE"]);
+        let data = encode_feed_request(&request);
+        assert_eq!(data, b"\x80GOOGGE\x00\x00".to_vec());
+        assert_eq!(decode_feed_request(&data), Ok(request));
+        assert_eq!(decode_feed_request(&[SNAPSHOT_REQUEST]), Ok(FeedRequest::Snapshot(vec![])));
+        assert_eq!(decode_feed_request(&data[..3]), Err(DecodeError::WrongLength));
+        assert_eq!(decode_feed_request(&[QUOTES]), Err(DecodeError::UnknownType(QUOTES)));
+
+        let request = FeedRequest::Subscribe(Feed::Orders, vec!["GOOG"]);
+        let data = encode_feed_request(&request);
+        assert_eq!(data, b"\x81\x03GOOG".to_vec());
+        assert_eq!(decode_feed_request(&data), Ok(request));
+        let request = FeedRequest::Unsubscribe(Feed::TopOfBook, vec![]);
+        assert_eq!(decode_feed_request(&encode_feed_request(&request)), Ok(request));
+        assert_eq!(decode_feed_request(&[SUBSCRIBE]), Err(DecodeError::TooShort));
+        assert_eq!(decode_feed_request(&[UNSUBSCRIBE, 4]), Err(DecodeError::UnknownFeed(4)));
+    }
+
+    #[test]
+    fn test_bad_packets_are_rejected() {
+        let mut data = encode_quotes(vec![quote("GOOG")]);
+        assert_eq!(decode_packet(&data[..QUOTE_SIZE]), Err(DecodeError::WrongLength));
+        data[1] = 0xff;
+        assert_eq!(decode_packet(&data), Err(DecodeError::InvalidTicker));
+
+        let data = encode_depth(vec![depth("GOOG")]);
+        assert_eq!(decode_packet(&data[..data.len() - 1]), Err(DecodeError::WrongLength));
+        assert_eq!(decode_packet(&data[..5]), Err(DecodeError::WrongLength));
+
+        let mut data = encode_deltas(vec![delta("GOOG")]);
+        assert_eq!(decode_packet(&data[..data.len() - 1]), Err(DecodeError::WrongLength));
+        data[1 + DELTA_HEADER_SIZE] = 2;
+        assert_eq!(decode_packet(&data), Err(DecodeError::UnknownSide(2)));
+
+        let mut data = encode_order_updates(vec![OrderUpdate { ticker: "GOOG", seq: 1, event: OrderEvent::Cancelled(1, 2) }]);
+        data[1 + 12] = 9;
+        assert_eq!(decode_packet(&data), Err(DecodeError::UnknownType(9)));
+
+        assert_eq!(decode_packet(&[]), Err(DecodeError::TooShort));
+        assert_eq!(decode_packet(&[42]), Err(DecodeError::UnknownType(42)));
+    }
+}
@@ -0,0 +1,91 @@
+use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+
+// every binary wire format of the exchange, shared by the exchange itself, its clients, tests and tools.
+// requests and responses are framed by a u32 length, while market data packets are sent as whole udp messages.
+// encoders return whole frames, ready to write. decoders take a message whose length prefix was already read,
+// and borrow from it instead of copying. all integers are big endian, and layouts are in the README
+
+pub mod market_data;
+pub mod request;
+pub mod response;
+
+// size of the length prefix every request and response starts with
+pub const LENGTH_SIZE: usize = 4;
+
+/// the ways a response or market data packet can fail to decode.
+/// requests are decoded by the exchange, so they fail with the `RejectReason` sent back to the client instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // the message is shorter than its header
+    TooShort,
+    // the body isn't the size its type's layout says
+    WrongLength,
+    UnknownVersion(u8),
+    UnknownType(u8),
+    UnknownRejectCode(u8),
+    UnknownLiquidity(u8),
+    UnknownSide(u8),
+    UnknownFeed(u8),
+    // the reason of an auth rejection isn't utf-8
+    InvalidReason,
+    // a market data ticker isn't utf-8
+    InvalidTicker,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::TooShort => write!(f, "message is shorter than its header"),
+            DecodeError::WrongLength => write!(f, "message length doesn't match its layout"),
+            DecodeError::UnknownVersion(version) => write!(f, "protocol version {} is not supported", version),
+            DecodeError::UnknownType(message_type) => write!(f, "message type {} does not exist", message_type),
+            DecodeError::UnknownRejectCode(code) => write!(f, "reject code {} does not exist", code),
+            DecodeError::UnknownLiquidity(liquidity) => write!(f, "liquidity {} does not exist", liquidity),
+            DecodeError::UnknownSide(side) => write!(f, "side {} does not exist", side),
+            DecodeError::UnknownFeed(feed) => write!(f, "feed {} does not exist", feed),
+            DecodeError::InvalidReason => write!(f, "auth rejection reason is not utf-8"),
+            DecodeError::InvalidTicker => write!(f, "ticker is not utf-8"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// reads the next frame from `reader`, returning the message after its length
+///
+/// # Parameters
+///
+/// * `reader` - where the frame is read from
+/// * `max_size` - largest message accepted, anything longer fails with `InvalidData` instead of being read
+pub fn read_message<R: Read>(reader: &mut R, max_size: usize) -> io::Result<Vec<u8>> {
+    let mut length = [0; LENGTH_SIZE];
+    reader.read_exact(&mut length)?;
+    let size = NetworkEndian::read_u32(&length) as usize;
+    if size > max_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too long", size)));
+    }
+
+    let mut data = vec![0; size];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// prefixes a message with its length, making it a frame ready to write
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut frame: Vec<u8> = Vec::with_capacity(LENGTH_SIZE + message.len());
+    frame.write_u32::<NetworkEndian>(message.len() as u32).expect("[ERROR] writing to a vec can't fail");
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// writes a ticker as the 4 bytes every layout gives it, padded with 0s or cut short if it's another length
+fn write_ticker(data: &mut Vec<u8>, ticker: &str) {
+    let mut bytes = [0; 4];
+    for (byte, ticker_byte) in bytes.iter_mut().zip(ticker.as_bytes()) {
+        *byte = *ticker_byte;
+    }
+    data.extend_from_slice(&bytes);
+}
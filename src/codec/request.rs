@@ -0,0 +1,452 @@
+use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
+use std::str;
+
+use super::{frame, write_ticker};
+use types::*;
+
+// binary requests sent by clients. every connection starts with an auth request, and every message after
+// that is a command whose lowest 2 bits of the first byte are its type. the auth request takes up the whole
+// first byte instead, which clashes with some execute headers, so the two are decoded separately.
+// status and cancel commands set bit 2 of their first byte when they name the order by client order id
+
+/// the auth request a binary connection starts with, borrowing its strings from the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Auth<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+/// a command sent by a logged in binary client, borrowing its ticker from the message
+/// * Execute - send a new order, optionally with a client order id echoed in every status of the order
+/// * Replace - change the price and/or quantity of an open order, None leaves that field unchanged
+/// * Status - ask for the status of an order, named by its order id or client order id
+/// * Cancel - cancel an open order, named by its order id or client order id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request<'a> {
+    Execute {
+        account_id: u32,
+        ticker: &'a str,
+        order_type: OrderType,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        // None falls back to the account's self trade prevention mode
+        self_trade_prevention: Option<SelfTradePrevention>,
+        quantity: u64,
+        // unique among the account's orders, and never 0
+        client_order_id: Option<u64>,
+    },
+    Replace {
+        account_id: u32,
+        order_id: u32,
+        price: Option<u64>,
+        quantity: Option<u64>,
+    },
+    Status {
+        account_id: u32,
+        order: OrderRef,
+    },
+    Cancel {
+        account_id: u32,
+        order: OrderRef,
+    },
+}
+
+impl<'a> Request<'a> {
+    /// returns the account the command was sent for
+    pub fn account_id(&self) -> u32 {
+        match *self {
+            Request::Execute { account_id, .. }
+            | Request::Replace { account_id, .. }
+            | Request::Status { account_id, .. }
+            | Request::Cancel { account_id, .. } => account_id,
+        }
+    }
+}
+
+/// turns an auth request into the frame sent to the exchange
+pub fn encode_auth(auth: &Auth) -> Vec<u8> {
+    let username = &auth.username.as_bytes()[..auth.username.len().min(255)];
+    let password = &auth.password.as_bytes()[..auth.password.len().min(255)];
+
+    let mut data = vec![4, username.len() as u8];
+    data.extend_from_slice(username);
+    data.push(password.len() as u8);
+    data.extend_from_slice(password);
+    frame(&data)
+}
+
+/// decodes an auth request, returning None if it's malformed
+pub fn decode_auth<'a>(data: &'a [u8]) -> Option<Auth<'a>> {
+    // the auth command takes up the whole first byte, unlike the other commands
+    if data.len() < 2 || CmdType::from_id(data[0]) != Ok(CmdType::Auth) {
+        return None;
+    }
+
+    let username_end = 2 + data[1] as usize;
+    if data.len() <= username_end {
+        return None;
+    }
+    let password_len = data[username_end] as usize;
+    if data.len() != username_end + 1 + password_len {
+        return None;
+    }
+
+    Some(Auth {
+        username: str::from_utf8(&data[2..username_end]).ok()?,
+        password: str::from_utf8(&data[username_end + 1..]).ok()?,
+    })
+}
+
+/// turns a command into the frame sent to the exchange
+pub fn encode_request(request: &Request) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    match *request {
+        Request::Execute {
+            account_id,
+            ticker,
+            order_type,
+            side,
+            time_in_force,
+            self_trade_prevention,
+            quantity,
+            client_order_id,
+        } => {
+            let side_id = match side {
+                OrderSide::Buy => 0,
+                OrderSide::Sell => 1,
+            };
+            let self_trade_prevention_id = match self_trade_prevention {
+                None => 0,
+                Some(SelfTradePrevention::CancelNewest) => 1,
+                Some(SelfTradePrevention::CancelOldest) => 2,
+                Some(SelfTradePrevention::CancelBoth) => 3,
+                Some(SelfTradePrevention::DecrementAndCancel) => 4,
+            };
+            let time_in_force_id = match time_in_force {
+                TimeInForce::GoodTillCancel => 0,
+                TimeInForce::Day => 1,
+                TimeInForce::ImmediateOrCancel => 2,
+                TimeInForce::FillOrKill => 3,
+            };
+            // stop limit orders send their stop price in place of the price, and their limit price at the end
+            let (order_type_id, price, limit_price) = match order_type {
+                OrderType::Market => (0, 0, None),
+                OrderType::Limit(price) => (1, price, None),
+                OrderType::Stop(stop_price) => (2, stop_price, None),
+                OrderType::StopLimit(stop_price, limit_price) => (3, stop_price, Some(limit_price)),
+            };
+
+            data.push(self_trade_prevention_id << 3 | side_id << 2);
+            push_u32(&mut data, account_id);
+            data.push(time_in_force_id << 4 | order_type_id);
+            write_ticker(&mut data, ticker);
+            push_u64(&mut data, price);
+            push_u64(&mut data, quantity);
+            if let Some(limit_price) = limit_price {
+                push_u64(&mut data, limit_price);
+            }
+            // orders without a client order id leave it off
+            if let Some(client_order_id) = client_order_id {
+                push_u64(&mut data, client_order_id);
+            }
+        }
+        Request::Replace {
+            account_id,
+            order_id,
+            price,
+            quantity,
+        } => {
+            // a price or quantity of 0 leaves that field unchanged
+            data.push(1);
+            push_u32(&mut data, account_id);
+            push_u32(&mut data, order_id);
+            push_u64(&mut data, price.unwrap_or(0));
+            push_u64(&mut data, quantity.unwrap_or(0));
+        }
+        Request::Status { account_id, order } => push_order_ref(&mut data, 2, account_id, order),
+        Request::Cancel { account_id, order } => push_order_ref(&mut data, 3, account_id, order),
+    }
+    frame(&data)
+}
+
+/// decodes a command, returning the reason it's rejected if it's malformed or out of range.
+/// whether the account and ticker exist is left to the caller
+pub fn decode_request<'a>(data: &'a [u8]) -> Result<Request<'a>, RejectReason> {
+    if data.len() < 5 {
+        return Err(RejectReason::MessageTooShort);
+    }
+    let account_id = NetworkEndian::read_u32(&data[1..5]);
+
+    match CmdType::from_id(data[0] & 3)? {
+        CmdType::Execute => {
+            if data.len() < 26 {
+                return Err(RejectReason::MessageTooShort);
+            }
+            let side = OrderSide::from_id((data[0] >> 2) & 1)?;
+            // 0 falls back to the account's self trade prevention mode
+            let self_trade_prevention = match data[0] >> 3 {
+                0 => None,
+                id => Some(SelfTradePrevention::from_id(id)?),
+            };
+            // low 4 bits are the order type, high 4 bits are the time in force
+            let mut order_type = OrderType::from_id(data[5] & 15)?;
+            let time_in_force = TimeInForce::from_id(data[5] >> 4)?;
+            let ticker = str::from_utf8(&data[6..10]).map_err(|_| RejectReason::UnknownTicker)?;
+
+            match order_type {
+                OrderType::Limit(ref mut thresh) => {
+                    *thresh = read_price(&data[10..18])?;
+                }
+                OrderType::Stop(ref mut thresh) => {
+                    *thresh = read_price(&data[10..18])?;
+                }
+                OrderType::StopLimit(ref mut thresh, ref mut limit) => {
+                    if data.len() < 34 {
+                        return Err(RejectReason::MessageTooShort);
+                    }
+                    *thresh = read_price(&data[10..18])?;
+                    *limit = read_price(&data[26..34])?;
+                }
+                _ => {}
+            };
+            // the client order id comes after the rest of the order, if it was sent
+            let client_order_id_offset = match order_type {
+                OrderType::StopLimit(_, _) => 34,
+                _ => 26,
+            };
+            let client_order_id = if data.len() >= client_order_id_offset + 8 {
+                let client_order_id = NetworkEndian::read_u64(&data[client_order_id_offset..client_order_id_offset + 8]);
+                Some(check_client_order_id(client_order_id)?)
+            } else {
+                None
+            };
+
+            Ok(Request::Execute {
+                account_id: account_id,
+                ticker: ticker,
+                order_type: order_type,
+                side: side,
+                time_in_force: time_in_force,
+                self_trade_prevention: self_trade_prevention,
+                quantity: check_quantity(NetworkEndian::read_u64(&data[18..26]))?,
+                client_order_id: client_order_id,
+            })
+        }
+        CmdType::Replace => {
+            if data.len() < 25 {
+                return Err(RejectReason::MessageTooShort);
+            }
+            // a price or quantity of 0 leaves that field unchanged
+            let price = read_price(&data[9..17])?;
+            let quantity = NetworkEndian::read_u64(&data[17..25]);
+            if quantity != 0 {
+                check_quantity(quantity)?;
+            }
+
+            Ok(Request::Replace {
+                account_id: account_id,
+                order_id: NetworkEndian::read_u32(&data[5..9]),
+                price: if price == 0 { None } else { Some(price) },
+                quantity: if quantity == 0 { None } else { Some(quantity) },
+            })
+        }
+        CmdType::Status => Ok(Request::Status {
+            account_id: account_id,
+            order: read_order_ref(data)?,
+        }),
+        CmdType::Cancel => Ok(Request::Cancel {
+            account_id: account_id,
+            order: read_order_ref(data)?,
+        }),
+        CmdType::Pnl => Err(RejectReason::UnknownCommand),
+        CmdType::Auth => Err(RejectReason::AlreadyLoggedIn),
+    }
+}
+
+/// reads a price from the 8 bytes in `data`, rejecting prices too large to trade safely
+fn read_price(data: &[u8]) -> Result<u64, RejectReason> {
+    check_price(NetworkEndian::read_u64(data))
+}
+
+/// reads the order a status or cancel command is for, by client order id if bit 2 of its first byte is set
+fn read_order_ref(data: &[u8]) -> Result<OrderRef, RejectReason> {
+    if (data[0] >> 2) & 1 == 1 {
+        if data.len() < 13 {
+            return Err(RejectReason::MessageTooShort);
+        }
+        return Ok(OrderRef::Client(check_client_order_id(NetworkEndian::read_u64(&data[5..13]))?));
+    }
+    if data.len() < 9 {
+        return Err(RejectReason::MessageTooShort);
+    }
+    Ok(OrderRef::Exchange(NetworkEndian::read_u32(&data[5..9])))
+}
+
+/// writes a status or cancel command of type `cmd_type`
+fn push_order_ref(data: &mut Vec<u8>, cmd_type: u8, account_id: u32, order: OrderRef) {
+    match order {
+        OrderRef::Exchange(order_id) => {
+            data.push(cmd_type);
+            push_u32(data, account_id);
+            push_u32(data, order_id);
+        }
+        OrderRef::Client(client_order_id) => {
+            data.push(cmd_type | 1 << 2);
+            push_u32(data, account_id);
+            push_u64(data, client_order_id);
+        }
+    }
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.write_u32::<NetworkEndian>(value).expect("[ERROR] writing to a vec can't fail");
+}
+
+fn push_u64(data: &mut Vec<u8>, value: u64) {
+    data.write_u64::<NetworkEndian>(value).expect("[ERROR] writing to a vec can't fail");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::LENGTH_SIZE;
+    use proptest::prelude::*;
+
+    fn arb_request() -> impl Strategy<Value = Request<'static>> {
+        let order_type = prop_oneof![
+            Just(OrderType::Market),
+            (0..=MAX_PRICE).prop_map(OrderType::Limit),
+            (0..=MAX_PRICE).prop_map(OrderType::Stop),
+            (0..=MAX_PRICE, 0..=MAX_PRICE).prop_map(|(stop_price, limit_price)| OrderType::StopLimit(stop_price, limit_price)),
+        ];
+        let self_trade_prevention = prop_oneof![
+            Just(None),
+            (1u8..=4).prop_map(|id| Some(SelfTradePrevention::from_id(id).unwrap())),
+        ];
+        let execute = (
+            any::<u32>(),
+            prop_oneof![Just("GOOG"), Just("AAPL")],
+            order_type,
+            0u8..2,
+            0u8..4,
+            self_trade_prevention,
+            (1..=MAX_QUANTITY, prop::option::of(1..=u64::MAX)),
+        )
+            .prop_map(|(account_id, ticker, order_type, side, time_in_force, self_trade_prevention, (quantity, client_order_id))| Request::Execute {
+                account_id: account_id,
+                ticker: ticker,
+                order_type: order_type,
+                side: OrderSide::from_id(side).unwrap(),
+                time_in_force: TimeInForce::from_id(time_in_force).unwrap(),
+                self_trade_prevention: self_trade_prevention,
+                quantity: quantity,
+                client_order_id: client_order_id,
+            });
+        let order = prop_oneof![
+            any::<u32>().prop_map(OrderRef::Exchange),
+            (1..=u64::MAX).prop_map(OrderRef::Client),
+        ];
+        prop_oneof![
+            execute,
+            (any::<u32>(), any::<u32>(), prop::option::of(1..=MAX_PRICE), prop::option::of(1..=MAX_QUANTITY)).prop_map(
+                |(account_id, order_id, price, quantity)| Request::Replace {
+                    account_id: account_id,
+                    order_id: order_id,
+                    price: price,
+                    quantity: quantity,
+                }
+            ),
+            (any::<u32>(), order.clone()).prop_map(|(account_id, order)| Request::Status {
+                account_id: account_id,
+                order: order,
+            }),
+            (any::<u32>(), order).prop_map(|(account_id, order)| Request::Cancel {
+                account_id: account_id,
+                order: order,
+            }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_requests_round_trip(request in arb_request()) {
+            let frame = encode_request(&request);
+            prop_assert_eq!(NetworkEndian::read_u32(&frame[..LENGTH_SIZE]) as usize, frame.len() - LENGTH_SIZE);
+            prop_assert_eq!(decode_request(&frame[LENGTH_SIZE..]), Ok(request));
+        }
+    }
+
+    #[test]
+    fn test_request_layout() {
+        let status = encode_request(&Request::Status { account_id: 1, order: OrderRef::Exchange(7) });
+        assert_eq!(status, vec![0, 0, 0, 9, 2, 0, 0, 0, 1, 0, 0, 0, 7]);
+        let cancel = encode_request(&Request::Cancel { account_id: 1, order: OrderRef::Client(7) });
+        assert_eq!(cancel, vec![0, 0, 0, 13, 3 | 1 << 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 7]);
+
+        let execute = encode_request(&Request::Execute {
+            account_id: 1,
+            ticker: "GOOG",
+            order_type: OrderType::StopLimit(95000, 94000),
+            side: OrderSide::Sell,
+            time_in_force: TimeInForce::Day,
+            self_trade_prevention: Some(SelfTradePrevention::CancelBoth),
+            quantity: 5,
+            client_order_id: Some(11),
+        });
+        assert_eq!(execute.len(), LENGTH_SIZE + 42);
+        assert_eq!(execute[LENGTH_SIZE], 3 << 3 | 1 << 2);
+        assert_eq!(execute[LENGTH_SIZE + 5], 1 << 4 | 3);
+        assert_eq!(&execute[LENGTH_SIZE + 6..LENGTH_SIZE + 10], b"GOOG");
+        assert_eq!(NetworkEndian::read_u64(&execute[LENGTH_SIZE + 26..LENGTH_SIZE + 34]), 94000);
+        assert_eq!(NetworkEndian::read_u64(&execute[LENGTH_SIZE + 34..]), 11);
+    }
+
+    #[test]
+    fn test_client_order_ids() {
+        let execute = encode_request(&Request::Execute {
+            account_id: 1,
+            ticker: "GOOG",
+            order_type: OrderType::Market,
+            side: OrderSide::Buy,
+            time_in_force: TimeInForce::GoodTillCancel,
+            self_trade_prevention: None,
+            quantity: 5,
+            client_order_id: Some(11),
+        });
+        let request = &execute[LENGTH_SIZE..];
+        assert_eq!(request.len(), 34);
+        // requests from clients that don't send one still decode
+        assert!(matches!(decode_request(&request[..26]), Ok(Request::Execute { client_order_id: None, .. })));
+
+        // 0 is kept for orders without a client order id
+        let mut zero = request.to_vec();
+        zero[26..].copy_from_slice(&[0; 8]);
+        assert_eq!(decode_request(&zero), Err(RejectReason::InvalidClientOrderId));
+        assert_eq!(decode_request(&[2 | 1 << 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]), Err(RejectReason::InvalidClientOrderId));
+        assert_eq!(decode_request(&[2 | 1 << 2, 0, 0, 0, 1, 0, 0, 0, 7]), Err(RejectReason::MessageTooShort));
+    }
+
+    #[test]
+    fn test_auth_requests() {
+        let frame = encode_auth(&Auth {
+            username: "alice",
+            password: "alice-password",
+        });
+        let request = &frame[LENGTH_SIZE..];
+        assert_eq!(decode_auth(request), Some(Auth { username: "alice", password: "alice-password" }));
+        let frame = encode_auth(&Auth { username: "bob", password: "" });
+        assert_eq!(decode_auth(&frame[LENGTH_SIZE..]), Some(Auth { username: "bob", password: "" }));
+
+        // wrong command, truncated and overlong requests are all rejected
+        let mut not_auth = request.to_vec();
+        not_auth[0] = 0;
+        assert_eq!(decode_auth(&not_auth), None);
+        assert_eq!(decode_auth(&[4]), None);
+        assert_eq!(decode_auth(&request[..request.len() - 1]), None);
+        let mut overlong = request.to_vec();
+        overlong.push(0);
+        assert_eq!(decode_auth(&overlong), None);
+        assert_eq!(decode_auth(&[4, 200, 0]), None);
+        assert_eq!(decode_auth(&[4, 1, 0xff, 0]), None);
+    }
+}
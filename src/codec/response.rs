@@ -0,0 +1,343 @@
+use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
+use std::error::Error;
+use std::str;
+
+use super::{frame, DecodeError};
+use types::*;
+
+// binary version of every response sent to clients. after its length, a response is
+//   u8 version  - PROTOCOL_VERSION
+//   u8 type     - which response it is, one of the types below
+//   body        - the fields of the response, with a fixed layout for each type
+// the body of every status starts with the u64 client order id of the order it's about, 0 if it has none
+
+pub const PROTOCOL_VERSION: u8 = 2;
+// version + type
+const HEADER_SIZE: usize = 2;
+// client order id every status body starts with
+const CLIENT_ORDER_ID_SIZE: usize = 8;
+// longest reason an auth rejection can carry
+const MAX_REASON_LENGTH: usize = 255;
+
+const FILLED: u8 = 1;
+const PARTIALLY_FILLED: u8 = 2;
+const WAITING: u8 = 3;
+const REJECTED: u8 = 4;
+const CANCELED: u8 = 5;
+const REPLACED: u8 = 6;
+const SELF_TRADE_PREVENTED: u8 = 7;
+const EXECUTION: u8 = 8;
+const AUTHENTICATED: u8 = 9;
+const AUTH_REJECTED: u8 = 10;
+
+/// a response sent to a binary client, borrowing its reason from the message
+/// * Authenticated - the auth request was accepted: account id
+/// * Auth Rejected - the auth request was rejected: reason
+/// * Status - a status of a command or an order, with the client order id of the order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response<'a> {
+    Authenticated(u32),
+    AuthRejected(&'a str),
+    Status(StatusReport),
+}
+
+/// turns a response into the frame sent to binary clients
+pub fn encode_response(response: &Response) -> Vec<u8> {
+    let mut data: Vec<u8> = vec![PROTOCOL_VERSION, 0];
+    data[1] = write_body(&mut data, response).expect("[ERROR] writing to a vec can't fail");
+    frame(&data)
+}
+
+/// writes the fields of a response, returning its type
+fn write_body(body: &mut Vec<u8>, response: &Response) -> Result<u8, Box<dyn Error>> {
+    let response_type = match *response {
+        Response::Authenticated(account_id) => {
+            body.write_u32::<NetworkEndian>(account_id)?;
+            AUTHENTICATED
+        }
+        Response::AuthRejected(reason) => {
+            let mut length = reason.len().min(MAX_REASON_LENGTH);
+            while !reason.is_char_boundary(length) {
+                length -= 1;
+            }
+            body.extend_from_slice(&reason.as_bytes()[..length]);
+            AUTH_REJECTED
+        }
+        Response::Status(report) => {
+            body.write_u64::<NetworkEndian>(report.client_order_id.unwrap_or(0))?;
+            write_status(body, &report.status)?
+        }
+    };
+    Ok(response_type)
+}
+
+/// writes the fields of a status after its client order id, returning its type
+fn write_status(body: &mut Vec<u8>, status: &OrderStatus) -> Result<u8, Box<dyn Error>> {
+    let response_type = match *status {
+        OrderStatus::Filled(order_id, cost) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            body.write_u64::<NetworkEndian>(cost)?;
+            FILLED
+        }
+        OrderStatus::PartiallyFilled(order_id, filled_quantity, cost) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            body.write_u64::<NetworkEndian>(filled_quantity)?;
+            body.write_u64::<NetworkEndian>(cost)?;
+            PARTIALLY_FILLED
+        }
+        OrderStatus::Waiting(order_id) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            WAITING
+        }
+        OrderStatus::Rejected(order_id, reason) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            body.push(reason.code());
+            REJECTED
+        }
+        OrderStatus::Canceled(order_id) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            CANCELED
+        }
+        OrderStatus::Replaced(order_id, price, quantity) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            body.write_u64::<NetworkEndian>(price)?;
+            body.write_u64::<NetworkEndian>(quantity)?;
+            REPLACED
+        }
+        OrderStatus::SelfTradePrevented(order_id, quantity) => {
+            body.write_u32::<NetworkEndian>(order_id)?;
+            body.write_u64::<NetworkEndian>(quantity)?;
+            SELF_TRADE_PREVENTED
+        }
+        OrderStatus::Execution(report) => {
+            body.write_u32::<NetworkEndian>(report.order_id)?;
+            body.write_u64::<NetworkEndian>(report.execution_id)?;
+            body.write_u64::<NetworkEndian>(report.price)?;
+            body.write_u64::<NetworkEndian>(report.quantity)?;
+            body.write_u64::<NetworkEndian>(report.leaves_quantity)?;
+            body.write_u64::<NetworkEndian>(report.cumulative_quantity)?;
+            body.write_u64::<NetworkEndian>(report.average_price)?;
+            body.push(match report.liquidity {
+                Liquidity::Maker => 0,
+                Liquidity::Taker => 1,
+            });
+            body.write_u64::<NetworkEndian>(report.timestamp)?;
+            EXECUTION
+        }
+    };
+    Ok(response_type)
+}
+
+/// checks that a body has the size its type's layout says
+fn expect_size(body: &[u8], size: usize) -> Result<(), DecodeError> {
+    if body.len() == size {
+        Ok(())
+    } else {
+        Err(DecodeError::WrongLength)
+    }
+}
+
+/// decodes a response
+pub fn decode_response<'a>(data: &'a [u8]) -> Result<Response<'a>, DecodeError> {
+    if data.len() < HEADER_SIZE {
+        return Err(DecodeError::TooShort);
+    }
+    if data[0] != PROTOCOL_VERSION {
+        return Err(DecodeError::UnknownVersion(data[0]));
+    }
+
+    let body = &data[HEADER_SIZE..];
+    let response = match data[1] {
+        AUTHENTICATED => {
+            expect_size(body, 4)?;
+            Response::Authenticated(NetworkEndian::read_u32(body))
+        }
+        AUTH_REJECTED => {
+            let reason = str::from_utf8(body).map_err(|_| DecodeError::InvalidReason)?;
+            Response::AuthRejected(reason)
+        }
+        status_type @ FILLED..=EXECUTION => {
+            if body.len() < CLIENT_ORDER_ID_SIZE {
+                return Err(DecodeError::WrongLength);
+            }
+            let client_order_id = match NetworkEndian::read_u64(body) {
+                0 => None,
+                client_order_id => Some(client_order_id),
+            };
+            let status = read_status(status_type, &body[CLIENT_ORDER_ID_SIZE..])?;
+            Response::Status(StatusReport::new(status, client_order_id))
+        }
+        response_type => return Err(DecodeError::UnknownType(response_type)),
+    };
+    Ok(response)
+}
+
+/// decodes the fields of a status after its client order id
+fn read_status(status_type: u8, body: &[u8]) -> Result<OrderStatus, DecodeError> {
+    let order_id = || NetworkEndian::read_u32(&body[0..4]);
+    let u64_at = |offset: usize| NetworkEndian::read_u64(&body[offset..offset + 8]);
+    let status = match status_type {
+        FILLED => {
+            expect_size(body, 12)?;
+            OrderStatus::Filled(order_id(), u64_at(4))
+        }
+        PARTIALLY_FILLED => {
+            expect_size(body, 20)?;
+            OrderStatus::PartiallyFilled(order_id(), u64_at(4), u64_at(12))
+        }
+        WAITING => {
+            expect_size(body, 4)?;
+            OrderStatus::Waiting(order_id())
+        }
+        REJECTED => {
+            expect_size(body, 5)?;
+            let reason = RejectReason::from_code(body[4]).ok_or(DecodeError::UnknownRejectCode(body[4]))?;
+            OrderStatus::Rejected(order_id(), reason)
+        }
+        CANCELED => {
+            expect_size(body, 4)?;
+            OrderStatus::Canceled(order_id())
+        }
+        REPLACED => {
+            expect_size(body, 20)?;
+            OrderStatus::Replaced(order_id(), u64_at(4), u64_at(12))
+        }
+        SELF_TRADE_PREVENTED => {
+            expect_size(body, 12)?;
+            OrderStatus::SelfTradePrevented(order_id(), u64_at(4))
+        }
+        EXECUTION => {
+            expect_size(body, 61)?;
+            let liquidity = match body[52] {
+                0 => Liquidity::Maker,
+                1 => Liquidity::Taker,
+                liquidity => return Err(DecodeError::UnknownLiquidity(liquidity)),
+            };
+            OrderStatus::Execution(ExecutionReport {
+                order_id: order_id(),
+                execution_id: u64_at(4),
+                price: u64_at(12),
+                quantity: u64_at(20),
+                leaves_quantity: u64_at(28),
+                cumulative_quantity: u64_at(36),
+                average_price: u64_at(44),
+                liquidity: liquidity,
+                timestamp: u64_at(53),
+            })
+        }
+        status_type => return Err(DecodeError::UnknownType(status_type)),
+    };
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::LENGTH_SIZE;
+    use proptest::prelude::*;
+
+    fn arb_status() -> impl Strategy<Value = OrderStatus> {
+        let execution = (
+            any::<u32>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<bool>(),
+            any::<u64>(),
+        )
+            .prop_map(|(order_id, execution_id, price, quantity, leaves_quantity, cumulative_quantity, average_price, is_maker, timestamp)| {
+                OrderStatus::Execution(ExecutionReport {
+                    execution_id: execution_id,
+                    order_id: order_id,
+                    price: price,
+                    quantity: quantity,
+                    leaves_quantity: leaves_quantity,
+                    cumulative_quantity: cumulative_quantity,
+                    average_price: average_price,
+                    liquidity: if is_maker { Liquidity::Maker } else { Liquidity::Taker },
+                    timestamp: timestamp,
+                })
+            });
+        prop_oneof![
+            (any::<u32>(), any::<u64>()).prop_map(|(order_id, cost)| OrderStatus::Filled(order_id, cost)),
+            (any::<u32>(), any::<u64>(), any::<u64>())
+                .prop_map(|(order_id, filled_quantity, cost)| OrderStatus::PartiallyFilled(order_id, filled_quantity, cost)),
+            any::<u32>().prop_map(OrderStatus::Waiting),
+            // every reject code, up to the newest one
+            (any::<u32>(), 1u8..=RejectReason::InvalidClientOrderId.code()).prop_map(|(order_id, code)| OrderStatus::Rejected(order_id, RejectReason::from_code(code).unwrap())),
+            any::<u32>().prop_map(OrderStatus::Canceled),
+            (any::<u32>(), any::<u64>(), any::<u64>()).prop_map(|(order_id, price, quantity)| OrderStatus::Replaced(order_id, price, quantity)),
+            (any::<u32>(), any::<u64>()).prop_map(|(order_id, quantity)| OrderStatus::SelfTradePrevented(order_id, quantity)),
+            execution,
+        ]
+    }
+
+    fn arb_response() -> impl Strategy<Value = Response<'static>> {
+        prop_oneof![
+            any::<u32>().prop_map(Response::Authenticated),
+            prop::sample::select(vec!["", "malformed auth request", "invalid username or password"]).prop_map(Response::AuthRejected),
+            (arb_status(), prop::option::of(1..=u64::MAX))
+                .prop_map(|(status, client_order_id)| Response::Status(StatusReport::new(status, client_order_id))),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_responses_round_trip(response in arb_response()) {
+            let frame = encode_response(&response);
+            prop_assert_eq!(NetworkEndian::read_u32(&frame[..LENGTH_SIZE]) as usize, frame.len() - LENGTH_SIZE);
+            prop_assert_eq!(frame[LENGTH_SIZE], PROTOCOL_VERSION);
+            prop_assert_eq!(decode_response(&frame[LENGTH_SIZE..]), Ok(response));
+        }
+
+        #[test]
+        fn prop_truncated_statuses_are_rejected(order_status in arb_status(), cut in 1usize..16) {
+            let frame = encode_response(&Response::Status(StatusReport::new(order_status, Some(3))));
+            let cut = cut.min(frame.len() - LENGTH_SIZE);
+            prop_assert!(decode_response(&frame[LENGTH_SIZE..frame.len() - cut]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_frame_layout() {
+        let waiting = StatusReport::new(OrderStatus::Waiting(7), Some(3));
+        assert_eq!(encode_response(&Response::Status(waiting)), vec![0, 0, 0, 14, 2, 3, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 7]);
+        let rejected = StatusReport::new(OrderStatus::Rejected(7, RejectReason::NotOwner), None);
+        assert_eq!(encode_response(&Response::Status(rejected)), vec![0, 0, 0, 15, 2, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 14]);
+        assert_eq!(encode_response(&Response::Authenticated(1)), vec![0, 0, 0, 6, 2, 9, 0, 0, 0, 1]);
+        assert_eq!(encode_response(&Response::AuthRejected("no")), vec![0, 0, 0, 4, 2, 10, b'n', b'o']);
+
+        let reason = "x".repeat(300);
+        assert_eq!(encode_response(&Response::AuthRejected(&reason)).len(), LENGTH_SIZE + HEADER_SIZE + MAX_REASON_LENGTH);
+    }
+
+    #[test]
+    fn test_bad_messages_are_rejected() {
+        let with_header = |version: u8, response_type: u8, body: &[u8]| -> Vec<u8> {
+            let mut data = vec![version, response_type];
+            data.extend_from_slice(body);
+            data
+        };
+
+        // statuses start with their client order id
+        let status_body = |fields: &[u8]| -> Vec<u8> {
+            let mut body = vec![0; CLIENT_ORDER_ID_SIZE];
+            body.extend_from_slice(fields);
+            body
+        };
+
+        assert_eq!(decode_response(&with_header(1, WAITING, &status_body(&[0, 0, 0, 7]))), Err(DecodeError::UnknownVersion(1)));
+        assert_eq!(decode_response(&with_header(2, 42, &status_body(&[0, 0, 0, 7]))), Err(DecodeError::UnknownType(42)));
+        assert_eq!(decode_response(&with_header(2, WAITING, &status_body(&[0, 0, 0, 7, 0]))), Err(DecodeError::WrongLength));
+        assert_eq!(decode_response(&with_header(2, WAITING, &[0, 0, 0, 7])), Err(DecodeError::WrongLength));
+        assert_eq!(
+            decode_response(&with_header(2, REJECTED, &status_body(&[0, 0, 0, 7, 99]))),
+            Err(DecodeError::UnknownRejectCode(99))
+        );
+        assert_eq!(decode_response(&with_header(2, AUTH_REJECTED, &[0xff])), Err(DecodeError::InvalidReason));
+        assert_eq!(decode_response(&[2]), Err(DecodeError::TooShort));
+    }
+}
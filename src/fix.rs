@@ -0,0 +1,426 @@
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt, str};
+
+// tag=value encoding of FIX 4.4 messages, used by the FIX gateway.
+// every field is `tag=value` followed by SOH, and every message is framed by
+// BeginString (8) and BodyLength (9) at the front and CheckSum (10) at the back
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 1;
+// "10=" + three digits + SOH
+const CHECKSUM_FIELD_LENGTH: usize = 7;
+
+pub mod tags {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const STOP_PX: u32 = 99;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+}
+
+pub mod msg_types {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+    pub const ORDER_STATUS_REQUEST: &str = "H";
+}
+
+/// the ways a FIX message can fail to decode
+/// * Bad Begin String - the message doesn't start with `8=FIX.4.4`
+/// * Bad Body Length - BodyLength is missing or isn't a number, or the body doesn't end where it says
+/// * Bad Checksum - CheckSum is missing or doesn't match the message
+/// * Malformed Field - a field isn't `tag=value` with a numeric tag and a value
+/// * Missing Msg Type - the first field of the body isn't MsgType
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixError {
+    BadBeginString,
+    BadBodyLength,
+    BadChecksum,
+    MalformedField,
+    MissingMsgType,
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match *self {
+            FixError::BadBeginString => "message doesn't start with BeginString FIX.4.4",
+            FixError::BadBodyLength => "BodyLength is missing or wrong",
+            FixError::BadChecksum => "CheckSum is missing or wrong",
+            FixError::MalformedField => "field isn't tag=value",
+            FixError::MissingMsgType => "body doesn't start with MsgType",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl Error for FixError {}
+
+/// a single FIX message: its type and every field after MsgType, in order.
+/// received messages keep their header fields (SenderCompID, MsgSeqNum, ...) in `fields`,
+/// messages being sent leave them out and have them added by `encode`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixMessage {
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> FixMessage {
+        FixMessage {
+            msg_type: msg_type.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// adds a field to the end of the message
+    pub fn with<T: ToString>(mut self, tag: u32, value: T) -> FixMessage {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    /// returns the value of the first field with the given tag, if there is one
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|field| field.0 == tag).map(|field| field.1.as_str())
+    }
+
+    /// returns whether a Y/N field is set to Y
+    pub fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    /// encodes the message with its standard header and trailer
+    ///
+    /// # Parameters
+    ///
+    /// * `sender_comp_id` - who is sending the message
+    /// * `target_comp_id` - who the message is for
+    /// * `seq_num` - the MsgSeqNum of the message
+    /// * `sending_time` - the SendingTime of the message, as formatted by `timestamp`
+    /// * `orig_sending_time` - when resending a message, the time it was first sent. marks the message as a possible duplicate
+    pub fn encode(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: u64, sending_time: &str, orig_sending_time: Option<&str>) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_field(&mut body, tags::MSG_TYPE, &self.msg_type);
+        push_field(&mut body, tags::SENDER_COMP_ID, sender_comp_id);
+        push_field(&mut body, tags::TARGET_COMP_ID, target_comp_id);
+        push_field(&mut body, tags::MSG_SEQ_NUM, &seq_num.to_string());
+        if orig_sending_time.is_some() {
+            push_field(&mut body, tags::POSS_DUP_FLAG, "Y");
+        }
+        push_field(&mut body, tags::SENDING_TIME, sending_time);
+        if let Some(orig_sending_time) = orig_sending_time {
+            push_field(&mut body, tags::ORIG_SENDING_TIME, orig_sending_time);
+        }
+        for (tag, value) in &self.fields {
+            push_field(&mut body, *tag, value);
+        }
+
+        let mut message = Vec::with_capacity(body.len() + 32);
+        push_field(&mut message, 8, BEGIN_STRING);
+        push_field(&mut message, 9, &body.len().to_string());
+        message.extend_from_slice(&body);
+        let checksum = checksum(&message);
+        push_field(&mut message, 10, &format!("{:03}", checksum));
+        message
+    }
+}
+
+fn push_field(buffer: &mut Vec<u8>, tag: u32, value: &str) {
+    buffer.extend_from_slice(tag.to_string().as_bytes());
+    buffer.push(b'=');
+    buffer.extend_from_slice(value.as_bytes());
+    buffer.push(SOH);
+}
+
+/// the FIX checksum: the sum of every byte before the CheckSum field, modulo 256
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// reads a `tag=value` field starting at `start`, returning its value and where the next field starts.
+/// returns None if the field hasn't fully arrived yet
+fn read_field<'a>(data: &'a [u8], start: usize, tag: &[u8]) -> Option<Result<(&'a [u8], usize), FixError>> {
+    let end = match data[start..].iter().position(|&byte| byte == SOH) {
+        Some(offset) => start + offset,
+        None => return None,
+    };
+    let field = &data[start..end];
+    if field.len() <= tag.len() || &field[..tag.len()] != tag {
+        return Some(Err(FixError::MalformedField));
+    }
+    Some(Ok((&field[tag.len()..], end + 1)))
+}
+
+/// decodes the first message in `data`.
+/// returns None if the message hasn't fully arrived yet, and otherwise the decoded message
+/// together with the number of bytes it took up, which should be dropped before decoding the next one
+pub fn decode(data: &[u8]) -> Option<(Result<FixMessage, FixError>, usize)> {
+    let begin_string = format!("8={}", BEGIN_STRING);
+    if data.len() < begin_string.len() + 1 {
+        return if begin_string.as_bytes().starts_with(data) {
+            None
+        } else {
+            Some((Err(FixError::BadBeginString), data.len()))
+        };
+    }
+    if !data.starts_with(begin_string.as_bytes()) || data[begin_string.len()] != SOH {
+        return Some((Err(FixError::BadBeginString), data.len()));
+    }
+
+    let (body_length, body_start) = match read_field(data, begin_string.len() + 1, b"9=") {
+        None => return None,
+        Some(Err(_)) => return Some((Err(FixError::BadBodyLength), data.len())),
+        Some(Ok((value, next))) => {
+            let body_length = str::from_utf8(value).ok().and_then(|value| value.parse::<usize>().ok());
+            match body_length {
+                Some(body_length) => (body_length, next),
+                None => return Some((Err(FixError::BadBodyLength), data.len())),
+            }
+        }
+    };
+
+    // the length comes from the counterparty, so it can be anything up to usize::MAX
+    let body_end = body_start.checked_add(body_length);
+    let message_end = body_end.and_then(|body_end| body_end.checked_add(CHECKSUM_FIELD_LENGTH));
+    let (body_end, message_end) = match (body_end, message_end) {
+        (Some(body_end), Some(message_end)) => (body_end, message_end),
+        _ => return Some((Err(FixError::BadBodyLength), data.len())),
+    };
+    if data.len() < message_end {
+        return None;
+    }
+    if body_length == 0 || data[body_end - 1] != SOH {
+        return Some((Err(FixError::BadBodyLength), data.len()));
+    }
+    let expected_checksum = match read_field(data, body_end, b"10=") {
+        Some(Ok((value, next))) if next == message_end => str::from_utf8(value).ok().and_then(|value| value.parse::<u8>().ok()),
+        _ => None,
+    };
+    if expected_checksum != Some(checksum(&data[..body_end])) {
+        return Some((Err(FixError::BadChecksum), message_end));
+    }
+
+    Some((decode_body(&data[body_start..body_end - 1]), message_end))
+}
+
+/// decodes the fields between BodyLength and CheckSum, without the final SOH
+fn decode_body(body: &[u8]) -> Result<FixMessage, FixError> {
+    let mut fields = Vec::new();
+    for field in body.split(|&byte| byte == SOH) {
+        let field = str::from_utf8(field).map_err(|_| FixError::MalformedField)?;
+        let mut parts = field.splitn(2, '=');
+        let tag = parts.next().and_then(|tag| tag.parse::<u32>().ok());
+        match (tag, parts.next()) {
+            (Some(tag), Some(value)) if !value.is_empty() => fields.push((tag, value.to_string())),
+            _ => return Err(FixError::MalformedField),
+        }
+    }
+
+    match fields.first() {
+        Some(&(tags::MSG_TYPE, _)) => {}
+        _ => return Err(FixError::MissingMsgType),
+    }
+    let msg_type = fields.remove(0).1;
+    Ok(FixMessage {
+        msg_type: msg_type,
+        fields: fields,
+    })
+}
+
+/// turns a FIX price like "100.5" into the exchange's price units, thousandths of a dollar.
+/// returns None for anything that isn't a positive number with at most three decimals
+pub fn parse_price(value: &str) -> Option<u64> {
+    let mut parts = value.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if whole.is_empty() || fraction.len() > 3 || !is_digits(whole) || !is_digits(fraction) {
+        return None;
+    }
+    let whole = whole.parse::<u64>().ok()?;
+    let fraction = format!("{:0<3}", fraction).parse::<u64>().ok()?;
+    whole.checked_mul(1000)?.checked_add(fraction)
+}
+
+/// turns a price in thousandths of a dollar into a FIX price, without trailing zeros
+pub fn format_price(price: u64) -> String {
+    let fraction = format!("{:03}", price % 1000);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (price / 1000).to_string()
+    } else {
+        format!("{}.{}", price / 1000, fraction)
+    }
+}
+
+/// the current time as a FIX UTCTimestamp, YYYYMMDD-HH:MM:SS.sss
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("[ERROR] system clock is before unix epoch");
+    format_timestamp(now.as_secs() * 1000 + now.subsec_millis() as u64)
+}
+
+/// formats milliseconds since the unix epoch as a FIX UTCTimestamp
+fn format_timestamp(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        millis % 1000
+    )
+}
+
+/// turns days since the unix epoch into a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn soh(message: &str) -> Vec<u8> {
+        message.replace('|', "\x01").into_bytes()
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let message = FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, "ping");
+        let encoded = message.encode("EXCH", "CLIENT", 7, "20240102-03:04:05.678", None);
+        let body = "35=1|49=EXCH|56=CLIENT|34=7|52=20240102-03:04:05.678|112=ping|";
+        let prefix = format!("8=FIX.4.4|9={}|{}", body.len(), body);
+        assert!(encoded.starts_with(&soh(&prefix)));
+        assert_eq!(&encoded[encoded.len() - 7..encoded.len() - 4], b"10=");
+
+        let (decoded, used) = decode(&encoded).unwrap();
+        let decoded = decoded.unwrap();
+        assert_eq!(used, encoded.len());
+        assert_eq!(decoded.msg_type, "1");
+        assert_eq!(decoded.get(tags::MSG_SEQ_NUM), Some("7"));
+        assert_eq!(decoded.get(tags::TEST_REQ_ID), Some("ping"));
+        assert!(!decoded.flag(tags::POSS_DUP_FLAG));
+
+        let resent = message.encode("EXCH", "CLIENT", 7, "20240102-03:04:06.000", Some("20240102-03:04:05.678"));
+        let decoded = decode(&resent).unwrap().0.unwrap();
+        assert!(decoded.flag(tags::POSS_DUP_FLAG));
+        assert_eq!(decoded.get(tags::ORIG_SENDING_TIME), Some("20240102-03:04:05.678"));
+    }
+
+    #[test]
+    fn test_partial_and_bad_messages() {
+        let encoded = FixMessage::new(msg_types::HEARTBEAT).encode("EXCH", "CLIENT", 1, "20240102-03:04:05.678", None);
+        for end in 0..encoded.len() {
+            assert!(decode(&encoded[..end]).is_none(), "decoded a message cut off at {}", end);
+        }
+
+        // a second message behind the first is left alone
+        let mut two = encoded.clone();
+        two.extend_from_slice(&encoded);
+        assert_eq!(decode(&two).unwrap().1, encoded.len());
+
+        let mut corrupted = encoded.clone();
+        let last_digit = corrupted.len() - 2;
+        corrupted[last_digit] = if corrupted[last_digit] == b'0' { b'1' } else { b'0' };
+        assert_eq!(decode(&corrupted), Some((Err(FixError::BadChecksum), encoded.len())));
+
+        assert_eq!(decode(b"8=FIX.4.2\x019=5\x01").unwrap().0, Err(FixError::BadBeginString));
+        assert_eq!(decode(b"GET / HTTP/1.1").unwrap().0, Err(FixError::BadBeginString));
+        assert_eq!(decode(b"8=FIX.4.4\x019=x\x01").unwrap().0, Err(FixError::BadBodyLength));
+        let huge = format!("8=FIX.4.4\x019={}\x0135=0\x01", usize::MAX);
+        assert_eq!(decode(huge.as_bytes()).unwrap().0, Err(FixError::BadBodyLength));
+        let huge = format!("8=FIX.4.4\x019={}\x0135=0\x01", usize::MAX - 10);
+        assert_eq!(decode(huge.as_bytes()).unwrap().0, Err(FixError::BadBodyLength));
+
+        let no_msg_type = soh("8=FIX.4.4|9=5|34=1|");
+        let mut no_msg_type = no_msg_type.clone();
+        let sum = checksum(&no_msg_type);
+        no_msg_type.extend_from_slice(&soh(&format!("10={:03}|", sum)));
+        assert_eq!(decode(&no_msg_type).unwrap().0, Err(FixError::MissingMsgType));
+    }
+
+    #[test]
+    fn test_prices() {
+        assert_eq!(parse_price("100"), Some(100000));
+        assert_eq!(parse_price("100.5"), Some(100500));
+        assert_eq!(parse_price("0.001"), Some(1));
+        assert_eq!(parse_price("100.0001"), None);
+        assert_eq!(parse_price("-1"), None);
+        assert_eq!(parse_price(".5"), None);
+        assert_eq!(parse_price("1e3"), None);
+        assert_eq!(format_price(100500), "100.5");
+        assert_eq!(format_price(100000), "100");
+        assert_eq!(format_price(1), "0.001");
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(format_timestamp(0), "19700101-00:00:00.000");
+        assert_eq!(format_timestamp(951_782_400_123), "20000229-00:00:00.123");
+        assert_eq!(format_timestamp(1_704_164_645_678), "20240102-03:04:05.678");
+        assert_eq!(timestamp().len(), 21);
+    }
+}
@@ -0,0 +1,1377 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{cmp, mem, thread};
+
+use super::{ACCOUNTS, SYMBOLS};
+use fix::{self, msg_types, tags, FixError, FixMessage};
+use types::*;
+
+// FIX 4.4 order entry. a session logs on with the username and password of an account,
+// after which orders, cancels, replaces and status requests are turned into commands
+// for the matching engine, and their statuses are sent back as execution reports
+
+// how long to wait for data before checking on orders and heartbeats
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long a new connection has to send its Logon
+const LOGON_TIMEOUT: Duration = Duration::from_secs(30);
+// largest HeartBtInt a session can ask for, in seconds
+const MAX_HEARTBEAT_INTERVAL: u64 = 300;
+// largest message a counterparty can send, anything longer closes the connection
+const MAX_MESSAGE_SIZE: usize = 8192;
+// OrderID sent for orders the matching engine never saw
+const NO_ORDER_ID: &str = "NONE";
+
+lazy_static! {
+    static ref ACTIVE_SESSIONS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+pub struct FixGateway {
+    ip_addr: &'static str,
+    port: u32,
+    comp_id: &'static str,
+    store_dir: &'static str,
+    order_channel: Sender<Cmd>,
+}
+
+impl FixGateway {
+    /// creates a new FixGateway
+    ///
+    /// # Parameters
+    ///
+    /// * `ip_addr` - the address to listen on
+    /// * `port` - the port to listen on
+    /// * `comp_id` - the CompID of the exchange, which counterparties send as their TargetCompID
+    /// * `store_dir` - the directory the sequence numbers of every session are kept in
+    /// * `order_channel` - the channel commands are sent to the matching engine on
+    pub fn new(ip_addr: &'static str, port: u32, comp_id: &'static str, store_dir: &'static str, order_channel: Sender<Cmd>) -> Self {
+        FixGateway {
+            ip_addr: ip_addr,
+            port: port,
+            comp_id: comp_id,
+            store_dir: store_dir,
+            order_channel: order_channel,
+        }
+    }
+
+    pub fn run(&self) {
+        let listener = TcpListener::bind(format!("{}:{}", self.ip_addr, self.port))
+            .expect("[ERROR] couldn't start FIX gateway");
+        println!("[INFO] FIX gateway started on {}:{}", self.ip_addr, self.port);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    let addr = match s.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            println!("[ERROR] FIX connection went away: {}", e);
+                            continue;
+                        }
+                    };
+                    println!("[INFO] new FIX connection: {}", addr);
+
+                    let comp_id = self.comp_id;
+                    let store_dir = PathBuf::from(self.store_dir);
+                    let order_channel = self.order_channel.clone();
+                    thread::Builder::new()
+                        .name(format!("fix {}", addr))
+                        .spawn(move || {
+                            if let Some(mut session) = FixSession::logon(s, comp_id, store_dir, order_channel) {
+                                session.run();
+                            }
+                        })
+                        .expect("[ERROR] failed to create FIX session thread");
+                }
+                Err(e) => {
+                    println!("[ERROR] FIX connection failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// a tcp stream together with the bytes read from it that don't make up a whole message yet
+struct Connection {
+    stream: TcpStream,
+    inbound: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Connection {
+            stream: stream,
+            inbound: Vec::new(),
+        })
+    }
+
+    /// returns the next whole message, or None if nothing arrives within the poll interval.
+    /// garbled messages are skipped, and messages that can't be framed close the connection
+    fn next_message(&mut self) -> io::Result<Option<FixMessage>> {
+        loop {
+            if let Some((result, used)) = fix::decode(&self.inbound) {
+                self.inbound.drain(..used);
+                match result {
+                    Ok(message) => return Ok(Some(message)),
+                    Err(e @ FixError::BadBeginString) | Err(e @ FixError::BadBodyLength) => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                    }
+                    Err(e) => {
+                        println!("[ERROR] ignoring garbled FIX message: {}", e);
+                        continue;
+                    }
+                }
+            }
+            if self.inbound.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "FIX message is too long"));
+            }
+
+            let mut data = [0; 4096];
+            match self.stream.read(&mut data) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(size) => self.inbound.extend_from_slice(&data[..size]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data)?;
+        self.stream.flush()
+    }
+}
+
+/// the sequence numbers of a session, saved to a file after every change so a
+/// session picks up where it left off when it logs on again, even after a restart
+struct SeqNumStore {
+    path: PathBuf,
+    next_incoming: u64,
+    next_outgoing: u64,
+}
+
+impl SeqNumStore {
+    /// loads the sequence numbers of the session between `comp_id` and `counterparty`,
+    /// starting both at 1 if the session has never logged on before
+    fn open(dir: &Path, comp_id: &str, counterparty: &str) -> io::Result<SeqNumStore> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}.seqnums", comp_id, counterparty));
+        let (next_incoming, next_outgoing) = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let numbers: Vec<u64> = contents.split_whitespace().filter_map(|number| number.parse().ok()).collect();
+                match numbers.as_slice() {
+                    [next_incoming, next_outgoing] => (*next_incoming, *next_outgoing),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("corrupt sequence number file {:?}", path))),
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (1, 1),
+            Err(e) => return Err(e),
+        };
+        Ok(SeqNumStore {
+            path: path,
+            next_incoming: next_incoming,
+            next_outgoing: next_outgoing,
+        })
+    }
+
+    /// writes the sequence numbers next to the file and moves them over it, so the file is never half written
+    fn save(&self) -> io::Result<()> {
+        let temp_path = self.path.with_extension("seqnums.tmp");
+        fs::write(&temp_path, format!("{} {}\n", self.next_incoming, self.next_outgoing))?;
+        fs::rename(&temp_path, &self.path)
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.next_incoming = 1;
+        self.next_outgoing = 1;
+        self.save()
+    }
+}
+
+/// marks a session as logged on for as long as it's alive, so the same session can't log on twice at once
+struct ActiveSession(String);
+
+impl ActiveSession {
+    fn claim(comp_id: &str, counterparty: &str) -> Option<ActiveSession> {
+        let key = format!("{}-{}", comp_id, counterparty);
+        let mut active = ACTIVE_SESSIONS.lock().expect("[ERROR] active FIX sessions lock was poisoned");
+        if active.insert(key.clone()) {
+            Some(ActiveSession(key))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE_SESSIONS.lock() {
+            active.remove(&self.0);
+        }
+    }
+}
+
+/// where an order is in its life, as far as its execution reports go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderState {
+    // sent to the matching engine, which hasn't answered yet
+    PendingNew,
+    // accepted, whether or not it has traded since
+    New,
+    Canceled,
+    Rejected,
+}
+
+/// an order sent on the session, with what has been reported about it so far
+struct FixOrder {
+    // the ClOrdID the order currently goes by, which changes with every cancel or replace
+    cl_ord_id: String,
+    order_id: Option<u32>,
+    symbol: String,
+    side: OrderSide,
+    quantity: u64,
+    cumulative_quantity: u64,
+    average_price: u64,
+    state: OrderState,
+    // every status of the order is sent on its own channel, so it is never mistaken for another order's
+    receiver: Option<Receiver<StatusReport>>,
+}
+
+impl FixOrder {
+    fn leaves_quantity(&self) -> u64 {
+        match self.state {
+            OrderState::Canceled | OrderState::Rejected => 0,
+            _ => self.quantity.saturating_sub(self.cumulative_quantity),
+        }
+    }
+
+    /// the OrdStatus of the order
+    fn ord_status(&self) -> &'static str {
+        match self.state {
+            OrderState::Rejected => "8",
+            OrderState::Canceled => "4",
+            OrderState::PendingNew => "A",
+            OrderState::New if self.cumulative_quantity >= self.quantity => "2",
+            OrderState::New if self.cumulative_quantity > 0 => "1",
+            OrderState::New => "0",
+        }
+    }
+
+    fn order_id(&self) -> String {
+        self.order_id.map_or(NO_ORDER_ID.to_string(), |order_id| order_id.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestKind {
+    Cancel,
+    Replace,
+    Status,
+}
+
+/// a cancel, replace or status request waiting on its answer from the matching engine
+struct PendingRequest {
+    kind: RequestKind,
+    cl_ord_id: String,
+    // index of the order in `FixSession::orders`
+    order: usize,
+    receiver: Receiver<StatusReport>,
+}
+
+/// a logged on FIX session with a single counterparty
+struct FixSession {
+    _active: ActiveSession,
+    connection: Connection,
+    order_channel: Sender<Cmd>,
+    account_id: u32,
+    // our CompID, sent as SenderCompID
+    comp_id: &'static str,
+    // their CompID, sent as TargetCompID
+    counterparty: String,
+    seq_nums: SeqNumStore,
+    // None when the counterparty asked for no heartbeats
+    heartbeat_interval: Option<Duration>,
+    last_received: Instant,
+    last_sent: Instant,
+    test_request_sent: bool,
+    // the highest MsgSeqNum received ahead of a gap we asked to have resent, until the gap is filled
+    resend_target: Option<u64>,
+    // application messages sent on this connection with their SendingTime, by MsgSeqNum, for answering resend requests
+    sent: BTreeMap<u64, (FixMessage, String)>,
+    orders: Vec<FixOrder>,
+    cl_ord_ids: HashMap<String, usize>,
+    requests: Vec<PendingRequest>,
+    logged_out: bool,
+}
+
+impl FixSession {
+    /// waits for the Logon a connection has to start with and checks it against the loaded accounts.
+    /// returns the logged on session, or None if the connection was closed instead
+    fn logon(stream: TcpStream, comp_id: &'static str, store_dir: PathBuf, order_channel: Sender<Cmd>) -> Option<FixSession> {
+        let mut connection = match Connection::new(stream) {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("[ERROR] failed to set up FIX connection: {}", e);
+                return None;
+            }
+        };
+
+        let started = Instant::now();
+        let message = loop {
+            match connection.next_message() {
+                Ok(Some(message)) => break message,
+                Ok(None) if started.elapsed() < LOGON_TIMEOUT => continue,
+                Ok(None) => {
+                    println!("[ERROR] FIX connection didn't log on in time");
+                    return None;
+                }
+                Err(e) => {
+                    println!("[ERROR] FIX connection closed before logging on: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        // anything but a well formed Logon is dropped without an answer
+        let counterparty = match message.get(tags::SENDER_COMP_ID) {
+            Some(counterparty) if message.msg_type == msg_types::LOGON && is_valid_comp_id(counterparty) => counterparty.to_string(),
+            _ => {
+                println!("[ERROR] FIX connection didn't start with a valid Logon");
+                return None;
+            }
+        };
+        if message.get(tags::TARGET_COMP_ID) != Some(comp_id) {
+            println!("[ERROR] FIX Logon from {} is for another TargetCompID", counterparty);
+            return None;
+        }
+        let active = match ActiveSession::claim(comp_id, &counterparty) {
+            Some(active) => active,
+            None => {
+                println!("[ERROR] FIX session {} is already logged on", counterparty);
+                return None;
+            }
+        };
+        let seq_nums = match SeqNumStore::open(&store_dir, comp_id, &counterparty) {
+            Ok(seq_nums) => seq_nums,
+            Err(e) => {
+                println!("[ERROR] couldn't load sequence numbers of FIX session {}: {}", counterparty, e);
+                return None;
+            }
+        };
+
+        let now = Instant::now();
+        let mut session = FixSession {
+            _active: active,
+            connection: connection,
+            order_channel: order_channel,
+            account_id: 0,
+            comp_id: comp_id,
+            counterparty: counterparty,
+            seq_nums: seq_nums,
+            heartbeat_interval: None,
+            last_received: now,
+            last_sent: now,
+            test_request_sent: false,
+            resend_target: None,
+            sent: BTreeMap::new(),
+            orders: Vec::new(),
+            cl_ord_ids: HashMap::new(),
+            requests: Vec::new(),
+            logged_out: false,
+        };
+        match session.on_logon(&message) {
+            Ok(true) => Some(session),
+            Ok(false) => {
+                session.close();
+                None
+            }
+            Err(e) => {
+                println!("[ERROR] FIX Logon from {} failed: {}", session.counterparty, e);
+                session.close();
+                None
+            }
+        }
+    }
+
+    /// checks the Logon and answers it. returns whether the session is now logged on
+    fn on_logon(&mut self, message: &FixMessage) -> io::Result<bool> {
+        let heartbeat_interval = match message.get(tags::HEART_BT_INT).and_then(|interval| interval.parse::<u64>().ok()) {
+            Some(interval) if interval <= MAX_HEARTBEAT_INTERVAL => interval,
+            _ => {
+                self.logout("HeartBtInt is missing or too large")?;
+                return Ok(false);
+            }
+        };
+        let reset = message.flag(tags::RESET_SEQ_NUM_FLAG);
+        if reset {
+            self.seq_nums.reset()?;
+        }
+        let seq_num = match message.get(tags::MSG_SEQ_NUM).and_then(|seq_num| seq_num.parse::<u64>().ok()) {
+            Some(seq_num) => seq_num,
+            None => {
+                self.logout("MsgSeqNum is missing")?;
+                return Ok(false);
+            }
+        };
+        if seq_num < self.seq_nums.next_incoming {
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", self.seq_nums.next_incoming, seq_num);
+            self.logout(&text)?;
+            return Ok(false);
+        }
+
+        let account = match (message.get(tags::USERNAME), message.get(tags::PASSWORD)) {
+            (Some(username), Some(password)) => ACCOUNTS.get(username).filter(|account| account.check_password(password)),
+            _ => None,
+        };
+        self.account_id = match account {
+            Some(account) => account.id(),
+            None => {
+                println!("[ERROR] FIX Logon from {} has invalid credentials", self.counterparty);
+                self.logout("invalid username or password")?;
+                return Ok(false);
+            }
+        };
+
+        if heartbeat_interval > 0 {
+            self.heartbeat_interval = Some(Duration::from_secs(heartbeat_interval));
+        }
+        let mut response = FixMessage::new(msg_types::LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, heartbeat_interval);
+        if reset {
+            response = response.with(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        self.send(response)?;
+        println!("[INFO] FIX session {} logged on with account id {}", self.counterparty, self.account_id);
+
+        if seq_num == self.seq_nums.next_incoming {
+            self.seq_nums.next_incoming += 1;
+            self.seq_nums.save()?;
+        } else {
+            self.request_resend(seq_num)?;
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self) {
+        if let Err(e) = self.serve() {
+            println!("[ERROR] FIX session {} ended: {}", self.counterparty, e);
+        }
+        self.close();
+        println!("[INFO] FIX session {} logged out", self.counterparty);
+    }
+
+    fn serve(&mut self) -> io::Result<()> {
+        while !self.logged_out {
+            if let Some(message) = self.connection.next_message()? {
+                self.handle(message)?;
+            }
+            self.poll_responses()?;
+            self.check_heartbeats()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        let _ = self.connection.stream.shutdown(Shutdown::Both);
+    }
+
+    /// checks the sequence number of a message and acts on it if it's the one we expect next
+    fn handle(&mut self, message: FixMessage) -> io::Result<()> {
+        self.last_received = Instant::now();
+        self.test_request_sent = false;
+
+        let seq_num = match message.get(tags::MSG_SEQ_NUM).and_then(|seq_num| seq_num.parse::<u64>().ok()) {
+            Some(seq_num) => seq_num,
+            None => return self.logout("MsgSeqNum is missing"),
+        };
+        if message.get(tags::SENDER_COMP_ID) != Some(self.counterparty.as_str()) || message.get(tags::TARGET_COMP_ID) != Some(self.comp_id) {
+            return self.logout("CompID problem");
+        }
+
+        // a SequenceReset that isn't filling a gap moves the sequence number no matter what it is
+        if message.msg_type == msg_types::SEQUENCE_RESET && !message.flag(tags::GAP_FILL_FLAG) {
+            return self.on_sequence_reset(&message);
+        }
+        let expected = self.seq_nums.next_incoming;
+        if seq_num < expected {
+            if message.flag(tags::POSS_DUP_FLAG) {
+                // a resend of a message we already have
+                return Ok(());
+            }
+            return self.logout(&format!("MsgSeqNum too low, expecting {} but received {}", expected, seq_num));
+        }
+        if seq_num > expected {
+            // dropped, it comes again with the rest of the gap
+            return self.request_resend(seq_num);
+        }
+
+        self.seq_nums.next_incoming += 1;
+        match message.msg_type.as_str() {
+            msg_types::HEARTBEAT => {}
+            msg_types::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_types::HEARTBEAT);
+                if let Some(test_req_id) = message.get(tags::TEST_REQ_ID) {
+                    heartbeat = heartbeat.with(tags::TEST_REQ_ID, test_req_id);
+                }
+                self.send(heartbeat)?;
+            }
+            msg_types::RESEND_REQUEST => self.on_resend_request(&message)?,
+            msg_types::REJECT => println!("[ERROR] FIX session {} rejected message {:?}", self.counterparty, message.get(tags::REF_SEQ_NUM)),
+            msg_types::SEQUENCE_RESET => self.on_sequence_reset(&message)?,
+            msg_types::LOGOUT => {
+                self.send(FixMessage::new(msg_types::LOGOUT))?;
+                self.logged_out = true;
+            }
+            msg_types::NEW_ORDER_SINGLE => self.on_new_order(&message)?,
+            msg_types::ORDER_CANCEL_REQUEST => self.on_order_request(&message, RequestKind::Cancel)?,
+            msg_types::ORDER_CANCEL_REPLACE_REQUEST => self.on_order_request(&message, RequestKind::Replace)?,
+            msg_types::ORDER_STATUS_REQUEST => self.on_order_request(&message, RequestKind::Status)?,
+            _ => self.reject(&message, None, 11, "unsupported MsgType")?,
+        }
+
+        if self.resend_target.is_some_and(|target| self.seq_nums.next_incoming > target) {
+            self.resend_target = None;
+        }
+        self.seq_nums.save()
+    }
+
+    /// asks for everything from the next expected sequence number on, unless we already have
+    fn request_resend(&mut self, seq_num: u64) -> io::Result<()> {
+        if self.resend_target.is_none() {
+            let request = FixMessage::new(msg_types::RESEND_REQUEST)
+                .with(tags::BEGIN_SEQ_NO, self.seq_nums.next_incoming)
+                .with(tags::END_SEQ_NO, 0);
+            self.send(request)?;
+        }
+        self.resend_target = Some(cmp::max(self.resend_target.unwrap_or(0), seq_num));
+        Ok(())
+    }
+
+    fn on_sequence_reset(&mut self, message: &FixMessage) -> io::Result<()> {
+        let new_seq_no = match message.get(tags::NEW_SEQ_NO).and_then(|seq_num| seq_num.parse::<u64>().ok()) {
+            Some(new_seq_no) => new_seq_no,
+            None => return self.reject(message, Some(tags::NEW_SEQ_NO), 1, "NewSeqNo is missing"),
+        };
+        // a reset can only move the sequence number forward, even in reset mode
+        if new_seq_no < self.seq_nums.next_incoming {
+            return self.reject(message, Some(tags::NEW_SEQ_NO), 5, "NewSeqNo is lower than the expected MsgSeqNum");
+        }
+        self.seq_nums.next_incoming = new_seq_no;
+        self.seq_nums.save()
+    }
+
+    /// resends the application messages the counterparty asked for, and gap fills everything else
+    fn on_resend_request(&mut self, message: &FixMessage) -> io::Result<()> {
+        let (begin, end) = match (message.get(tags::BEGIN_SEQ_NO), message.get(tags::END_SEQ_NO)) {
+            (Some(begin), Some(end)) => match (begin.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(begin), Ok(end)) => (cmp::max(begin, 1), end),
+                _ => return self.reject(message, None, 6, "BeginSeqNo or EndSeqNo isn't a number"),
+            },
+            (None, _) => return self.reject(message, Some(tags::BEGIN_SEQ_NO), 1, "BeginSeqNo is missing"),
+            (_, None) => return self.reject(message, Some(tags::END_SEQ_NO), 1, "EndSeqNo is missing"),
+        };
+        let last_sent = self.seq_nums.next_outgoing - 1;
+        let end = if end == 0 { last_sent } else { cmp::min(end, last_sent) };
+
+        let mut gap_start = None;
+        for seq_num in begin..=end {
+            let resend = self.sent.get(&seq_num).cloned();
+            match resend {
+                Some((message, sending_time)) => {
+                    if let Some(gap_start) = gap_start.take() {
+                        self.send_gap_fill(gap_start, seq_num)?;
+                    }
+                    let data = message.encode(self.comp_id, &self.counterparty, seq_num, &fix::timestamp(), Some(&sending_time));
+                    self.connection.write(&data)?;
+                }
+                None => {
+                    gap_start = gap_start.or(Some(seq_num));
+                }
+            }
+        }
+        if let Some(gap_start) = gap_start {
+            self.send_gap_fill(gap_start, end + 1)?;
+        }
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// tells the counterparty to skip the messages from `seq_num` up to `new_seq_no`
+    fn send_gap_fill(&mut self, seq_num: u64, new_seq_no: u64) -> io::Result<()> {
+        let now = fix::timestamp();
+        let gap_fill = FixMessage::new(msg_types::SEQUENCE_RESET)
+            .with(tags::GAP_FILL_FLAG, "Y")
+            .with(tags::NEW_SEQ_NO, new_seq_no);
+        let data = gap_fill.encode(self.comp_id, &self.counterparty, seq_num, &now, Some(&now));
+        self.connection.write(&data)
+    }
+
+    /// sends heartbeats when we've been quiet, and checks the counterparty hasn't gone quiet
+    fn check_heartbeats(&mut self) -> io::Result<()> {
+        let interval = match self.heartbeat_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        if self.last_sent.elapsed() >= interval {
+            self.send(FixMessage::new(msg_types::HEARTBEAT))?;
+        }
+
+        let silence = self.last_received.elapsed();
+        if self.test_request_sent && silence >= interval * 2 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "counterparty stopped sending heartbeats"));
+        }
+        if !self.test_request_sent && silence >= interval + interval / 5 {
+            self.send(FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, fix::timestamp()))?;
+            self.test_request_sent = true;
+        }
+        Ok(())
+    }
+
+    /// sends a message with the next outgoing sequence number
+    fn send(&mut self, message: FixMessage) -> io::Result<()> {
+        let seq_num = self.seq_nums.next_outgoing;
+        let sending_time = fix::timestamp();
+        let data = message.encode(self.comp_id, &self.counterparty, seq_num, &sending_time, None);
+        self.seq_nums.next_outgoing += 1;
+        self.seq_nums.save()?;
+
+        // session messages are gap filled instead of resent
+        if message.msg_type == msg_types::EXECUTION_REPORT || message.msg_type == msg_types::ORDER_CANCEL_REJECT {
+            self.sent.insert(seq_num, (message, sending_time));
+        }
+        self.connection.write(&data)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// sends a Logout and ends the session
+    fn logout(&mut self, text: &str) -> io::Result<()> {
+        println!("[ERROR] logging out FIX session {}: {}", self.counterparty, text);
+        self.logged_out = true;
+        self.send(FixMessage::new(msg_types::LOGOUT).with(tags::TEXT, text))
+    }
+
+    /// rejects a message that can't be processed at the session level
+    ///
+    /// # Parameters
+    ///
+    /// * `message` - the message being rejected
+    /// * `tag` - the field that was wrong, if it was a single field
+    /// * `reason` - the SessionRejectReason
+    /// * `text` - why the message was rejected
+    fn reject(&mut self, message: &FixMessage, tag: Option<u32>, reason: u32, text: &str) -> io::Result<()> {
+        let mut reject = FixMessage::new(msg_types::REJECT).with(tags::REF_SEQ_NUM, message.get(tags::MSG_SEQ_NUM).unwrap_or("0"));
+        if let Some(tag) = tag {
+            reject = reject.with(tags::REF_TAG_ID, tag);
+        }
+        self.send(reject.with(tags::SESSION_REJECT_REASON, reason).with(tags::TEXT, text))
+    }
+
+    /// rejects a message missing one of the given tags, returning whether it did
+    fn reject_missing_tags(&mut self, message: &FixMessage, required: &[u32]) -> io::Result<bool> {
+        match required.iter().find(|&&tag| message.get(tag).is_none()) {
+            Some(&tag) => {
+                self.reject(message, Some(tag), 1, "required tag missing")?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn on_new_order(&mut self, message: &FixMessage) -> io::Result<()> {
+        let required = [tags::CL_ORD_ID, tags::SYMBOL, tags::SIDE, tags::ORDER_QTY, tags::ORD_TYPE];
+        if self.reject_missing_tags(message, &required)? {
+            return Ok(());
+        }
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default().to_string();
+        if self.cl_ord_ids.contains_key(&cl_ord_id) {
+            let report = rejection_report(message, "8", self.next_exec_id(None), "duplicate ClOrdID");
+            return self.send(report);
+        }
+
+        let (order_info, order) = match fix_to_order(message, self.account_id) {
+            Ok(order) => order,
+            Err(reason) => {
+                println!("[ERROR] rejected FIX order from account {}: {}", self.account_id, reason);
+                let report = rejection_report(message, "8", self.next_exec_id(None), reason.message());
+                return self.send(report);
+            }
+        };
+        self.cl_ord_ids.insert(cl_ord_id, self.orders.len());
+        self.orders.push(order);
+        self.send_cmd(Cmd::Execute(order_info))
+    }
+
+    /// handles an OrderCancelRequest, OrderCancelReplaceRequest or OrderStatusRequest,
+    /// which find their order by OrigClOrdID (or ClOrdID for status requests) or OrderID
+    fn on_order_request(&mut self, message: &FixMessage, kind: RequestKind) -> io::Result<()> {
+        let required: &[u32] = match kind {
+            RequestKind::Status => &[tags::CL_ORD_ID],
+            _ => &[tags::CL_ORD_ID, tags::ORIG_CL_ORD_ID],
+        };
+        if self.reject_missing_tags(message, required)? {
+            return Ok(());
+        }
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default().to_string();
+        let orig_cl_ord_id = match kind {
+            RequestKind::Status => cl_ord_id.clone(),
+            _ => message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default().to_string(),
+        };
+
+        let order_id_tag = message.get(tags::ORDER_ID).and_then(|order_id| order_id.parse::<u32>().ok());
+        let index = self
+            .cl_ord_ids
+            .get(&orig_cl_ord_id)
+            .cloned()
+            .or_else(|| order_id_tag.and_then(|order_id| self.orders.iter().position(|order| order.order_id == Some(order_id))));
+        let (index, order_id) = match index.map(|index| (index, self.orders[index].order_id)) {
+            Some((index, Some(order_id))) => (index, order_id),
+            Some((index, None)) => return self.reject_request(message, kind, Some(index), "order hasn't been accepted yet"),
+            None => return self.reject_request(message, kind, None, "unknown order"),
+        };
+        if kind != RequestKind::Status && self.cl_ord_ids.contains_key(&cl_ord_id) {
+            return self.reject_request(message, kind, Some(index), "duplicate ClOrdID");
+        }
+
+        let (sender, receiver) = channel();
+        let cmd = match kind {
+            RequestKind::Cancel => Cmd::Cancel(CancelInfo::new(self.account_id, OrderRef::Exchange(order_id), sender)),
+            RequestKind::Status => Cmd::Status(StatusInfo::new(self.account_id, OrderRef::Exchange(order_id), sender)),
+            RequestKind::Replace => match fix_to_replace(message) {
+                Ok((price, quantity)) => Cmd::Replace(ReplaceInfo::new(self.account_id, order_id, price, quantity, sender)),
+                Err(reason) => return self.reject_request(message, kind, Some(index), reason.message()),
+            },
+        };
+        self.requests.push(PendingRequest {
+            kind: kind,
+            cl_ord_id: cl_ord_id,
+            order: index,
+            receiver: receiver,
+        });
+        self.send_cmd(cmd)
+    }
+
+    /// answers a request that never made it to the matching engine: an OrderCancelReject for
+    /// cancels and replaces, and an execution report for status requests
+    fn reject_request(&mut self, message: &FixMessage, kind: RequestKind, order: Option<usize>, text: &str) -> io::Result<()> {
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default().to_string();
+        match (kind, order) {
+            (RequestKind::Status, Some(index)) => {
+                let report = self.execution_report(index, "I", None).with(tags::TEXT, text);
+                self.send(report)
+            }
+            (RequestKind::Status, None) => {
+                let report = rejection_report(message, "I", self.next_exec_id(None), text);
+                self.send(report)
+            }
+            (_, order) => {
+                let (order_id, orig_cl_ord_id, ord_status) = match order {
+                    Some(index) => {
+                        let order = &self.orders[index];
+                        (order.order_id(), order.cl_ord_id.clone(), order.ord_status())
+                    }
+                    None => (NO_ORDER_ID.to_string(), message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default().to_string(), "8"),
+                };
+                let reject = cancel_reject(kind, order_id, cl_ord_id, orig_cl_ord_id, ord_status, order.is_none(), text);
+                self.send(reject)
+            }
+        }
+    }
+
+    fn send_cmd(&mut self, cmd: Cmd) -> io::Result<()> {
+        self.order_channel
+            .send(cmd)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "matching engine has stopped"))
+    }
+
+    /// turns everything the matching engine has sent back since the last poll into messages.
+    /// requests go first, since the engine answers a request before sending updates it caused
+    fn poll_responses(&mut self) -> io::Result<()> {
+        let mut index = 0;
+        while index < self.requests.len() {
+            match self.requests[index].receiver.try_recv() {
+                Ok(report) => {
+                    let request = self.requests.remove(index);
+                    self.on_request_status(&request, report.status)?;
+                }
+                Err(TryRecvError::Empty) => index += 1,
+                Err(TryRecvError::Disconnected) => {
+                    self.requests.remove(index);
+                }
+            }
+        }
+
+        for index in 0..self.orders.len() {
+            loop {
+                let status = match self.orders[index].receiver {
+                    Some(ref receiver) => receiver.try_recv(),
+                    None => break,
+                };
+                match status {
+                    Ok(report) => self.on_order_status(index, report.status)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.orders[index].receiver = None;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// reports a status sent on an order's own channel
+    fn on_order_status(&mut self, index: usize, status: OrderStatus) -> io::Result<()> {
+        let state = self.orders[index].state;
+        match status {
+            OrderStatus::Rejected(_, reason) if state == OrderState::PendingNew => {
+                self.orders[index].state = OrderState::Rejected;
+                let report = self.execution_report(index, "8", None).with(tags::TEXT, reason.message());
+                self.send(report)
+            }
+            // the first status of an accepted order acknowledges it, before anything else is reported
+            _ if state == OrderState::PendingNew => {
+                self.orders[index].order_id = Some(status.order_id());
+                self.orders[index].state = OrderState::New;
+                let report = self.execution_report(index, "0", None);
+                self.send(report)?;
+                self.on_order_status(index, status)
+            }
+            OrderStatus::Execution(report) => {
+                {
+                    let order = &mut self.orders[index];
+                    order.cumulative_quantity = report.cumulative_quantity;
+                    order.average_price = report.average_price;
+                }
+                let message = self
+                    .execution_report(index, "F", Some(report.execution_id.to_string()))
+                    .with(tags::LAST_PX, fix::format_price(report.price))
+                    .with(tags::LAST_QTY, report.quantity);
+                self.send(message)
+            }
+            OrderStatus::Canceled(_) if state == OrderState::New => {
+                self.orders[index].state = OrderState::Canceled;
+                let report = self.execution_report(index, "4", None);
+                self.send(report)
+            }
+            OrderStatus::SelfTradePrevented(_, quantity) if state == OrderState::New => {
+                let canceled = cmp::min(quantity, self.orders[index].leaves_quantity());
+                self.orders[index].quantity -= canceled;
+                let text = format!("{} shares canceled to prevent a self trade", quantity);
+                let report = self.execution_report(index, "D", None).with(tags::TEXT, text);
+                self.send(report)
+            }
+            // fills are reported by their executions, and everything else has already been reported
+            _ => Ok(()),
+        }
+    }
+
+    /// reports the answer to a cancel, replace or status request
+    fn on_request_status(&mut self, request: &PendingRequest, status: OrderStatus) -> io::Result<()> {
+        let index = request.order;
+        match (request.kind, status) {
+            (RequestKind::Cancel, OrderStatus::Canceled(_)) => {
+                self.orders[index].state = OrderState::Canceled;
+                let orig_cl_ord_id = self.rename_order(index, &request.cl_ord_id);
+                let report = self.execution_report(index, "4", None).with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                self.send(report)
+            }
+            (RequestKind::Replace, OrderStatus::Replaced(_, price, quantity)) => {
+                self.orders[index].quantity = quantity;
+                let orig_cl_ord_id = self.rename_order(index, &request.cl_ord_id);
+                let report = self
+                    .execution_report(index, "5", None)
+                    .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id)
+                    .with(tags::PRICE, fix::format_price(price));
+                self.send(report)
+            }
+            (RequestKind::Status, status) => {
+                let text = {
+                    let order = &mut self.orders[index];
+                    match status {
+                        OrderStatus::Filled(_, cost) => {
+                            order.cumulative_quantity = order.quantity;
+                            order.average_price = cost / order.quantity;
+                        }
+                        OrderStatus::PartiallyFilled(_, filled_quantity, cost) if filled_quantity > 0 => {
+                            order.cumulative_quantity = filled_quantity;
+                            order.average_price = cost / filled_quantity;
+                        }
+                        OrderStatus::Canceled(_) => order.state = OrderState::Canceled,
+                        _ => {}
+                    }
+                    match status {
+                        OrderStatus::Rejected(_, reason) => Some(reason.message()),
+                        _ => None,
+                    }
+                };
+                let mut report = self.execution_report(index, "I", None);
+                if let Some(text) = text {
+                    report = report.with(tags::TEXT, text);
+                }
+                self.send(report)
+            }
+            (kind, status) => {
+                let text = match status {
+                    OrderStatus::Rejected(_, reason) => reason.message(),
+                    _ => RejectReason::OrderNotOpen.message(),
+                };
+                let order = &self.orders[index];
+                let reject = cancel_reject(
+                    kind,
+                    order.order_id(),
+                    request.cl_ord_id.clone(),
+                    order.cl_ord_id.clone(),
+                    order.ord_status(),
+                    false,
+                    text,
+                );
+                self.send(reject)
+            }
+        }
+    }
+
+    /// moves an order to the ClOrdID of a cancel or replace that went through, returning the one it had before
+    fn rename_order(&mut self, index: usize, cl_ord_id: &str) -> String {
+        self.cl_ord_ids.insert(cl_ord_id.to_string(), index);
+        mem::replace(&mut self.orders[index].cl_ord_id, cl_ord_id.to_string())
+    }
+
+    /// returns a new ExecID, the id of the execution for trades and one made up from the order id
+    /// and the MsgSeqNum of the report otherwise, which is never sent twice in a session
+    fn next_exec_id(&self, order_id: Option<u32>) -> String {
+        format!("{}-{}", order_id.unwrap_or(0), self.seq_nums.next_outgoing)
+    }
+
+    /// an execution report for an order, with everything the session knows about it
+    fn execution_report(&self, index: usize, exec_type: &str, exec_id: Option<String>) -> FixMessage {
+        let order = &self.orders[index];
+        let exec_id = exec_id.unwrap_or_else(|| self.next_exec_id(order.order_id));
+        FixMessage::new(msg_types::EXECUTION_REPORT)
+            .with(tags::ORDER_ID, order.order_id())
+            .with(tags::CL_ORD_ID, &order.cl_ord_id)
+            .with(tags::EXEC_ID, exec_id)
+            .with(tags::EXEC_TYPE, exec_type)
+            .with(tags::ORD_STATUS, order.ord_status())
+            .with(tags::SYMBOL, &order.symbol)
+            .with(tags::SIDE, side_to_fix(order.side))
+            .with(tags::ORDER_QTY, order.quantity)
+            .with(tags::LEAVES_QTY, order.leaves_quantity())
+            .with(tags::CUM_QTY, order.cumulative_quantity)
+            .with(tags::AVG_PX, fix::format_price(order.average_price))
+            .with(tags::TRANSACT_TIME, fix::timestamp())
+    }
+}
+
+/// CompIDs name the sequence number files, so they're kept to characters that are safe in a file name
+fn is_valid_comp_id(comp_id: &str) -> bool {
+    !comp_id.is_empty() && comp_id.len() <= 64 && comp_id.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
+fn side_to_fix(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Buy => "1",
+        OrderSide::Sell => "2",
+    }
+}
+
+/// an execution report for a message that never became an order, echoing back what it was sent with
+fn rejection_report(message: &FixMessage, exec_type: &str, exec_id: String, text: &str) -> FixMessage {
+    let mut report = FixMessage::new(msg_types::EXECUTION_REPORT)
+        .with(tags::ORDER_ID, NO_ORDER_ID)
+        .with(tags::CL_ORD_ID, message.get(tags::CL_ORD_ID).unwrap_or_default())
+        .with(tags::EXEC_ID, exec_id)
+        .with(tags::EXEC_TYPE, exec_type)
+        .with(tags::ORD_STATUS, "8");
+    for &tag in &[tags::SYMBOL, tags::SIDE, tags::ORDER_QTY] {
+        if let Some(value) = message.get(tag) {
+            report = report.with(tag, value);
+        }
+    }
+    if exec_type == "8" {
+        report = report.with(tags::ORD_REJ_REASON, 99);
+    }
+    report
+        .with(tags::LEAVES_QTY, 0)
+        .with(tags::CUM_QTY, 0)
+        .with(tags::AVG_PX, 0)
+        .with(tags::TEXT, text)
+}
+
+/// an OrderCancelReject answering a cancel or replace request
+fn cancel_reject(
+    kind: RequestKind,
+    order_id: String,
+    cl_ord_id: String,
+    orig_cl_ord_id: String,
+    ord_status: &str,
+    is_unknown_order: bool,
+    text: &str,
+) -> FixMessage {
+    FixMessage::new(msg_types::ORDER_CANCEL_REJECT)
+        .with(tags::ORDER_ID, order_id)
+        .with(tags::CL_ORD_ID, cl_ord_id)
+        .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id)
+        .with(tags::ORD_STATUS, ord_status)
+        .with(tags::CXL_REJ_RESPONSE_TO, if kind == RequestKind::Cancel { 1 } else { 2 })
+        .with(tags::CXL_REJ_REASON, if is_unknown_order { 1 } else { 99 })
+        .with(tags::TEXT, text)
+}
+
+/// reads a price field, rejecting it if it's missing or isn't a valid price
+fn fix_price(message: &FixMessage, tag: u32) -> Result<u64, RejectReason> {
+    let price = message.get(tag).and_then(fix::parse_price).ok_or(RejectReason::InvalidPrice)?;
+    check_price(price)
+}
+
+fn fix_quantity(value: &str) -> Result<u64, RejectReason> {
+    check_quantity(value.parse::<u64>().map_err(|_| RejectReason::InvalidQuantity)?)
+}
+
+/// turns a NewOrderSingle into an order for the matching engine, along with the record the session keeps of it
+///
+/// # Parameters
+///
+/// * `message` - the NewOrderSingle, which has every required tag
+/// * `account_id` - the account the session logged on with
+fn fix_to_order(message: &FixMessage, account_id: u32) -> Result<(OrderInfo, FixOrder), RejectReason> {
+    let symbol = message
+        .get(tags::SYMBOL)
+        .and_then(|symbol| SYMBOLS.get(symbol))
+        .ok_or(RejectReason::UnknownTicker)?;
+    let side = match message.get(tags::SIDE) {
+        Some("1") => OrderSide::Buy,
+        Some("2") => OrderSide::Sell,
+        _ => return Err(RejectReason::UnknownOrderSide),
+    };
+    let order_type = match message.get(tags::ORD_TYPE) {
+        Some("1") => OrderType::Market,
+        Some("2") => OrderType::Limit(fix_price(message, tags::PRICE)?),
+        Some("3") => OrderType::Stop(fix_price(message, tags::STOP_PX)?),
+        Some("4") => OrderType::StopLimit(fix_price(message, tags::STOP_PX)?, fix_price(message, tags::PRICE)?),
+        _ => return Err(RejectReason::UnknownOrderType),
+    };
+    // FIX orders without a TimeInForce are day orders
+    let time_in_force = match message.get(tags::TIME_IN_FORCE) {
+        None | Some("0") => TimeInForce::Day,
+        Some("1") => TimeInForce::GoodTillCancel,
+        Some("3") => TimeInForce::ImmediateOrCancel,
+        Some("4") => TimeInForce::FillOrKill,
+        _ => return Err(RejectReason::UnknownTimeInForce),
+    };
+    let quantity = fix_quantity(message.get(tags::ORDER_QTY).unwrap_or_default())?;
+
+    let (sender, receiver) = channel();
+    let order = FixOrder {
+        cl_ord_id: message.get(tags::CL_ORD_ID).unwrap_or_default().to_string(),
+        order_id: None,
+        symbol: symbol.ticker().to_string(),
+        side: side,
+        quantity: quantity,
+        cumulative_quantity: 0,
+        average_price: 0,
+        state: OrderState::PendingNew,
+        receiver: Some(receiver),
+    };
+    // ClOrdIDs are kept by the session, since they change with every cancel or replace
+    Ok((OrderInfo::new(account_id, symbol, order_type, side, time_in_force, None, quantity, None, sender), order))
+}
+
+/// reads the new price and quantity of an OrderCancelReplaceRequest. the price is Price,
+/// or StopPx for stop orders, and either can be left out to keep it as it is
+fn fix_to_replace(message: &FixMessage) -> Result<(Option<u64>, Option<u64>), RejectReason> {
+    let price = match (message.get(tags::PRICE), message.get(tags::STOP_PX)) {
+        (Some(_), _) => Some(fix_price(message, tags::PRICE)?),
+        (None, Some(_)) => Some(fix_price(message, tags::STOP_PX)?),
+        (None, None) => None,
+    };
+    let quantity = match message.get(tags::ORDER_QTY) {
+        Some(quantity) => Some(fix_quantity(quantity)?),
+        None => None,
+    };
+    Ok((price, quantity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const COMP_ID: &str = "RUSTYX";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn store_dir(name: &str) -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        std::env::temp_dir().join(format!("rustyxchange-fix-{}-{}", name, nanos))
+    }
+
+    /// the counterparty end of a session, with the test standing in for the matching engine
+    struct TestClient {
+        comp_id: &'static str,
+        connection: Connection,
+        seq_num: u64,
+        orders: Receiver<Cmd>,
+        session: thread::JoinHandle<()>,
+    }
+
+    impl TestClient {
+        /// connects a new session for `comp_id` that stores its sequence numbers in `store_dir`
+        fn connect(comp_id: &'static str, store_dir: &Path) -> TestClient {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (order_channel, orders) = channel();
+            let store_dir = store_dir.to_path_buf();
+            let session = thread::spawn(move || {
+                let (s, _) = listener.accept().unwrap();
+                if let Some(mut session) = FixSession::logon(s, COMP_ID, store_dir, order_channel) {
+                    session.run();
+                }
+            });
+            TestClient {
+                comp_id: comp_id,
+                connection: Connection::new(stream).unwrap(),
+                seq_num: 1,
+                orders: orders,
+                session: session,
+            }
+        }
+
+        /// closes the connection and waits for the session to end
+        fn disconnect(self) {
+            let _ = self.connection.stream.shutdown(Shutdown::Both);
+            self.session.join().unwrap();
+        }
+
+        fn send_with_seq_num(&mut self, message: FixMessage, seq_num: u64) {
+            let data = message.encode(self.comp_id, COMP_ID, seq_num, &fix::timestamp(), None);
+            self.connection.write(&data).unwrap();
+        }
+
+        fn send(&mut self, message: FixMessage) {
+            let seq_num = self.seq_num;
+            self.seq_num += 1;
+            self.send_with_seq_num(message, seq_num);
+        }
+
+        fn receive(&mut self) -> FixMessage {
+            let started = Instant::now();
+            while started.elapsed() < TIMEOUT {
+                if let Some(message) = self.connection.next_message().unwrap() {
+                    return message;
+                }
+            }
+            panic!("no message from the session");
+        }
+
+        fn logon(&mut self, password: &str, reset: bool) -> FixMessage {
+            let mut logon = FixMessage::new(msg_types::LOGON)
+                .with(tags::ENCRYPT_METHOD, 0)
+                .with(tags::HEART_BT_INT, 30)
+                .with(tags::USERNAME, "alice")
+                .with(tags::PASSWORD, password);
+            if reset {
+                logon = logon.with(tags::RESET_SEQ_NUM_FLAG, "Y");
+            }
+            self.send(logon);
+            self.receive()
+        }
+
+        fn next_cmd(&self) -> Cmd {
+            self.orders.recv_timeout(TIMEOUT).unwrap()
+        }
+    }
+
+    fn new_order(cl_ord_id: &str) -> FixMessage {
+        FixMessage::new(msg_types::NEW_ORDER_SINGLE)
+            .with(tags::CL_ORD_ID, cl_ord_id)
+            .with(tags::SYMBOL, "GOOG")
+            .with(tags::SIDE, 1)
+            .with(tags::ORDER_QTY, 10)
+            .with(tags::ORD_TYPE, 2)
+            .with(tags::PRICE, "100.5")
+    }
+
+    #[test]
+    fn test_seq_num_store() {
+        let dir = store_dir("store");
+        let mut store = SeqNumStore::open(&dir, COMP_ID, "CLIENT").unwrap();
+        assert_eq!((store.next_incoming, store.next_outgoing), (1, 1));
+        store.next_incoming = 5;
+        store.next_outgoing = 9;
+        store.save().unwrap();
+
+        let mut store = SeqNumStore::open(&dir, COMP_ID, "CLIENT").unwrap();
+        assert_eq!((store.next_incoming, store.next_outgoing), (5, 9));
+        store.reset().unwrap();
+        let store = SeqNumStore::open(&dir, COMP_ID, "CLIENT").unwrap();
+        assert_eq!((store.next_incoming, store.next_outgoing), (1, 1));
+
+        fs::write(dir.join(format!("{}-OTHER.seqnums", COMP_ID)), "garbage").unwrap();
+        assert!(SeqNumStore::open(&dir, COMP_ID, "OTHER").is_err());
+        assert!(!is_valid_comp_id("../CLIENT"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_logon_is_checked() {
+        let dir = store_dir("logon");
+        let mut client = TestClient::connect("LOGON", &dir);
+        let logout = client.logon("wrong-password", true);
+        assert_eq!(logout.msg_type, msg_types::LOGOUT);
+        assert_eq!(logout.get(tags::TEXT), Some("invalid username or password"));
+        client.disconnect();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_order_lifecycle() {
+        let dir = store_dir("orders");
+        let mut client = TestClient::connect("ORDERS", &dir);
+        let logon = client.logon("alice-password", true);
+        assert_eq!(logon.msg_type, msg_types::LOGON);
+        assert_eq!(logon.get(tags::MSG_SEQ_NUM), Some("1"));
+
+        // a limit order is acknowledged once the matching engine accepts it
+        client.send(new_order("o1"));
+        let sender = match client.next_cmd() {
+            Cmd::Execute(order_info) => {
+                let (order, sender) = order_info.consume(7);
+                assert_eq!((order.account_id, order.symbol.ticker(), order.side), (1, "GOOG", OrderSide::Buy));
+                assert_eq!((order.order_type, order.quantity, order.time_in_force), (OrderType::Limit(100500), 10, TimeInForce::Day));
+                sender
+            }
+            _ => panic!("expected an order"),
+        };
+        sender.send(StatusReport::new(OrderStatus::Waiting(7), None)).unwrap();
+        let ack = client.receive();
+        assert_eq!(ack.msg_type, msg_types::EXECUTION_REPORT);
+        assert_eq!((ack.get(tags::ORDER_ID), ack.get(tags::CL_ORD_ID)), (Some("7"), Some("o1")));
+        assert_eq!((ack.get(tags::EXEC_TYPE), ack.get(tags::ORD_STATUS)), (Some("0"), Some("0")));
+
+        sender
+            .send(StatusReport::new(OrderStatus::Execution(ExecutionReport {
+                execution_id: 3,
+                order_id: 7,
+                price: 100500,
+                quantity: 4,
+                leaves_quantity: 6,
+                cumulative_quantity: 4,
+                average_price: 100500,
+                liquidity: Liquidity::Maker,
+                timestamp: 0,
+            }), None))
+            .unwrap();
+        let trade = client.receive();
+        assert_eq!((trade.get(tags::EXEC_TYPE), trade.get(tags::ORD_STATUS)), (Some("F"), Some("1")));
+        assert_eq!((trade.get(tags::LAST_QTY), trade.get(tags::LAST_PX)), (Some("4"), Some("100.5")));
+        assert_eq!((trade.get(tags::CUM_QTY), trade.get(tags::LEAVES_QTY)), (Some("4"), Some("6")));
+
+        // cancels find the order by its ClOrdID, and move it to the ClOrdID of the cancel
+        client.send(
+            FixMessage::new(msg_types::ORDER_CANCEL_REQUEST)
+                .with(tags::ORIG_CL_ORD_ID, "o1")
+                .with(tags::CL_ORD_ID, "c1")
+                .with(tags::SYMBOL, "GOOG")
+                .with(tags::SIDE, 1),
+        );
+        match client.next_cmd() {
+            Cmd::Cancel(cancel_info) => {
+                let (account_id, order, sender) = cancel_info.consume();
+                assert_eq!((account_id, order), (1, OrderRef::Exchange(7)));
+                sender.send(StatusReport::new(OrderStatus::Canceled(7), None)).unwrap();
+            }
+            _ => panic!("expected a cancel"),
+        }
+        let canceled = client.receive();
+        assert_eq!((canceled.get(tags::EXEC_TYPE), canceled.get(tags::ORD_STATUS)), (Some("4"), Some("4")));
+        assert_eq!((canceled.get(tags::CL_ORD_ID), canceled.get(tags::ORIG_CL_ORD_ID)), (Some("c1"), Some("o1")));
+        assert_eq!(canceled.get(tags::LEAVES_QTY), Some("0"));
+
+        // unknown orders never reach the matching engine
+        client.send(
+            FixMessage::new(msg_types::ORDER_CANCEL_REQUEST)
+                .with(tags::ORIG_CL_ORD_ID, "nope")
+                .with(tags::CL_ORD_ID, "c2"),
+        );
+        let reject = client.receive();
+        assert_eq!(reject.msg_type, msg_types::ORDER_CANCEL_REJECT);
+        assert_eq!((reject.get(tags::CXL_REJ_REASON), reject.get(tags::TEXT)), (Some("1"), Some("unknown order")));
+
+        // bad orders are rejected without reaching the matching engine either
+        client.send(new_order("o2").with(tags::TIME_IN_FORCE, 9));
+        let rejected = client.receive();
+        assert_eq!((rejected.get(tags::EXEC_TYPE), rejected.get(tags::ORD_STATUS)), (Some("8"), Some("8")));
+        assert_eq!(rejected.get(tags::TEXT), Some(RejectReason::UnknownTimeInForce.message()));
+        client.send(FixMessage::new(msg_types::NEW_ORDER_SINGLE).with(tags::CL_ORD_ID, "o3"));
+        let reject = client.receive();
+        assert_eq!((reject.msg_type.as_str(), reject.get(tags::REF_TAG_ID)), (msg_types::REJECT, Some("55")));
+        assert!(client.orders.try_recv().is_err());
+        client.disconnect();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_session_messages() {
+        let dir = store_dir("session");
+        let mut client = TestClient::connect("SESSION", &dir);
+        client.logon("alice-password", true);
+
+        client.send(FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, "ping"));
+        let heartbeat = client.receive();
+        assert_eq!((heartbeat.msg_type.as_str(), heartbeat.get(tags::TEST_REQ_ID)), (msg_types::HEARTBEAT, Some("ping")));
+
+        client.send(new_order("o1").with(tags::TIME_IN_FORCE, 9));
+        let rejected = client.receive();
+        assert_eq!(rejected.get(tags::MSG_SEQ_NUM), Some("3"));
+
+        // execution reports are resent, and session messages are gap filled
+        client.send(FixMessage::new(msg_types::RESEND_REQUEST).with(tags::BEGIN_SEQ_NO, 1).with(tags::END_SEQ_NO, 0));
+        let gap_fill = client.receive();
+        assert_eq!(gap_fill.msg_type, msg_types::SEQUENCE_RESET);
+        assert_eq!((gap_fill.get(tags::MSG_SEQ_NUM), gap_fill.get(tags::NEW_SEQ_NO)), (Some("1"), Some("3")));
+        assert!(gap_fill.flag(tags::GAP_FILL_FLAG));
+        let resent = client.receive();
+        assert_eq!((resent.msg_type.as_str(), resent.get(tags::MSG_SEQ_NUM)), (msg_types::EXECUTION_REPORT, Some("3")));
+        assert!(resent.flag(tags::POSS_DUP_FLAG));
+        assert_eq!(resent.get(tags::ORIG_SENDING_TIME), rejected.get(tags::SENDING_TIME));
+
+        // a message from the future asks for the gap to be resent, and is dropped until then
+        client.send_with_seq_num(FixMessage::new(msg_types::HEARTBEAT), 10);
+        let resend_request = client.receive();
+        assert_eq!(resend_request.msg_type, msg_types::RESEND_REQUEST);
+        assert_eq!((resend_request.get(tags::MSG_SEQ_NUM), resend_request.get(tags::BEGIN_SEQ_NO)), (Some("4"), Some("5")));
+
+        // the same session can't log on twice at once
+        let mut duplicate = TestClient::connect("SESSION", &dir);
+        duplicate.send(FixMessage::new(msg_types::LOGON).with(tags::HEART_BT_INT, 30));
+        duplicate.session.join().unwrap();
+        client.disconnect();
+
+        // sequence numbers carry over to the next logon
+        let mut client = TestClient::connect("SESSION", &dir);
+        client.seq_num = 5;
+        let logon = client.logon("alice-password", false);
+        assert_eq!((logon.msg_type.as_str(), logon.get(tags::MSG_SEQ_NUM)), (msg_types::LOGON, Some("5")));
+        client.send(FixMessage::new(msg_types::TEST_REQUEST).with(tags::TEST_REQ_ID, "again"));
+        assert_eq!(client.receive().get(tags::TEST_REQ_ID), Some("again"));
+        client.disconnect();
+
+        // and a sequence number that went backwards ends the session
+        let mut client = TestClient::connect("SESSION", &dir);
+        client.seq_num = 2;
+        let logout = client.logon("alice-password", false);
+        assert_eq!(logout.msg_type, msg_types::LOGOUT);
+        assert_eq!(logout.get(tags::TEXT), Some("MsgSeqNum too low, expecting 7 but received 2"));
+        client.disconnect();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
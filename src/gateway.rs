@@ -1,15 +1,28 @@
 use byteorder::{ByteOrder, NetworkEndian};
-use std::convert::TryInto;
 use std::error::Error;
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
 use std::{fmt, str, thread, u32};
-use std::collections::HashMap;
 
-use super::SYMBOLS;
+use super::{ACCOUNTS, SYMBOLS};
 
 use types::*;
+use codec::request::{decode_auth, decode_request, Request};
+use codec::response::{encode_response, Response};
+use json_protocol::{auth_response_to_json, json_to_cmd, status_to_json, JsonAuth};
+
+// order id sent with rejections of commands that never made it to the matching engine
+pub const NO_ORDER_ID: u32 = u32::MAX;
+// largest message a client can send, anything longer is skipped and rejected
+const MAX_MESSAGE_SIZE: usize = 1024;
+// how long to wait for a command before sending out statuses
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long a new connection has to log in
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+// how long the rest of a command has to arrive once its first bytes have
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Gateway {
     ip_addr: &'static str,
@@ -31,79 +44,30 @@ impl Gateway {
         let listener = TcpListener::bind(format!("{}:{}", self.ip_addr, self.port))
             .expect("[ERROR] couldn't connect to server");
         println!("[INFO] gateway started on {}:{}", self.ip_addr, self.port);
+        self.serve(listener);
+    }
 
-        let mut account_ids: HashMap<String, u32> = HashMap::new();
-        let mut id_counter: u32 = 1000000000;
+    /// accepts connections from `listener`, giving each its own client thread
+    fn serve(&self, listener: TcpListener) {
         for stream in listener.incoming() {
             match stream {
                 Ok(s) => {
-                    let addr = s.peer_addr().unwrap();
-                    println!("[INFO] new connection: {}", addr);
-
-                    let mut reader =
-                        BufReader::new(s.try_clone().expect("[ERROR] failed to clone stream"));
-
-                    if reader.fill_buf().unwrap_or(&[] as &[u8]).is_empty() {
-                        println!("[ERROR] received invalid attempted connection");
-                        continue;
-                    }
-
-                    // TODO: better authentication, for now only check username
-                    let mut data = [0 as u8; 4];
-
-                    // initial one byte is length of username
-                    if reader.read_exact(&mut data).is_err() {
-                        println!("[ERROR] failed to read username length");
-                        continue;
-                    }
-
-                    let username_len = NetworkEndian::read_u32(&data) as usize;
-
-                    let mut data = vec![0 as u8; username_len];
-                    let read_size = match reader.read(&mut data) {
-                        Ok(size) => size,
-                        Err(_) => {
-                            println!("[ERROR] failed to read username");
-                            continue;
-                        }
-                    };
-
-                    if read_size == 0 || read_size != username_len {
-                        println!("[ERROR] incorrect username length found");
-                        continue;
-                    }
-
-                    let username = match str::from_utf8(data.as_slice()) {
-                        Ok(s) => s,
-                        Err(_) => {
-                            println!("[ERROR] failed to read username");
+                    let addr = match s.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            println!("[ERROR] connection went away: {}", e);
                             continue;
                         }
                     };
+                    println!("[INFO] new connection: {}", addr);
 
-                    let account_id = if account_ids.contains_key(username) {
-                        *account_ids.get(username).unwrap()
-                    } else {
-                        let id = id_counter;
-                        id_counter += 1;
-                        account_ids.insert(username_len.to_string(), id);
-                        id
-                    };
-
-                    println!("[INFO] user {} with id {}", username, account_id);
-
-                    let mut writer = BufWriter::new(s.try_clone().expect("[ERROR] failed to clone stream"));
-                    let mut data = [0 as u8; 4];
-                    NetworkEndian::write_u32(&mut data, account_id);
-                    writer.write_all(&mut data).expect("[ERROR] failed to send account id to client");
-                    drop(reader);
-                    drop(writer);
-
-                    let client = Client::new(account_id, s, self.order_channel.clone());
+                    // logging in happens on the client thread, so a slow or silent connection can't hold up the others
+                    let order_channel = self.order_channel.clone();
                     thread::Builder::new()
                         .name(format!("{}", addr))
-                        .spawn(move || {
-                            client.run();
+                        .spawn(move || match log_in(s, order_channel) {
+                            Ok((client, reader)) => client.run(reader),
+                            Err(e) => println!("[ERROR] login from {} failed: {}", addr, e),
                         })
                         .expect("[ERROR] failed to create client thread");
                 }
@@ -115,18 +79,155 @@ impl Gateway {
     }
 }
 
+/// reads the auth request a new connection has to start with, answers it, and returns the logged in client along
+/// with the reader holding anything it sent after the auth request
+fn log_in(stream: TcpStream, order_channel: Sender<Cmd>) -> Result<(Client, BufReader<TcpStream>), Box<dyn Error>> {
+    stream.set_read_timeout(Some(LOGIN_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    // json sessions log in with a json object, while binary auth requests start with their length,
+    // whose first byte is always 0. the rest of the session uses the same encoding
+    let encoding = match reader.fill_buf()? {
+        [] => return Err("connection closed before logging in".into()),
+        [b'{', ..] => Encoding::Json,
+        _ => Encoding::Binary,
+    };
+
+    // every connection has to start with an auth request, orders are only accepted once it succeeds
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let account_id = match authenticate(&mut reader, encoding) {
+        Ok(account_id) => account_id,
+        Err(e) => {
+            let _ = send_auth_response(&mut writer, encoding, Err(&e.to_string()));
+            return Err(e);
+        }
+    };
+
+    println!("[INFO] {} logged in with account id {} using {:?}", stream.peer_addr()?, account_id, encoding);
+    send_auth_response(&mut writer, encoding, Ok(account_id))?;
+    Ok((Client::new(account_id, stream, order_channel, encoding), reader))
+}
+
+/// how a connection encodes its messages, picked by the auth request it starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    // length prefixed binary messages, as laid out in the README
+    Binary,
+    // one json object per line, see json_protocol
+    Json,
+}
+
+/// reads the auth request a new connection has to start with and checks its credentials against the loaded accounts.
+/// returns the id of the account to trade on
+fn authenticate<R: BufRead>(reader: &mut R, encoding: Encoding) -> Result<u32, Box<dyn Error>> {
+    match encoding {
+        Encoding::Binary => {
+            let mut data = [0 as u8; 4];
+            reader.read_exact(&mut data)?;
+
+            let size = NetworkEndian::read_u32(&data) as usize;
+            if size > MAX_MESSAGE_SIZE {
+                return Err(InvalidAuthRequest.into());
+            }
+            let mut data = vec![0 as u8; size];
+            reader.read_exact(&mut data)?;
+
+            let auth = decode_auth(data.as_slice()).ok_or(InvalidAuthRequest)?;
+            check_credentials(auth.username, auth.password)
+        }
+        Encoding::Json => authenticate_json(&read_json_line(reader)?),
+    }
+}
+
+/// checks a json login against the loaded accounts, returning the id of the account to trade on
+pub fn authenticate_json(line: &str) -> Result<u32, Box<dyn Error>> {
+    let auth: JsonAuth = serde_json::from_str(line).map_err(|_| InvalidAuthRequest)?;
+    check_credentials(&auth.username, &auth.password)
+}
+
+fn check_credentials(username: &str, password: &str) -> Result<u32, Box<dyn Error>> {
+    match ACCOUNTS.get(username) {
+        Some(account) if account.check_password(password) => Ok(account.id()),
+        // unknown users get the same answer as wrong passwords
+        _ => Err(InvalidCredentials.into()),
+    }
+}
+
+/// reads one line of a json session, which can be at most `MAX_MESSAGE_SIZE` bytes long
+fn read_json_line<R: BufRead>(reader: &mut R) -> Result<String, Box<dyn Error>> {
+    let mut data: Vec<u8> = Vec::new();
+    reader.by_ref().take(MAX_MESSAGE_SIZE as u64 + 1).read_until(b'\n', &mut data)?;
+    if data.last() != Some(&b'\n') && data.len() > MAX_MESSAGE_SIZE {
+        // skip the rest of the line so the next one is read from the right place
+        loop {
+            let (is_done, used) = {
+                let buf = reader.fill_buf()?;
+                match buf.iter().position(|byte| *byte == b'\n') {
+                    Some(i) => (true, i + 1),
+                    None => (buf.is_empty(), buf.len()),
+                }
+            };
+            reader.consume(used);
+            if is_done {
+                break;
+            }
+        }
+        return Err(RejectReason::MessageTooLong.into());
+    }
+    String::from_utf8(data).map_err(|_| RejectReason::InvalidJson.into())
+}
+
+/// tells the client whether it logged in. binary clients get an `Authenticated` or `AuthRejected`
+/// response frame, json clients get a json line
+fn send_auth_response<W: Write>(writer: &mut W, encoding: Encoding, result: Result<u32, &str>) -> Result<(), Box<dyn Error>> {
+    if encoding == Encoding::Json {
+        writer.write_all(auth_response_to_json(result).as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    let response = match result {
+        Ok(account_id) => Response::Authenticated(account_id),
+        Err(reason) => Response::AuthRejected(reason),
+    };
+    writer.write_all(&encode_response(&response))?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct InvalidAuthRequest;
+
+impl fmt::Display for InvalidAuthRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "malformed auth request")
+    }
+}
+
+impl Error for InvalidAuthRequest {
+    fn description(&self) -> &str {
+        "malformed auth request"
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
+
 #[derive(Debug, Clone)]
-struct InvalidRWSize;
+struct InvalidCredentials;
 
-impl fmt::Display for InvalidRWSize {
+impl fmt::Display for InvalidCredentials {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid read or write size from buffer")
+        write!(f, "invalid username or password")
     }
 }
 
-impl Error for InvalidRWSize {
+impl Error for InvalidCredentials {
     fn description(&self) -> &str {
-        "invalid read or write size from buffer"
+        "invalid username or password"
     }
 
     fn cause(&self) -> Option<&dyn Error> {
@@ -137,23 +238,20 @@ impl Error for InvalidRWSize {
 
 struct Client {
     account_id: u32,
+    encoding: Encoding,
     stream: TcpStream,
     order_channel: Sender<Cmd>,
-    sender: Sender<OrderStatus>,
-    receiver: Receiver<OrderStatus>,
+    sender: Sender<StatusReport>,
+    receiver: Receiver<StatusReport>,
 }
 
 impl Client {
-    fn new(account_id: u32, stream: TcpStream, order_channel: Sender<Cmd>) -> Self {
-        let (sender, receiver): (Sender<OrderStatus>, Receiver<OrderStatus>) = channel();
-
-        // set timeout to none -- we will handle dead connections ourselves
-        stream
-            .set_read_timeout(None)
-            .expect("[ERROR] failed to set read timeout to None");
+    fn new(account_id: u32, stream: TcpStream, order_channel: Sender<Cmd>, encoding: Encoding) -> Self {
+        let (sender, receiver): (Sender<StatusReport>, Receiver<StatusReport>) = channel();
 
         Client {
             account_id: account_id,
+            encoding: encoding,
             stream: stream,
             order_channel: order_channel,
             sender: sender,
@@ -161,63 +259,74 @@ impl Client {
         }
     }
 
-    fn run(&self) {
-        let mut reader = BufReader::new(
-            self.stream
-                .try_clone()
-                .expect("[ERROR] failed to clone stream"),
-        );
+    fn run(&self, reader: BufReader<TcpStream>) {
+        if let Err(e) = self.serve(reader) {
+            println!("[ERROR] client with account id {} failed: {}", self.account_id, e);
+        }
+        println!("[INFO] client with account id {} disconnected", self.account_id);
+    }
 
-        let mut writer = BufWriter::new(
-            self.stream
-                .try_clone()
-                .expect("[ERROR] failed to clone stream"),
-        );
+    fn serve(&self, mut reader: BufReader<TcpStream>) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(self.stream.try_clone()?);
 
+        // waiting for a command times out every poll interval, so statuses go out even while the client is quiet
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
         loop {
-            if !reader.fill_buf().unwrap_or(&[] as &[u8]).is_empty() {
-                match self.recv_order(&mut reader) {
-                    Ok(cmd) => {
-                        self.order_channel
-                            .send(cmd)
-                            .expect("[ERROR] order channel was dropped");
-                    }
-                    Err(e) => {
-                        println!("[ERROR] failed to process order: {}", e);
-                    }
+            let has_data = match reader.fill_buf() {
+                // the client closed the connection
+                Ok([]) => return Ok(()),
+                Ok(_) => true,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => false,
+                Err(e) => return Err(e.into()),
+            };
+
+            if has_data {
+                // the rest of a command that started arriving gets longer than a poll interval
+                self.stream.set_read_timeout(Some(MESSAGE_TIMEOUT))?;
+                let cmd = self.recv_order(&mut reader);
+                self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+                match cmd {
+                    Ok(cmd) => self.order_channel.send(cmd).map_err(|_| "matching engine has stopped")?,
+                    Err(e) => match e.downcast_ref::<RejectReason>() {
+                        // bad commands never make it to the matching engine, so they are rejected from here
+                        Some(reason) => {
+                            println!("[ERROR] rejected command from account {}: {}", self.account_id, reason);
+                            self.send_status(&mut writer, StatusReport::new(OrderStatus::Rejected(NO_ORDER_ID, *reason), None))?;
+                        }
+                        // a half read command leaves the stream out of step, so the connection is closed
+                        None => return Err(e),
+                    },
                 }
             }
 
-            // FIXME: will this be inefficient?
-            while let Ok(order_status) = self.receiver.try_recv() {
-                match self.send_status(&mut writer, order_status) {
-                    Err(e) => {
-                        println!("[ERROR] failed to send status: {}", e);
-                    }
-                    _ => {}
-                }
+            while let Ok(report) = self.receiver.try_recv() {
+                self.send_status(&mut writer, report)?;
             }
         }
-
-        /*
-        self.stream
-            .shutdown(Shutdown::Both)
-            .expect("[ERROR] failed to shutdown tcp stream");
-        */
     }
 
     fn recv_order(&self, reader: &mut BufReader<TcpStream>) -> Result<Cmd, Box<dyn Error>> {
+        if self.encoding == Encoding::Json {
+            let line = read_json_line(reader)?;
+            return Ok(json_to_cmd(line.trim_end(), self.account_id, self.sender.clone())?);
+        }
+
         // read the first byte
         let mut data = [0 as u8; 4];
         reader.read_exact(&mut data)?;
 
         let size = NetworkEndian::read_u32(&data) as usize;
-        let mut data = vec![0 as u8; size];
-        let read_size = reader.read(&mut data)?;
-        if read_size == 0 || read_size != size {
-            return Err(InvalidRWSize.into());
+        if size > MAX_MESSAGE_SIZE {
+            // skip over the message so the next one is read from the right place
+            io::copy(&mut reader.by_ref().take(size as u64), &mut io::sink())?;
+            return Err(RejectReason::MessageTooLong.into());
         }
 
+        // a command can arrive split over several reads
+        let mut data = vec![0 as u8; size];
+        reader.read_exact(&mut data)?;
+
         let order = self.data_to_struct(data.as_slice())?;
         Ok(order)
     }
@@ -225,126 +334,60 @@ impl Client {
     fn send_status(
         &self,
         writer: &mut BufWriter<TcpStream>,
-        order_status: OrderStatus,
+        report: StatusReport,
     ) -> Result<(), Box<dyn Error>> {
-        let mut data: Vec<u8> = vec![0; 1000];
-
-        // TODO: check these byte values
-        match order_status {
-            OrderStatus::Filled(order_id, price) => {
-                data.push(13 as u8);
-                data.push(0 as u8);
-                NetworkEndian::write_u32(&mut data[1..5], order_id);
-                NetworkEndian::write_u64(&mut data[5..13], price);
-            }
-            OrderStatus::PartiallyFilled(order_id, quantity, price) => {
-                data.push(21 as u8);
-                data.push(1 as u8);
-                NetworkEndian::write_u32(&mut data[1..5], order_id);
-                NetworkEndian::write_u64(&mut data[5..13], quantity);
-                NetworkEndian::write_u64(&mut data[13..21], price);
-            }
-            OrderStatus::Waiting(order_id) => {
-                data.push(5 as u8);
-                data.push(2 as u8);
-                NetworkEndian::write_u32(&mut data[1..5], order_id);
-            }
-            OrderStatus::Rejected(order_id, reason) => {
-                data.push(13 as u8);
-                data.push(3 as u8);
-                NetworkEndian::write_u32(&mut data[1..5], order_id);
-                // len returns a usize, which can be either a u32 or u64. for simplicity, assume it is a u64
-                NetworkEndian::write_u64(&mut data[5..13], reason.len() as u64);
-                // append the message after the payload
-                for byte in reason.as_bytes() {
-                    data.push(*byte);
-                }
-            }
-            OrderStatus::Canceled(order_id) => {
-                data.push(4 as u8);
-                NetworkEndian::write_u32(&mut data[0..4], order_id);
-            }
-        };
-
-        let size = data.len();
-        let write_size = writer.write(data.as_slice())?;
-
-        if write_size == 0 || write_size != size {
-            return Err(InvalidRWSize.into());
+        if self.encoding == Encoding::Json {
+            writer.write_all(status_to_json(&report).as_bytes())?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+            return Ok(());
         }
 
+        writer.write_all(&encode_response(&Response::Status(report)))?;
+        writer.flush()?;
         Ok(())
     }
 
-    fn data_to_struct(&self, data: &[u8]) -> Result<Cmd, Box<dyn Error>> {
-        let cmd_type = CmdType::from_id(data[0] & 3);
-        let account_id = u32::from_be_bytes(data[1..5].try_into()?);
-
-        match cmd_type {
-            CmdType::Execute => {
-                let order_side = OrderSide::from_id(data[0] >> 2);
-                let mut order_type = OrderType::from_id(data[5]);
-                let ticker = str::from_utf8(&data[6..10])?;
-
-                match order_type {
-                    OrderType::Limit(ref mut thresh) => {
-                        *thresh = NetworkEndian::read_u64(data[10..18].try_into()?);
-                    }
-                    OrderType::Stop(ref mut thresh) => {
-                        *thresh = NetworkEndian::read_u64(data[10..18].try_into()?);
-                    }
-                    _ => {}
-                };
+    fn data_to_struct(&self, data: &[u8]) -> Result<Cmd, RejectReason> {
+        let request = decode_request(data)?;
+        // clients can only send commands for the account they logged in with
+        if request.account_id() != self.account_id {
+            return Err(RejectReason::AccountMismatch);
+        }
 
-                let quantity = u64::from_be_bytes(data[18..26].try_into()?);
-                let symbol = SYMBOLS
-                    .get(ticker)
-                    .expect(&format!("[ERROR]: invalid ticker {} found", ticker)[..]);
+        match request {
+            Request::Execute {
+                account_id,
+                ticker,
+                order_type,
+                side,
+                time_in_force,
+                self_trade_prevention,
+                quantity,
+                client_order_id,
+            } => {
+                let symbol = SYMBOLS.get(ticker).ok_or(RejectReason::UnknownTicker)?;
 
                 Ok(Cmd::Execute(OrderInfo::new(
                     account_id,
                     symbol,
                     order_type,
-                    order_side,
+                    side,
+                    time_in_force,
+                    self_trade_prevention,
                     quantity,
+                    client_order_id,
                     self.sender.clone(),
                 )))
             }
-            CmdType::Status => {
-                let order_id = u32::from_be_bytes(data[5..9].try_into()?);
-
-                Ok(Cmd::Status(StatusInfo::new(
-                    account_id,
-                    order_id,
-                    self.sender.clone(),
-                )))
-            }
-            CmdType::Cancel => {
-                let order_id = u32::from_be_bytes(data[5..9].try_into()?);
-
-                Ok(Cmd::Cancel(CancelInfo::new(
-                    account_id,
-                    order_id,
-                    self.sender.clone(),
-                )))
-            }
-            CmdType::Pnl => {
-                let order_id = u32::from_be_bytes(data[5..9].try_into()?);
-                Ok(Cmd::Cancel(CancelInfo::new(
-                    account_id,
-                    order_id,
-                    self.sender.clone(),
-                )))
-            }
-            CmdType::Auth => {
-                let order_id = u32::from_be_bytes(data[5..9].try_into()?);
-
-                Ok(Cmd::Cancel(CancelInfo::new(
-                    account_id,
-                    order_id,
-                    self.sender.clone(),
-                )))
-            }
+            Request::Replace {
+                account_id,
+                order_id,
+                price,
+                quantity,
+            } => Ok(Cmd::Replace(ReplaceInfo::new(account_id, order_id, price, quantity, self.sender.clone()))),
+            Request::Status { account_id, order } => Ok(Cmd::Status(StatusInfo::new(account_id, order, self.sender.clone()))),
+            Request::Cancel { account_id, order } => Ok(Cmd::Cancel(CancelInfo::new(account_id, order, self.sender.clone()))),
         }
     }
 }
@@ -352,7 +395,269 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use codec::request::{encode_auth, Auth};
+    use codec::response::decode_response;
+    use codec::{self, LENGTH_SIZE};
+    use byteorder::WriteBytesExt;
+    use std::net::SocketAddr;
+    use matching_engine::process_orders;
+    use rustyxchange::client::OrderClient;
 
     #[test]
     fn test_add() {}
+
+    fn test_client(account_id: u32) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (order_channel, _) = channel();
+        Client::new(account_id, stream, order_channel, Encoding::Binary)
+    }
+
+    /// builds an execute command for account 1 with the given header byte and type/time in force byte
+    fn execute(header: u8, order_type: u8, ticker: &str, price: u64, quantity: u64) -> Vec<u8> {
+        let mut data = vec![header, 0, 0, 0, 1, order_type];
+        data.extend_from_slice(ticker.as_bytes());
+        data.write_u64::<NetworkEndian>(price).unwrap();
+        data.write_u64::<NetworkEndian>(quantity).unwrap();
+        data
+    }
+
+    fn rejection(client: &Client, data: &[u8]) -> RejectReason {
+        match client.data_to_struct(data) {
+            Ok(_) => panic!("expected {:?} to be rejected", data),
+            Err(reason) => reason,
+        }
+    }
+
+    #[test]
+    fn test_valid_commands_are_decoded() {
+        let client = test_client(1);
+        assert!(matches!(client.data_to_struct(&execute(0, 1, "GOOG", 100, 10)), Ok(Cmd::Execute(_))));
+        assert!(matches!(client.data_to_struct(&[2, 0, 0, 0, 1, 0, 0, 0, 7]), Ok(Cmd::Status(_))));
+        assert!(matches!(client.data_to_struct(&[3, 0, 0, 0, 1, 0, 0, 0, 7]), Ok(Cmd::Cancel(_))));
+        match client.data_to_struct(&[2 | 1 << 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 7]) {
+            Ok(Cmd::Status(status_info)) => assert_eq!(status_info.consume().1, OrderRef::Client(7)),
+            _ => panic!("expected a status request by client order id"),
+        }
+    }
+
+    #[test]
+    fn test_bad_commands_are_rejected() {
+        let client = test_client(1);
+        assert_eq!(rejection(&client, &[0, 0, 0]), RejectReason::MessageTooShort);
+        assert_eq!(rejection(&client, &execute(0, 1, "GOOG", 100, 10)[..20]), RejectReason::MessageTooShort);
+        assert_eq!(rejection(&client, &[2, 0, 0, 0, 1, 0]), RejectReason::MessageTooShort);
+        assert_eq!(rejection(&client, &execute(0, 3, "GOOG", 100, 10)), RejectReason::MessageTooShort);
+        assert_eq!(rejection(&client, &[2, 0, 0, 0, 2, 0, 0, 0, 7]), RejectReason::AccountMismatch);
+        assert_eq!(rejection(&client, &execute(0, 7, "GOOG", 100, 10)), RejectReason::UnknownOrderType);
+        assert_eq!(rejection(&client, &execute(0, 0x41, "GOOG", 100, 10)), RejectReason::UnknownTimeInForce);
+        assert_eq!(rejection(&client, &execute(5 << 3, 1, "GOOG", 100, 10)), RejectReason::UnknownSelfTradePrevention);
+        assert_eq!(rejection(&client, &execute(0, 1, "ZZZZ", 100, 10)), RejectReason::UnknownTicker);
+        assert_eq!(rejection(&client, &execute(0, 1, "GOOG", 100, 0)), RejectReason::InvalidQuantity);
+        assert_eq!(rejection(&client, &execute(0, 1, "GOOG", 100, MAX_QUANTITY + 1)), RejectReason::InvalidQuantity);
+        assert_eq!(rejection(&client, &execute(0, 1, "GOOG", MAX_PRICE + 1, 10)), RejectReason::InvalidPrice);
+    }
+
+    #[test]
+    fn test_authenticate_json_and_binary() {
+        let mut reader = io::Cursor::new(&b"{\"username\": \"bob\", \"password\": \"bob-password\"}\n"[..]);
+        assert_eq!(authenticate(&mut reader, Encoding::Json).unwrap(), 2);
+        let mut reader = io::Cursor::new(&b"{\"username\": \"bob\", \"password\": \"alice-password\"}\n"[..]);
+        assert!(authenticate(&mut reader, Encoding::Json).is_err());
+        let mut reader = io::Cursor::new(&b"{\"username\": \"bob\"}\n"[..]);
+        assert!(authenticate(&mut reader, Encoding::Json).is_err());
+
+        let data = encode_auth(&Auth {
+            username: "bob",
+            password: "bob-password",
+        });
+        assert_eq!(authenticate(&mut io::Cursor::new(data), Encoding::Binary).unwrap(), 2);
+        let data = encode_auth(&Auth {
+            username: "bob",
+            password: "alice-password",
+        });
+        assert!(authenticate(&mut io::Cursor::new(data), Encoding::Binary).is_err());
+    }
+
+    #[test]
+    fn test_json_lines_are_bounded() {
+        let mut data = b"{\"command\": \"Status\", \"order_id\": 3}\n".to_vec();
+        data.extend(vec![b' '; MAX_MESSAGE_SIZE + 10]);
+        data.extend(b"\n{\"command\": \"Cancel\", \"order_id\": 3}\n");
+        let mut reader = io::Cursor::new(data);
+
+        assert_eq!(read_json_line(&mut reader).unwrap(), "{\"command\": \"Status\", \"order_id\": 3}\n");
+        let error = read_json_line(&mut reader).err().unwrap();
+        assert_eq!(error.downcast_ref::<RejectReason>(), Some(&RejectReason::MessageTooLong));
+        assert_eq!(read_json_line(&mut reader).unwrap(), "{\"command\": \"Cancel\", \"order_id\": 3}\n");
+    }
+
+    #[test]
+    fn test_json_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (order_channel, _) = channel();
+        let client = Client::new(1, listener.accept().unwrap().0, order_channel, Encoding::Json);
+
+        stream.write_all(b"{\"command\": \"Cancel\", \"order_id\": 3}\nnot json\n").unwrap();
+        let mut reader = BufReader::new(client.stream.try_clone().unwrap());
+        assert!(matches!(client.recv_order(&mut reader), Ok(Cmd::Cancel(_))));
+        let error = client.recv_order(&mut reader).err().unwrap();
+        assert_eq!(error.downcast_ref::<RejectReason>(), Some(&RejectReason::InvalidJson));
+
+        let mut writer = BufWriter::new(client.stream.try_clone().unwrap());
+        client.send_status(&mut writer, StatusReport::new(OrderStatus::Canceled(3), None)).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"order_id\":3,\"status\":\"Canceled\"}\n");
+    }
+
+    #[test]
+    fn test_oversized_message_is_skipped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (order_channel, _) = channel();
+        let client = Client::new(1, listener.accept().unwrap().0, order_channel, Encoding::Binary);
+
+        let mut data: Vec<u8> = Vec::new();
+        data.write_u32::<NetworkEndian>(MAX_MESSAGE_SIZE as u32 + 1).unwrap();
+        data.extend(vec![0; MAX_MESSAGE_SIZE + 1]);
+        data.write_u32::<NetworkEndian>(9).unwrap();
+        data.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0, 7]);
+        stream.write_all(&data).unwrap();
+
+        let mut reader = BufReader::new(client.stream.try_clone().unwrap());
+        let error = client.recv_order(&mut reader).err().unwrap();
+        assert_eq!(error.downcast_ref::<RejectReason>(), Some(&RejectReason::MessageTooLong));
+        // the next message is still read correctly
+        assert!(matches!(client.recv_order(&mut reader), Ok(Cmd::Status(_))));
+    }
+
+    #[test]
+    fn test_split_message_is_read_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (order_channel, _) = channel();
+        let client = Client::new(1, listener.accept().unwrap().0, order_channel, Encoding::Binary);
+
+        let mut data: Vec<u8> = Vec::new();
+        data.write_u32::<NetworkEndian>(9).unwrap();
+        data.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0, 7]);
+        stream.write_all(&data[..7]).unwrap();
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(&data[7..]).unwrap();
+            stream
+        });
+
+        let mut reader = BufReader::new(client.stream.try_clone().unwrap());
+        assert!(matches!(client.recv_order(&mut reader), Ok(Cmd::Status(_))));
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_statuses_are_sent_while_the_client_is_quiet() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (order_channel, orders) = channel();
+        let client = Client::new(1, listener.accept().unwrap().0, order_channel, Encoding::Binary);
+        let reader = BufReader::new(client.stream.try_clone().unwrap());
+        let client_thread = thread::spawn(move || client.run(reader));
+
+        let mut data: Vec<u8> = Vec::new();
+        data.write_u32::<NetworkEndian>(9).unwrap();
+        data.extend_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0, 7]);
+        stream.write_all(&data).unwrap();
+
+        let sender = match orders.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Cmd::Status(status_info) => status_info.consume().2,
+            _ => panic!("expected a status request"),
+        };
+        // a fill notice for a resting order, which arrives without the client sending anything
+        let report = StatusReport::new(OrderStatus::Filled(7, 100), None);
+        sender.send(report).unwrap();
+        let data = codec::read_message(&mut stream, MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(decode_response(&data), Ok(Response::Status(report)));
+
+        // the client thread stops once the connection is closed
+        drop(stream);
+        client_thread.join().unwrap();
+    }
+
+    /// starts a gateway on a free port, with the test standing in for the matching engine
+    fn start_gateway() -> (SocketAddr, Receiver<Cmd>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (order_channel, orders) = channel();
+        thread::spawn(move || Gateway::new("127.0.0.1", addr.port() as u32, order_channel).serve(listener));
+        (addr, orders)
+    }
+
+    #[test]
+    fn test_silent_connection_does_not_block_logins() {
+        let (addr, _orders) = start_gateway();
+        let _silent = TcpStream::connect(addr).unwrap();
+        let mut half_auth = TcpStream::connect(addr).unwrap();
+        half_auth.write_all(&encode_auth(&Auth { username: "bob", password: "bob-password" })[..6]).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(&encode_auth(&Auth { username: "alice", password: "alice-password" })).unwrap();
+        let data = codec::read_message(&mut stream, MAX_MESSAGE_SIZE).unwrap();
+        assert_eq!(decode_response(&data), Ok(Response::Authenticated(ACCOUNTS.get("alice").unwrap().id())));
+    }
+
+    fn recv_status(client: &mut OrderClient) -> OrderStatus {
+        client.recv_status().unwrap().status
+    }
+
+    #[test]
+    fn test_order_client_trades_through_the_matching_engine() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (order_channel, orders) = channel();
+        let (market_data_send, _market_data_recv) = channel();
+        let (fill_send, _fill_recv) = channel();
+        thread::spawn(move || process_orders(market_data_send, fill_send, orders, 0, 0));
+        thread::spawn(move || Gateway::new("127.0.0.1", addr.port() as u32, order_channel).serve(listener));
+
+        let mut alice = OrderClient::connect(addr, "alice", "alice-password").unwrap();
+        let mut bob = OrderClient::connect(addr, "bob", "bob-password").unwrap();
+        alice.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        bob.set_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        alice.execute("GOOG", OrderType::Limit(100), OrderSide::Sell, TimeInForce::GoodTillCancel, None, 10, Some(1)).unwrap();
+        assert_eq!(alice.recv_status().unwrap(), StatusReport::new(OrderStatus::Waiting(0), Some(1)));
+        bob.execute("GOOG", OrderType::Limit(100), OrderSide::Buy, TimeInForce::GoodTillCancel, None, 4, Some(2)).unwrap();
+
+        // the aggressor's fill comes before its final status
+        assert!(matches!(recv_status(&mut bob), OrderStatus::Execution(ExecutionReport { order_id: 1, quantity: 4, .. })));
+        assert_eq!(recv_status(&mut bob), OrderStatus::Filled(1, 4 * 100));
+        // the resting side hears about its fill without sending anything
+        assert!(matches!(recv_status(&mut alice), OrderStatus::Execution(ExecutionReport { order_id: 0, quantity: 4, .. })));
+        assert_eq!(recv_status(&mut alice), OrderStatus::PartiallyFilled(0, 4, 4 * 100));
+
+        alice.cancel(OrderRef::Client(1)).unwrap();
+        assert_eq!(alice.recv_status().unwrap(), StatusReport::new(OrderStatus::Canceled(0), Some(1)));
+    }
+
+    #[test]
+    fn test_check_password() {
+        let alice = ACCOUNTS.get("alice").unwrap();
+        assert!(alice.check_password("alice-password"));
+        assert!(!alice.check_password("bob-password"));
+        assert!(!alice.check_password(""));
+    }
+
+    #[test]
+    fn test_auth_responses() {
+        let mut data: Vec<u8> = Vec::new();
+        send_auth_response(&mut data, Encoding::Binary, Ok(7)).unwrap();
+        assert_eq!(decode_response(&data[LENGTH_SIZE..]), Ok(Response::Authenticated(7)));
+
+        let mut data: Vec<u8> = Vec::new();
+        send_auth_response(&mut data, Encoding::Binary, Err("invalid username or password")).unwrap();
+        assert_eq!(decode_response(&data[LENGTH_SIZE..]), Ok(Response::AuthRejected("invalid username or password")));
+    }
 }
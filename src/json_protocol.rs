@@ -0,0 +1,357 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::mpsc::Sender;
+
+use super::SYMBOLS;
+use types::*;
+
+// newline delimited json version of the order sending protocol.
+// every request and response is a single json object on its own line,
+// with the same meaning as its binary counterpart.
+// statuses of orders sent with a client order id carry it in their "client_order_id" field
+
+/// the kinds of orders, without the prices that go with them in `OrderType`
+#[derive(Deserialize)]
+enum JsonOrderType {
+    Market,
+    Limit,
+    Stop,
+    StopLimit,
+}
+
+/// a request sent by a json client, tagged by its "command" field
+#[derive(Deserialize)]
+#[serde(tag = "command")]
+enum JsonRequest {
+    Execute {
+        symbol: String,
+        side: OrderSide,
+        order_type: JsonOrderType,
+        // limit price of limit and stop limit orders
+        price: Option<u64>,
+        // stop price of stop and stop limit orders
+        stop_price: Option<u64>,
+        quantity: u64,
+        time_in_force: Option<TimeInForce>,
+        self_trade_prevention: Option<SelfTradePrevention>,
+        client_order_id: Option<u64>,
+    },
+    Replace {
+        order_id: u32,
+        price: Option<u64>,
+        quantity: Option<u64>,
+    },
+    // status and cancel requests name their order by exactly one of order_id and client_order_id
+    Status {
+        order_id: Option<u32>,
+        client_order_id: Option<u64>,
+    },
+    Cancel {
+        order_id: Option<u32>,
+        client_order_id: Option<u64>,
+    },
+}
+
+/// the login line a json session starts with
+#[derive(Deserialize)]
+pub struct JsonAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// returns the price a json order needs, rejecting it if it's missing or too large
+fn required_price(price: Option<u64>) -> Result<u64, RejectReason> {
+    check_price(price.ok_or(RejectReason::InvalidPrice)?)
+}
+
+/// returns the order a status or cancel request names, rejecting requests that name none or both
+fn order_ref(order_id: Option<u32>, client_order_id: Option<u64>) -> Result<OrderRef, RejectReason> {
+    match (order_id, client_order_id) {
+        (Some(order_id), None) => Ok(OrderRef::Exchange(order_id)),
+        (None, Some(client_order_id)) => Ok(OrderRef::Client(check_client_order_id(client_order_id)?)),
+        _ => Err(RejectReason::InvalidJson),
+    }
+}
+
+/// turns a line sent by a json client into a command for the matching engine
+///
+/// # Parameters
+///
+/// * `line` - the json request, without its newline
+/// * `account_id` - the account the client logged in with, which every command is sent for
+/// * `sender` - the channel responses to the command are sent back on
+pub fn json_to_cmd(line: &str, account_id: u32, sender: Sender<StatusReport>) -> Result<Cmd, RejectReason> {
+    let request: JsonRequest = serde_json::from_str(line).map_err(|_| RejectReason::InvalidJson)?;
+
+    match request {
+        JsonRequest::Execute {
+            symbol,
+            side,
+            order_type,
+            price,
+            stop_price,
+            quantity,
+            time_in_force,
+            self_trade_prevention,
+            client_order_id,
+        } => {
+            let order_type = match order_type {
+                JsonOrderType::Market => OrderType::Market,
+                JsonOrderType::Limit => OrderType::Limit(required_price(price)?),
+                JsonOrderType::Stop => OrderType::Stop(required_price(stop_price)?),
+                JsonOrderType::StopLimit => OrderType::StopLimit(required_price(stop_price)?, required_price(price)?),
+            };
+            let symbol = SYMBOLS.get(&symbol).ok_or(RejectReason::UnknownTicker)?;
+            let client_order_id = match client_order_id {
+                Some(client_order_id) => Some(check_client_order_id(client_order_id)?),
+                None => None,
+            };
+
+            Ok(Cmd::Execute(OrderInfo::new(
+                account_id,
+                symbol,
+                order_type,
+                side,
+                time_in_force.unwrap_or(TimeInForce::GoodTillCancel),
+                self_trade_prevention,
+                check_quantity(quantity)?,
+                client_order_id,
+                sender,
+            )))
+        }
+        JsonRequest::Replace { order_id, price, quantity } => {
+            if let Some(price) = price {
+                check_price(price)?;
+            }
+            if let Some(quantity) = quantity {
+                check_quantity(quantity)?;
+            }
+            Ok(Cmd::Replace(ReplaceInfo::new(account_id, order_id, price, quantity, sender)))
+        }
+        JsonRequest::Status { order_id, client_order_id } => {
+            Ok(Cmd::Status(StatusInfo::new(account_id, order_ref(order_id, client_order_id)?, sender)))
+        }
+        JsonRequest::Cancel { order_id, client_order_id } => {
+            Ok(Cmd::Cancel(CancelInfo::new(account_id, order_ref(order_id, client_order_id)?, sender)))
+        }
+    }
+}
+
+/// turns a status into the json line sent to json clients, without its newline
+pub fn status_to_json(status_report: &StatusReport) -> String {
+    let mut value: Value = match status_report.status {
+        OrderStatus::Filled(order_id, cost) => json!({
+            "status": "Filled",
+            "order_id": order_id,
+            "cost": cost,
+        }),
+        OrderStatus::PartiallyFilled(order_id, filled_quantity, cost) => json!({
+            "status": "PartiallyFilled",
+            "order_id": order_id,
+            "filled_quantity": filled_quantity,
+            "cost": cost,
+        }),
+        OrderStatus::Waiting(order_id) => json!({
+            "status": "Waiting",
+            "order_id": order_id,
+        }),
+        OrderStatus::Rejected(order_id, reason) => json!({
+            "status": "Rejected",
+            "order_id": order_id,
+            "code": reason.code(),
+            "reason": reason.message(),
+        }),
+        OrderStatus::Canceled(order_id) => json!({
+            "status": "Canceled",
+            "order_id": order_id,
+        }),
+        OrderStatus::Replaced(order_id, price, quantity) => json!({
+            "status": "Replaced",
+            "order_id": order_id,
+            "price": price,
+            "quantity": quantity,
+        }),
+        OrderStatus::SelfTradePrevented(order_id, quantity) => json!({
+            "status": "SelfTradePrevented",
+            "order_id": order_id,
+            "quantity": quantity,
+        }),
+        OrderStatus::Execution(report) => json!({
+            "status": "Execution",
+            "order_id": report.order_id,
+            "execution_id": report.execution_id,
+            "price": report.price,
+            "quantity": report.quantity,
+            "leaves_quantity": report.leaves_quantity,
+            "cumulative_quantity": report.cumulative_quantity,
+            "average_price": report.average_price,
+            "liquidity": report.liquidity,
+            "timestamp": report.timestamp,
+        }),
+    };
+    if let Some(client_order_id) = status_report.client_order_id {
+        value["client_order_id"] = json!(client_order_id);
+    }
+    value.to_string()
+}
+
+/// turns a top of book update into the json line sent to json clients, without its newline.
+/// market data is tagged by its "market_data" field, so it can't be mistaken for an order status
+pub fn price_info_to_json(price_info: &PriceInfo) -> String {
+    let value = json!({
+        "market_data": "Quote",
+        "symbol": price_info.get_symbol().ticker(),
+        "best_bid": price_info.best_bid,
+        "bid_size": price_info.bid_size,
+        "best_ask": price_info.best_ask,
+        "ask_size": price_info.ask_size,
+    });
+    value.to_string()
+}
+
+/// turns the result of a login into the json line sent back to the client, without its newline
+pub fn auth_response_to_json(result: Result<u32, &str>) -> String {
+    let value: Value = match result {
+        Ok(account_id) => json!({
+            "status": "Authenticated",
+            "account_id": account_id,
+        }),
+        Err(reason) => json!({
+            "status": "AuthRejected",
+            "reason": reason,
+        }),
+    };
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn decode(line: &str) -> Result<Cmd, RejectReason> {
+        json_to_cmd(line, 1, channel().0)
+    }
+
+    fn decode_order(line: &str) -> Order {
+        match decode(line) {
+            Ok(Cmd::Execute(order_info)) => order_info.consume(0).0,
+            _ => panic!("expected {} to decode to an order", line),
+        }
+    }
+
+    #[test]
+    fn test_execute_requests() {
+        let order = decode_order(
+            r#"{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Limit", "price": 100500, "quantity": 10}"#,
+        );
+        assert_eq!((order.account_id, order.symbol.ticker(), order.side), (1, "GOOG", OrderSide::Buy));
+        assert_eq!((order.order_type, order.quantity), (OrderType::Limit(100500), 10));
+        assert_eq!((order.time_in_force, order.self_trade_prevention), (TimeInForce::GoodTillCancel, None));
+
+        let order = decode_order(
+            r#"{"command": "Execute", "symbol": "GOOG", "side": "Sell", "order_type": "StopLimit", "stop_price": 95000,
+                "price": 94000, "quantity": 5, "time_in_force": "Day", "self_trade_prevention": "CancelBoth"}"#,
+        );
+        assert_eq!(order.order_type, OrderType::StopLimit(95000, 94000));
+        assert_eq!((order.time_in_force, order.self_trade_prevention), (TimeInForce::Day, Some(SelfTradePrevention::CancelBoth)));
+        assert_eq!(order.client_order_id, None);
+
+        let order = decode_order(
+            r#"{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Market", "quantity": 1, "client_order_id": 11}"#,
+        );
+        assert_eq!(order.client_order_id, Some(11));
+    }
+
+    #[test]
+    fn test_order_id_requests() {
+        assert!(matches!(decode(r#"{"command": "Status", "order_id": 3}"#), Ok(Cmd::Status(_))));
+        assert!(matches!(decode(r#"{"command": "Cancel", "order_id": 3}"#), Ok(Cmd::Cancel(_))));
+        match decode(r#"{"command": "Cancel", "client_order_id": 11}"#) {
+            Ok(Cmd::Cancel(cancel_info)) => assert_eq!(cancel_info.consume().1, OrderRef::Client(11)),
+            _ => panic!("expected a cancel"),
+        }
+        match decode(r#"{"command": "Replace", "order_id": 3, "quantity": 20}"#) {
+            Ok(Cmd::Replace(replace_info)) => {
+                let (account_id, order_id, price, quantity, _) = replace_info.consume();
+                assert_eq!((account_id, order_id, price, quantity), (1, 3, None, Some(20)));
+            }
+            _ => panic!("expected a replace"),
+        }
+    }
+
+    #[test]
+    fn test_bad_requests_are_rejected() {
+        let rejection = |line: &str| decode(line).err().unwrap();
+        assert_eq!(rejection("not json"), RejectReason::InvalidJson);
+        assert_eq!(rejection(r#"{"command": "Pnl"}"#), RejectReason::InvalidJson);
+        assert_eq!(rejection(r#"{"command": "Status"}"#), RejectReason::InvalidJson);
+        assert_eq!(rejection(r#"{"command": "Status", "order_id": 3, "client_order_id": 11}"#), RejectReason::InvalidJson);
+        assert_eq!(rejection(r#"{"command": "Cancel", "client_order_id": 0}"#), RejectReason::InvalidClientOrderId);
+        assert_eq!(
+            rejection(r#"{"command": "Execute", "symbol": "GOOG", "side": "Up", "order_type": "Market", "quantity": 1}"#),
+            RejectReason::InvalidJson
+        );
+        assert_eq!(
+            rejection(r#"{"command": "Execute", "symbol": "ZZZZ", "side": "Buy", "order_type": "Market", "quantity": 1}"#),
+            RejectReason::UnknownTicker
+        );
+        assert_eq!(
+            rejection(r#"{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Limit", "quantity": 1}"#),
+            RejectReason::InvalidPrice
+        );
+        assert_eq!(
+            rejection(r#"{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Market", "quantity": 0}"#),
+            RejectReason::InvalidQuantity
+        );
+        assert_eq!(rejection(r#"{"command": "Replace", "order_id": 3, "price": 1000000001}"#), RejectReason::InvalidPrice);
+    }
+
+    #[test]
+    fn test_statuses_to_json() {
+        let parse = |status: OrderStatus| -> Value { serde_json::from_str(&status_to_json(&StatusReport::new(status, None))).unwrap() };
+        assert_eq!(parse(OrderStatus::Filled(3, 1000)), json!({"status": "Filled", "order_id": 3, "cost": 1000}));
+        assert_eq!(
+            parse(OrderStatus::Rejected(3, RejectReason::NotOwner)),
+            json!({"status": "Rejected", "order_id": 3, "code": 14, "reason": "order belongs to another account"})
+        );
+
+        let report = ExecutionReport {
+            execution_id: 7,
+            order_id: 3,
+            price: 100,
+            quantity: 2,
+            leaves_quantity: 8,
+            cumulative_quantity: 2,
+            average_price: 100,
+            liquidity: Liquidity::Maker,
+            timestamp: 42,
+        };
+        let execution = parse(OrderStatus::Execution(report));
+        assert_eq!(execution["status"], "Execution");
+        assert_eq!(execution["liquidity"], "Maker");
+        assert_eq!(execution["leaves_quantity"], 8);
+        assert!(!status_to_json(&StatusReport::new(OrderStatus::Execution(report), None)).contains('\n'));
+
+        let waiting: Value = serde_json::from_str(&status_to_json(&StatusReport::new(OrderStatus::Waiting(3), Some(11)))).unwrap();
+        assert_eq!(waiting, json!({"status": "Waiting", "order_id": 3, "client_order_id": 11}));
+    }
+
+    #[test]
+    fn test_price_info_to_json() {
+        let price_info = PriceInfo::new(SYMBOLS.get("GOOG").unwrap(), 100500, 10, 101000, 20);
+        let quote: Value = serde_json::from_str(&price_info_to_json(&price_info)).unwrap();
+        assert_eq!(
+            quote,
+            json!({"market_data": "Quote", "symbol": "GOOG", "best_bid": 100500, "bid_size": 10, "best_ask": 101000, "ask_size": 20})
+        );
+    }
+
+    #[test]
+    fn test_auth_responses_to_json() {
+        let authenticated: Value = serde_json::from_str(&auth_response_to_json(Ok(1))).unwrap();
+        assert_eq!(authenticated, json!({"status": "Authenticated", "account_id": 1}));
+        let rejected: Value = serde_json::from_str(&auth_response_to_json(Err("invalid username or password"))).unwrap();
+        assert_eq!(rejected, json!({"status": "AuthRejected", "reason": "invalid username or password"}));
+    }
+}
@@ -0,0 +1,18 @@
+// client library of the exchange: the types and wire formats shared with the exchange itself,
+// and typed clients for order entry and market data built on them
+extern crate byteorder;
+extern crate getset;
+extern crate reliudp;
+extern crate serde;
+extern crate sha2;
+#[cfg(test)]
+extern crate proptest;
+
+// all the types that will be shared across implementations
+pub mod types;
+
+// binary wire formats of requests, responses and market data
+pub mod codec;
+
+// clients for the order entry gateway and the market data feed
+pub mod client;
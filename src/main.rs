@@ -4,43 +4,110 @@ extern crate getset;
 extern crate byteorder;
 extern crate reliudp;
 extern crate serde;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+extern crate tungstenite;
+#[cfg(test)]
+extern crate proptest;
+extern crate rustyxchange;
 
 use std::{str, u32, thread};
 use std::collections::{HashSet, HashMap};
 use std::sync::mpsc::{Sender, Receiver, channel};
 use std::io::{BufRead, BufReader};
 use std::fs::File;
+use serde::Deserialize;
 
-// all the types that will be shared across implementations
-mod types;
+// types and wire formats shared with clients live in the library
+use rustyxchange::{codec, types};
 use types::*;
 
 // matching engine implementation
 mod matching_engine;
 use matching_engine::process_orders;
 
+// accountant implementation, keeps track of account balances and positions
+mod accountant;
+use accountant::Accountant;
+
 // market data implementation
 mod market_data;
 use market_data::MarketDataProvider;
 
+// newline delimited json version of the order sending protocol
+mod json_protocol;
+
 // exchange gateway implementation
 mod gateway;
 use gateway::Gateway;
 
+// FIX 4.4 order entry gateway, next to the exchange gateway
+mod fix;
+mod fix_gateway;
+use fix_gateway::FixGateway;
+
+// websocket version of the json protocol, for browser clients
+mod websocket_gateway;
+use websocket_gateway::WebSocketGateway;
+
 const GATEWAY_IP: &'static str = "0.0.0.0";
 const GATEWAY_PORT: u32 = 8888;
+const FIX_GATEWAY_IP: &'static str = "0.0.0.0";
+const FIX_GATEWAY_PORT: u32 = 9878;
+// SenderCompID of the exchange, which FIX sessions log on to as their TargetCompID
+const FIX_COMP_ID: &'static str = "RUSTYX";
+// directory the sequence numbers of FIX sessions are kept in
+const FIX_STORE_DIR: &'static str = "fix_sessions";
+const WEBSOCKET_GATEWAY_IP: &'static str = "0.0.0.0";
+const WEBSOCKET_GATEWAY_PORT: u32 = 8080;
 const MARKET_DATA_IP: &'static str = "0.0.0.0";
 const MARKET_DATA_PORT: u32  = 4567;
+// number of price levels per side in the depth of book feed
+const MARKET_DATA_DEPTH: usize = 10;
 const ACCOUNTS_FILE : &'static str = "accounts.json";
 const SYMBOLS_FILE : &'static str = "symbols.txt";
+// day orders expire at 20:00 UTC (4pm New York), given in seconds after midnight UTC
+const SESSION_CLOSE: u64 = 20 * 60 * 60;
 
 lazy_static! {
     pub static ref ACCOUNTS: HashMap<String, Account> = load_user_accounts(ACCOUNTS_FILE);
     pub static ref SYMBOLS: HashMap<String, Symbol> = load_symbols(SYMBOLS_FILE);
 }
 
-fn load_user_accounts(_filename : &str) -> HashMap<String, Account> {
-    return HashMap::new();
+/// an entry in the accounts file
+#[derive(Deserialize)]
+struct AccountRecord {
+    id: u32,
+    username: String,
+    password_hash: String,
+    salt: String,
+    initial_balance: u64,
+    #[serde(default)]
+    positions: HashMap<String, i64>,
+    #[serde(default)]
+    self_trade_prevention: Option<SelfTradePrevention>,
+}
+
+fn load_user_accounts(filename : &str) -> HashMap<String, Account> {
+    let rdr = BufReader::new(File::open(filename).expect("[ERROR] couldn't open accounts file"));
+    let records: Vec<AccountRecord> = serde_json::from_reader(rdr).expect("[ERROR] couldn't parse accounts file");
+    let mut accounts: HashMap<String, Account> = HashMap::new();
+    for record in records {
+        println!("[INFO] loaded account {} with id {}", record.username, record.id);
+        let account = Account::new(
+            record.id,
+            record.username.clone(),
+            record.password_hash,
+            record.salt,
+            record.initial_balance,
+            record.positions,
+            record.self_trade_prevention,
+        );
+        accounts.insert(record.username, account);
+    }
+
+    accounts
 }
 
 fn load_symbols(_filename : &str) -> HashMap<String, Symbol> {
@@ -58,7 +125,9 @@ fn load_symbols(_filename : &str) -> HashMap<String, Symbol> {
 fn main() {
     // create channels for orders
     let (order_sender, order_receiver): (Sender<Cmd>, Receiver<Cmd>) = channel();
-    let (md_sender, md_receiver): (Sender<PriceInfo>, Receiver<PriceInfo>) = channel();
+    let (md_sender, md_receiver): (Sender<MarketData>, Receiver<MarketData>) = channel();
+    let (fill_sender, fill_receiver): (Sender<Fill>, Receiver<Fill>) = channel();
+    let (md_subscribe_sender, md_subscribe_receiver): (Sender<Sender<PriceInfo>>, Receiver<Sender<PriceInfo>>) = channel();
 
     let mut symbols = HashSet::new();
     for symbol in SYMBOLS.values() {
@@ -67,15 +136,33 @@ fn main() {
 
     // spawn thread for matching engine, pass receiver channel into matching engine
     thread::Builder::new().name("matching_engine".to_string()).spawn(|| {
-        process_orders(md_sender, order_receiver);
+        process_orders(md_sender, fill_sender, order_receiver, SESSION_CLOSE, MARKET_DATA_DEPTH);
     }).expect("[ERROR] failed to create matching engine thread");
 
+    // spawn thread for accountant, which is sent every fill by the matching engine
+    let mut accountant = Accountant::new(fill_receiver);
+    thread::Builder::new().name("accountant".to_string()).spawn(move || {
+        accountant.run();
+    }).expect("[ERROR] failed to create accountant thread");
+
     // spawn thread for market data server
-    let mut provider = MarketDataProvider::new(MARKET_DATA_IP, MARKET_DATA_PORT, md_receiver);
+    let mut provider = MarketDataProvider::new(MARKET_DATA_IP, MARKET_DATA_PORT, md_receiver, md_subscribe_receiver);
     thread::Builder::new().name("market_data".to_string()).spawn(move || {
         provider.run();
     }).expect("[ERROR] failed to create market data thread");
 
+    // spawn thread for FIX gateway, which sends orders to the matching engine alongside the TCP gateway
+    let fix_gateway = FixGateway::new(FIX_GATEWAY_IP, FIX_GATEWAY_PORT, FIX_COMP_ID, FIX_STORE_DIR, order_sender.clone());
+    thread::Builder::new().name("fix_gateway".to_string()).spawn(move || {
+        fix_gateway.run();
+    }).expect("[ERROR] failed to create FIX gateway thread");
+
+    // spawn thread for websocket gateway, which streams market data from the market data provider to browsers
+    let websocket_gateway = WebSocketGateway::new(WEBSOCKET_GATEWAY_IP, WEBSOCKET_GATEWAY_PORT, order_sender.clone(), md_subscribe_sender);
+    thread::Builder::new().name("websocket_gateway".to_string()).spawn(move || {
+        websocket_gateway.run();
+    }).expect("[ERROR] failed to create websocket gateway thread");
+
     // initialize gateway, start TCP server
     let gateway = Gateway::new(GATEWAY_IP, GATEWAY_PORT, order_sender);
     gateway.run();
@@ -1,38 +1,198 @@
-use std::collections::HashMap;
-use byteorder::{ByteOrder, NetworkEndian, WriteBytesExt};
-use reliudp::RUdpServer;
-use std::sync::mpsc::Receiver;
+use std::collections::{HashMap, HashSet};
+use reliudp::{MessageType, RUdpServer, SocketEvent};
+use std::net::SocketAddr;
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use super::SYMBOLS;
+use codec::market_data::{
+    decode_feed_request, encode_deltas, encode_depth, encode_order_books, encode_order_updates, encode_prints, encode_quotes, Delta, Depth,
+    FeedRequest, OrderBook, OrderUpdate, Print, Quote,
+};
 use types::*;
 
+// how often the whole state of every subscribed symbol is sent, for peers that joined late or missed deltas
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the tickers a peer subscribed to on each feed
+#[derive(Debug, Default)]
+struct Subscriptions {
+    feeds: HashMap<Feed, HashSet<String>>,
+}
+
+impl Subscriptions {
+    /// adds `tickers` to `feed`, or every symbol if it's empty. unknown tickers are left out
+    fn subscribe(&mut self, feed: Feed, tickers: &[&str]) {
+        let subscribed = self.feeds.entry(feed).or_default();
+        if tickers.is_empty() {
+            subscribed.extend(SYMBOLS.keys().cloned());
+        } else {
+            subscribed.extend(tickers.iter().filter(|ticker| SYMBOLS.contains_key(**ticker)).map(|ticker| ticker.to_string()));
+        }
+    }
+
+    /// takes `tickers` off `feed`, or every symbol if it's empty
+    fn unsubscribe(&mut self, feed: Feed, tickers: &[&str]) {
+        if let Some(subscribed) = self.feeds.get_mut(&feed) {
+            if tickers.is_empty() {
+                subscribed.clear();
+            }
+            for ticker in tickers {
+                subscribed.remove(*ticker);
+            }
+        }
+    }
+
+    fn covers(&self, feed: Feed, ticker: &str) -> bool {
+        self.feeds.get(&feed).is_some_and(|subscribed| subscribed.contains(ticker))
+    }
+}
+
+/// the updates from the matching engine taken in by one call to `refresh`, each with its sequence number
+#[derive(Debug, Default)]
+struct Updates {
+    trades: Vec<(Trade, u64)>,
+    order_events: Vec<(OrderEventInfo, u64)>,
+    quotes: Vec<(PriceInfo, u64)>,
+    deltas: Vec<(DepthInfo, u64, Vec<LevelUpdate>)>,
+}
+
+impl Updates {
+    /// returns the packets carrying the updates `subscriptions` cover, trades and order events first.
+    /// feeds without any such updates get no packet
+    fn packets(&self, subscriptions: &Subscriptions) -> Vec<Vec<u8>> {
+        let prints: Vec<Print> = self
+            .trades
+            .iter()
+            .filter(|(trade, _)| subscriptions.covers(Feed::Trades, trade.get_symbol().ticker()))
+            .map(|(trade, seq)| Print::new(trade, *seq))
+            .collect();
+        let order_updates: Vec<OrderUpdate> = self
+            .order_events
+            .iter()
+            .filter(|(order_event_info, _)| subscriptions.covers(Feed::Orders, order_event_info.get_symbol().ticker()))
+            .map(|(order_event_info, seq)| OrderUpdate::new(order_event_info, *seq))
+            .collect();
+        let quotes: Vec<Quote> = self
+            .quotes
+            .iter()
+            .filter(|(price_info, _)| subscriptions.covers(Feed::TopOfBook, price_info.get_symbol().ticker()))
+            .map(|(price_info, seq)| Quote::new(price_info, *seq))
+            .collect();
+        let deltas: Vec<Delta> = self
+            .deltas
+            .iter()
+            .filter(|(depth_info, _, _)| subscriptions.covers(Feed::Depth, depth_info.get_symbol().ticker()))
+            .map(|(depth_info, seq, updates)| Delta {
+                ticker: depth_info.get_symbol().ticker(),
+                seq: *seq,
+                updates: updates.clone(),
+            })
+            .collect();
+
+        let mut packets: Vec<Vec<u8>> = Vec::new();
+        if !prints.is_empty() {
+            packets.push(encode_prints(prints));
+        }
+        if !order_updates.is_empty() {
+            packets.push(encode_order_updates(order_updates));
+        }
+        if !quotes.is_empty() {
+            packets.push(encode_quotes(quotes));
+        }
+        if !deltas.is_empty() {
+            packets.push(encode_deltas(deltas));
+        }
+        packets
+    }
+}
+
 pub struct MarketDataProvider {
     symb_to_prices: HashMap<String, PriceInfo>,
+    symb_to_depth: HashMap<String, DepthInfo>,
+    // every resting order of every symbol, rebuilt from the order events of the matching engine for snapshots
+    symb_to_orders: HashMap<String, RestingOrders>,
+    // sequence number of the last change to the top of book, depth and resting orders of every symbol,
+    // and of its last trade, counted separately
+    price_seqs: HashMap<String, u64>,
+    depth_seqs: HashMap<String, u64>,
+    order_seqs: HashMap<String, u64>,
+    trade_seqs: HashMap<String, u64>,
     ip_addr: &'static str,
     port: u32,
-    receiver: Receiver<PriceInfo>,
+    receiver: Receiver<MarketData>,
+    // other threads that want every price update send a channel for them on here
+    subscribe_receiver: Receiver<Sender<PriceInfo>>,
+    subscribers: Vec<Sender<PriceInfo>>,
+    // what every udp peer subscribed to. peers start out with nothing
+    peers: HashMap<SocketAddr, Subscriptions>,
 }
 
 impl MarketDataProvider {
-    pub fn new(ip_addr: &'static str, port: u32, receiver: Receiver<PriceInfo>) -> MarketDataProvider {
+    /// creates a new MarketDataProvider
+    ///
+    /// # Parameters
+    ///
+    /// * `ip_addr` - the address the UDP server listens on
+    /// * `port` - the port the UDP server listens on
+    /// * `receiver` - the channel market data from the matching engine arrives on
+    /// * `subscribe_receiver` - the channel subscribers send the channel they want top of book updates on
+    pub fn new(ip_addr: &'static str, port: u32, receiver: Receiver<MarketData>, subscribe_receiver: Receiver<Sender<PriceInfo>>) -> MarketDataProvider {
         let mut symb_to_prices: HashMap<String, PriceInfo> = HashMap::new();
+        let mut symb_to_depth: HashMap<String, DepthInfo> = HashMap::new();
+        let mut symb_to_orders: HashMap<String, RestingOrders> = HashMap::new();
         for (ticker,symbol) in SYMBOLS.iter() {
             symb_to_prices.insert(ticker.to_string(), PriceInfo::new(symbol, 0, 0, 0, 0));
+            symb_to_depth.insert(ticker.to_string(), DepthInfo::new(symbol, Vec::new(), Vec::new()));
+            symb_to_orders.insert(ticker.to_string(), RestingOrders::new(symbol));
         }
 
         MarketDataProvider {
             symb_to_prices: symb_to_prices,
+            symb_to_depth: symb_to_depth,
+            symb_to_orders: symb_to_orders,
+            price_seqs: HashMap::new(),
+            depth_seqs: HashMap::new(),
+            order_seqs: HashMap::new(),
+            trade_seqs: HashMap::new(),
             ip_addr: ip_addr,
             port: port,
             receiver: receiver,
+            subscribe_receiver: subscribe_receiver,
+            subscribers: Vec::new(),
+            peers: HashMap::new(),
         }
     }
 
-    pub fn update_price(&mut self, price_info: PriceInfo) {
+    /// stores the new top of the book of a symbol, returning its sequence number
+    pub fn update_price(&mut self, price_info: PriceInfo) -> u64 {
         let ticker = price_info.get_symbol().ticker();
+        let seq = self.price_seqs.entry(ticker.to_string()).or_insert(0);
+        *seq += 1;
         self.symb_to_prices
             .insert(ticker.to_string(), price_info);
+        *seq
+    }
+
+    /// stores the new depth of a symbol, returning its sequence number
+    pub fn update_depth(&mut self, depth_info: DepthInfo) -> u64 {
+        let ticker = depth_info.get_symbol().ticker();
+        let seq = self.depth_seqs.entry(ticker.to_string()).or_insert(0);
+        *seq += 1;
+        self.symb_to_depth
+            .insert(ticker.to_string(), depth_info);
+        *seq
+    }
+
+    /// applies an order event to the resting orders of its symbol, returning its sequence number
+    pub fn update_orders(&mut self, order_event_info: &OrderEventInfo) -> Option<u64> {
+        let ticker = order_event_info.get_symbol().ticker();
+        let resting_orders = self.symb_to_orders.get_mut(ticker)?;
+        resting_orders.apply(&order_event_info.event);
+        let seq = self.order_seqs.entry(ticker.to_string()).or_insert(0);
+        *seq += 1;
+        Some(*seq)
     }
 
     pub fn run(&mut self) {
@@ -41,57 +201,156 @@ impl MarketDataProvider {
             .expect("[ERROR] failed to create UDP server");
         println!("[INFO]: UDP server on {}:{}", self.ip_addr, self.port);
 
+        let mut last_snapshot: Option<Instant> = None;
         loop {
             server.next_tick().unwrap();
-            for server_event in server.drain_events() {
-                println!("Server: Incoming event {:?}", server_event);
+            // the server drops peers that are gone, sometimes before their last event is drained
+            self.peers.retain(|addr, _| server.get(*addr).is_some());
+            let events: Vec<(SocketAddr, SocketEvent)> = server.drain_events().collect();
+            for (addr, event) in events {
+                match event {
+                    SocketEvent::Data(data) => self.handle_request(&mut server, addr, &data),
+                    SocketEvent::Connected => {
+                        println!("[INFO] market data peer {} connected", addr);
+                        self.peers.entry(addr).or_default();
+                    }
+                    event => {
+                        println!("[INFO] market data peer {}: {:?}", addr, event);
+                        self.peers.remove(&addr);
+                    }
+                }
             }
 
-            self.refresh();
-
-            // ticker + bid + bid size + ask + ask size
-
-            // let mut data : [u8] = [0; 36*curr_prices.len()];
-            let mut data: Vec<u8> = vec![0; 36 * self.symb_to_prices.len()];
-            // let mut data = [0; curr_prices.len()*(4 + 8 + 8 + 8 + 8)];
-            for (ticker, price_info) in self.symb_to_prices.iter() {
-                // println!("sending {:?} for {}", price_info, ticker);
-                // set top-level market data
-                let ticker_bytes = ticker.as_bytes();
-                let num: u32 = NetworkEndian::read_u32(ticker_bytes);
-                data.write_u32::<NetworkEndian>(num).unwrap();
-                data.write_u64::<NetworkEndian>(price_info.best_bid)
-                    .unwrap();
-                data.write_u64::<NetworkEndian>(price_info.bid_size)
-                    .unwrap();
-                data.write_u64::<NetworkEndian>(price_info.best_ask)
-                    .unwrap();
-                data.write_u64::<NetworkEndian>(price_info.ask_size)
-                    .unwrap();
-
-                // NetworkEndian::write_u32(&mut data, num);
-                // NetworkEndian::write_u64(&mut data, price_info.best_bid);
-                // NetworkEndian::write_u64(&mut data, price_info.bid_size);
-                // NetworkEndian::write_u64(&mut data, price_info.best_ask);
-                // NetworkEndian::write_u64(&mut data, price_info.ask_size);
+            // changes go out as they happen, and must arrive for peers to stay in sync
+            let updates = self.refresh();
+            for (addr, subscriptions) in &self.peers {
+                send_packets(&mut server, *addr, updates.packets(subscriptions), MessageType::KeyMessage);
             }
 
-            // let data_wrapped : Arc<[u8]> = Arc::from(data.iter().cloned().map(|x| x as u8).collect::<Vec<u8>>().into_boxed_slice());
-            // println!("market data: {:?}", data);
-            let data_wrapped: Arc<[u8]> = Arc::from(data.into_boxed_slice());
-
-            server.send_data(&data_wrapped, reliudp::MessageType::KeyMessage);
+            // a lost snapshot is made up for by the next one
+            if last_snapshot.is_none_or(|last_snapshot| last_snapshot.elapsed() >= SNAPSHOT_INTERVAL) {
+                for (addr, subscriptions) in &self.peers {
+                    send_packets(&mut server, *addr, self.snapshot(&[], subscriptions), MessageType::Forgettable);
+                }
+                last_snapshot = Some(Instant::now());
+            }
             ::std::thread::sleep(::std::time::Duration::from_millis(1));
         }
     }
 
-    fn refresh(&mut self) {
+    /// answers a request `addr` sent
+    fn handle_request(&mut self, server: &mut RUdpServer, addr: SocketAddr, data: &[u8]) {
+        let request = match decode_feed_request(data) {
+            Ok(request) => request,
+            Err(e) => {
+                println!("[ERROR] bad request from market data peer {}: {}", addr, e);
+                return;
+            }
+        };
+
+        let subscriptions = self.peers.entry(addr).or_default();
+        match request {
+            FeedRequest::Snapshot(tickers) => {
+                let packets = self.snapshot(&tickers, &self.peers[&addr]);
+                send_packets(server, addr, packets, MessageType::KeyMessage);
+            }
+            FeedRequest::Subscribe(feed, tickers) => {
+                subscriptions.subscribe(feed, &tickers);
+                // the peer starts from a snapshot of what it just subscribed to
+                let mut subscribed = Subscriptions::default();
+                subscribed.subscribe(feed, &tickers);
+                send_packets(server, addr, self.snapshot(&[], &subscribed), MessageType::KeyMessage);
+            }
+            FeedRequest::Unsubscribe(feed, tickers) => subscriptions.unsubscribe(feed, &tickers),
+        }
+    }
+
+    /// returns packets holding the top of book, depth and resting orders of `tickers`, or of every symbol if it's empty,
+    /// for the feeds `subscriptions` cover them on. feeds without any such symbols get no packet
+    fn snapshot(&self, tickers: &[&str], subscriptions: &Subscriptions) -> Vec<Vec<u8>> {
+        let is_wanted = |feed: Feed, ticker: &str| (tickers.is_empty() || tickers.contains(&ticker)) && subscriptions.covers(feed, ticker);
+        let seq = |seqs: &HashMap<String, u64>, ticker: &str| seqs.get(ticker).cloned().unwrap_or(0);
+        let quotes: Vec<Quote> = self
+            .symb_to_prices
+            .iter()
+            .filter(|(ticker, _)| is_wanted(Feed::TopOfBook, ticker))
+            .map(|(ticker, price_info)| Quote::new(price_info, seq(&self.price_seqs, ticker)))
+            .collect();
+        let books: Vec<Depth> = self
+            .symb_to_depth
+            .iter()
+            .filter(|(ticker, _)| is_wanted(Feed::Depth, ticker))
+            .map(|(ticker, depth_info)| Depth::new(depth_info, seq(&self.depth_seqs, ticker)))
+            .collect();
+        let order_books: Vec<OrderBook> = self
+            .symb_to_orders
+            .iter()
+            .filter(|(ticker, _)| is_wanted(Feed::Orders, ticker))
+            .map(|(ticker, resting_orders)| OrderBook {
+                ticker: resting_orders.get_symbol().ticker(),
+                seq: seq(&self.order_seqs, ticker),
+                orders: resting_orders.events(),
+            })
+            .collect();
+
+        let mut packets: Vec<Vec<u8>> = Vec::new();
+        if !quotes.is_empty() {
+            packets.push(encode_quotes(quotes));
+        }
+        if !books.is_empty() {
+            packets.push(encode_depth(books));
+        }
+        if !order_books.is_empty() {
+            packets.push(encode_order_books(order_books));
+        }
+        packets
+    }
+
+    /// takes in the updates from the matching engine, numbering them for their feeds.
+    /// trades aren't state, so they're only ever sent here and never in snapshots
+    fn refresh(&mut self) -> Updates {
+        // new subscribers start with the current prices of every symbol
+        while let Ok(subscriber) = self.subscribe_receiver.try_recv() {
+            if self.symb_to_prices.values().all(|price_info| subscriber.send(price_info.clone()).is_ok()) {
+                self.subscribers.push(subscriber);
+            }
+        }
+
+        let mut updates = Updates::default();
         for _ in 0..100 {
-            if let Ok(new_price_info) = self.receiver.try_recv() {
-                println!("new price info: {:?}", new_price_info);
-                self.update_price(new_price_info);
+            match self.receiver.try_recv() {
+                Ok(MarketData::TopOfBook(new_price_info)) => {
+                    println!("new price info: {:?}", new_price_info);
+                    // subscribers that have gone away are dropped
+                    self.subscribers.retain(|subscriber| subscriber.send(new_price_info.clone()).is_ok());
+                    let seq = self.update_price(new_price_info.clone());
+                    updates.quotes.push((new_price_info, seq));
+                }
+                Ok(MarketData::Depth(new_depth_info)) => {
+                    let level_updates = match self.symb_to_depth.get(new_depth_info.get_symbol().ticker()) {
+                        Some(depth_info) => depth_delta(depth_info, &new_depth_info),
+                        None => continue,
+                    };
+                    let seq = self.update_depth(new_depth_info.clone());
+                    updates.deltas.push((new_depth_info, seq, level_updates));
+                }
+                Ok(MarketData::Trade(trade)) => {
+                    let seq = self.trade_seqs.entry(trade.get_symbol().ticker().to_string()).or_insert(0);
+                    *seq += 1;
+                    updates.trades.push((trade, *seq));
+                }
+                Ok(MarketData::Order(order_event_info)) => {
+                    if let Some(seq) = self.update_orders(&order_event_info) {
+                        updates.order_events.push((order_event_info, seq));
+                    }
+                }
+                // only ever made by clients
+                Ok(MarketData::Orders(_)) => {}
+                Err(_) => break,
             }
         }
+
+        updates
     }
 
     /*
@@ -109,14 +368,45 @@ impl MarketDataProvider {
     */
 }
 
+/// sends `packets` to the peer at `addr`, if it's still there
+fn send_packets(server: &mut RUdpServer, addr: SocketAddr, packets: Vec<Vec<u8>>, message_type: MessageType) {
+    if let Some(peer) = server.get_mut(addr) {
+        for packet in packets {
+            peer.send_data(Arc::from(packet.into_boxed_slice()), message_type);
+        }
+    }
+}
+
+/// returns the level updates that turn the depth `old` into `new`
+fn depth_delta(old: &DepthInfo, new: &DepthInfo) -> Vec<LevelUpdate> {
+    let mut updates: Vec<LevelUpdate> = Vec::new();
+    for &(side, old_levels, new_levels) in &[(OrderSide::Buy, &old.bids, &new.bids), (OrderSide::Sell, &old.asks, &new.asks)] {
+        for level in old_levels.iter().filter(|level| new_levels.iter().all(|new_level| new_level.price != level.price)) {
+            updates.push(LevelUpdate::new(side, level.price, 0));
+        }
+        for level in new_levels.iter().filter(|level| !old_levels.contains(level)) {
+            updates.push(LevelUpdate::new(side, level.price, level.quantity));
+        }
+    }
+    updates
+}
+
 // TODO: unit tests to make sure functions are working correctly
 #[cfg(test)]
 mod tests {
     use super::*;
-    use reliudp::SocketEvent;
-    use std::sync::mpsc::{channel, Receiver, Sender};
+    use codec::market_data::{decode_packet, encode_feed_request, Packet};
+    use std::sync::mpsc::channel;
     use std::{fs, str, thread, u32};
 
+    fn every_feed() -> Subscriptions {
+        let mut subscriptions = Subscriptions::default();
+        for feed in &[Feed::TopOfBook, Feed::Depth, Feed::Trades, Feed::Orders] {
+            subscriptions.subscribe(*feed, &[]);
+        }
+        subscriptions
+    }
+
     #[test]
     fn test_connect() {
         // let (market_data_sender, market_data_receiver): (Sender<PriceInfo>, Receiver<PriceInfo>) = channel();
@@ -142,6 +432,172 @@ mod tests {
 
         // market_data_sender.send(PriceInfo::new(Symbol::new("AAPL".to_string()),123,500,456,1500));
     }
+
+    #[test]
+    fn test_subscribers_get_every_update() {
+        let (price_sender, price_receiver) = channel();
+        let (subscribe_sender, subscribe_receiver) = channel();
+        let mut provider = MarketDataProvider::new("127.0.0.1", 0, price_receiver, subscribe_receiver);
+
+        let (subscriber, updates) = channel();
+        subscribe_sender.send(subscriber).unwrap();
+        let (gone, _) = channel();
+        subscribe_sender.send(gone).unwrap();
+        provider.refresh();
+        // a snapshot of every symbol first
+        assert_eq!(updates.try_iter().count(), SYMBOLS.len());
+        assert_eq!(provider.subscribers.len(), 1);
+
+        let goog = SYMBOLS.get("GOOG").unwrap();
+        price_sender.send(MarketData::TopOfBook(PriceInfo::new(goog, 100, 10, 101, 20))).unwrap();
+        provider.refresh();
+        let update = updates.try_recv().unwrap();
+        assert_eq!((update.get_symbol().ticker(), update.best_bid, update.ask_size), ("GOOG", 100, 20));
+
+        drop(updates);
+        price_sender.send(MarketData::TopOfBook(PriceInfo::new(goog, 100, 10, 102, 20))).unwrap();
+        provider.refresh();
+        assert!(provider.subscribers.is_empty());
+
+        // depth is kept for the udp feed, and never sent to subscribers
+        let bids = vec![PriceLevel::new(100, 10), PriceLevel::new(99, 5)];
+        price_sender.send(MarketData::Depth(DepthInfo::new(goog, bids.clone(), Vec::new()))).unwrap();
+        provider.refresh();
+        assert_eq!(provider.symb_to_depth["GOOG"].bids, bids);
+    }
+
+    #[test]
+    fn test_changes_are_sent_as_deltas() {
+        let (md_sender, md_receiver) = channel();
+        let (_, subscribe_receiver) = channel();
+        let mut provider = MarketDataProvider::new("127.0.0.1", 0, md_receiver, subscribe_receiver);
+        let goog = SYMBOLS.get("GOOG").unwrap();
+        assert!(provider.refresh().packets(&every_feed()).is_empty());
+
+        let bids = vec![PriceLevel::new(100, 10), PriceLevel::new(99, 5)];
+        md_sender.send(MarketData::Depth(DepthInfo::new(goog, bids, Vec::new()))).unwrap();
+        let bids = vec![PriceLevel::new(100, 10), PriceLevel::new(98, 1)];
+        md_sender.send(MarketData::Depth(DepthInfo::new(goog, bids.clone(), Vec::new()))).unwrap();
+        md_sender.send(MarketData::TopOfBook(PriceInfo::new(goog, 100, 10, 0, 0))).unwrap();
+        let packets = provider.refresh().packets(&every_feed());
+        assert_eq!(packets.len(), 2);
+        match decode_packet(&packets[0]).unwrap() {
+            Packet::Quotes(quotes) => assert_eq!((quotes.len(), quotes[0].ticker, quotes[0].seq, quotes[0].best_bid), (1, "GOOG", 1, 100)),
+            packet => panic!("expected quotes, got {:?}", packet),
+        }
+        // each change gets the next sequence number of the symbol
+        let buy = |price, quantity| LevelUpdate::new(OrderSide::Buy, price, quantity);
+        assert_eq!(
+            decode_packet(&packets[1]),
+            Ok(Packet::Delta(vec![
+                Delta { ticker: "GOOG", seq: 1, updates: vec![buy(100, 10), buy(99, 5)] },
+                Delta { ticker: "GOOG", seq: 2, updates: vec![buy(99, 0), buy(98, 1)] },
+            ]))
+        );
+
+        // trades of each symbol are numbered on their own, and go out before the book changes they led to
+        md_sender.send(MarketData::Trade(Trade::new(goog, 5, 100, 4, OrderSide::Sell, 0))).unwrap();
+        md_sender.send(MarketData::Trade(Trade::new(goog, 6, 100, 1, OrderSide::Sell, 0))).unwrap();
+        md_sender.send(MarketData::Depth(DepthInfo::new(goog, vec![PriceLevel::new(100, 5), PriceLevel::new(98, 1)], Vec::new()))).unwrap();
+        let packets = provider.refresh().packets(&every_feed());
+        match decode_packet(&packets[0]).unwrap() {
+            Packet::Trades(prints) => assert_eq!(prints.iter().map(|print| (print.seq, print.trade_id)).collect::<Vec<_>>(), vec![(1, 5), (2, 6)]),
+            packet => panic!("expected trades, got {:?}", packet),
+        }
+        match decode_packet(&packets[1]).unwrap() {
+            Packet::Delta(deltas) => assert_eq!((deltas[0].seq, deltas[0].updates.clone()), (3, vec![buy(100, 5)])),
+            packet => panic!("expected deltas, got {:?}", packet),
+        }
+        let bids = vec![PriceLevel::new(100, 5), PriceLevel::new(98, 1)];
+
+        // snapshots hold the latest state with its sequence number
+        let snapshot = provider.snapshot(&["GOOG"], &every_feed());
+        assert_eq!(decode_packet(&snapshot[1]), Ok(Packet::Depth(vec![Depth { ticker: "GOOG", seq: 3, bids: bids, asks: vec![] }])));
+        match decode_packet(&provider.snapshot(&[], &every_feed())[0]).unwrap() {
+            Packet::Quotes(quotes) => assert_eq!(quotes.len(), SYMBOLS.len()),
+            packet => panic!("expected quotes, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_peers_only_get_what_they_subscribed_to() {
+        let (md_sender, md_receiver) = channel();
+        let (_, subscribe_receiver) = channel();
+        let mut provider = MarketDataProvider::new("127.0.0.1", 0, md_receiver, subscribe_receiver);
+        let mut server = RUdpServer::new("127.0.0.1:0").unwrap();
+        let peer: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let goog = SYMBOLS.get("GOOG").unwrap();
+        let other = SYMBOLS.values().find(|symbol| symbol.ticker() != "GOOG").unwrap();
+
+        let request = |request: FeedRequest| encode_feed_request(&request);
+        provider.handle_request(&mut server, peer, &request(FeedRequest::Subscribe(Feed::Trades, vec!["GOOG", "NOPE"])));
+        provider.handle_request(&mut server, peer, &request(FeedRequest::Subscribe(Feed::TopOfBook, vec![])));
+        provider.handle_request(&mut server, peer, &request(FeedRequest::Unsubscribe(Feed::TopOfBook, vec!["GOOG"])));
+        let subscriptions = &provider.peers[&peer];
+        assert_eq!(subscriptions.feeds[&Feed::Trades], vec!["GOOG".to_string()].into_iter().collect());
+        assert_eq!(subscriptions.feeds[&Feed::TopOfBook].len(), SYMBOLS.len() - 1);
+
+        md_sender.send(MarketData::Trade(Trade::new(goog, 1, 100, 4, OrderSide::Sell, 0))).unwrap();
+        md_sender.send(MarketData::Trade(Trade::new(other, 2, 100, 4, OrderSide::Sell, 0))).unwrap();
+        md_sender.send(MarketData::TopOfBook(PriceInfo::new(goog, 100, 10, 0, 0))).unwrap();
+        md_sender.send(MarketData::TopOfBook(PriceInfo::new(other, 100, 10, 0, 0))).unwrap();
+        md_sender.send(MarketData::Depth(DepthInfo::new(goog, vec![PriceLevel::new(100, 10)], Vec::new()))).unwrap();
+        let packets = provider.refresh().packets(&provider.peers[&peer]);
+        assert_eq!(packets.len(), 2);
+        match (decode_packet(&packets[0]).unwrap(), decode_packet(&packets[1]).unwrap()) {
+            (Packet::Trades(prints), Packet::Quotes(quotes)) => {
+                assert_eq!(prints.iter().map(|print| print.ticker).collect::<Vec<&str>>(), vec!["GOOG"]);
+                assert_eq!(quotes.iter().map(|quote| quote.ticker).collect::<Vec<&str>>(), vec![other.ticker()]);
+            }
+            packets => panic!("expected trades and quotes, got {:?}", packets),
+        }
+        // trades have no snapshot
+        assert_eq!(provider.snapshot(&["GOOG"], &provider.peers[&peer]).len(), 0);
+
+        provider.handle_request(&mut server, peer, &request(FeedRequest::Unsubscribe(Feed::Trades, vec![])));
+        provider.handle_request(&mut server, peer, &request(FeedRequest::Unsubscribe(Feed::TopOfBook, vec![])));
+        md_sender.send(MarketData::Trade(Trade::new(goog, 3, 100, 4, OrderSide::Sell, 0))).unwrap();
+        assert!(provider.refresh().packets(&provider.peers[&peer]).is_empty());
+    }
+
+    #[test]
+    fn test_order_events_are_kept_for_snapshots() {
+        let (md_sender, md_receiver) = channel();
+        let (_, subscribe_receiver) = channel();
+        let mut provider = MarketDataProvider::new("127.0.0.1", 0, md_receiver, subscribe_receiver);
+        let goog = SYMBOLS.get("GOOG").unwrap();
+
+        let events = vec![
+            OrderEvent::Added(1, OrderSide::Buy, 100, 10),
+            OrderEvent::Added(2, OrderSide::Buy, 100, 5),
+            OrderEvent::Added(3, OrderSide::Sell, 102, 7),
+            OrderEvent::Executed(1, 4, 9),
+            OrderEvent::Replaced(1, 4, 101, 6),
+            OrderEvent::Cancelled(3, 7),
+        ];
+        for event in &events {
+            md_sender.send(MarketData::Order(OrderEventInfo::new(goog, *event))).unwrap();
+        }
+        let packets = provider.refresh().packets(&every_feed());
+        assert_eq!(packets.len(), 1);
+        match decode_packet(&packets[0]).unwrap() {
+            Packet::Orders(updates) => {
+                assert_eq!(updates.iter().map(|update| update.seq).collect::<Vec<u64>>(), vec![1, 2, 3, 4, 5, 6]);
+                assert_eq!(updates.iter().map(|update| update.event).collect::<Vec<OrderEvent>>(), events);
+            }
+            packet => panic!("expected order events, got {:?}", packet),
+        }
+
+        let snapshot = provider.snapshot(&["GOOG"], &every_feed());
+        assert_eq!(
+            decode_packet(&snapshot[2]),
+            Ok(Packet::OrderBook(vec![OrderBook {
+                ticker: "GOOG",
+                seq: 6,
+                orders: vec![OrderEvent::Added(4, OrderSide::Buy, 101, 6), OrderEvent::Added(2, OrderSide::Buy, 100, 5)],
+            }]))
+        );
+    }
 }
 
 // fn refresh_market_data(mut provider: MarketDataProvider, recv_price: &mut Receiver<PriceInfo>) -> () {
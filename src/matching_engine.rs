@@ -1,13 +1,39 @@
 use std::cmp;
 use std::error::Error;
 use std::collections::BTreeMap;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::fmt;
 
-use super::SYMBOLS;
+use super::{ACCOUNTS, SYMBOLS};
+use gateway::NO_ORDER_ID;
 use types::*;
 
+// every execution report gets its own id, unique across all order books
+static EXECUTION_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+// every trade gets its own id, unique across all order books
+static TRADE_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+// every order resting in a book gets an anonymized reference for the order by order feed, unique across all order books.
+// orders get a new one each time a replace sends them to the back of the queue
+static ORDER_REF_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// something that happened in a book while matching, to be published on the market data feeds.
+/// resting orders go by order id here, and get their anonymized references once the events are published
+#[derive(Debug, Clone, PartialEq)]
+enum MarketEvent {
+    Trade(Trade),
+    // order id, side, price and quantity of an order that came to rest at the back of its price level
+    Added(u32, OrderSide, u64, u64),
+    // order id of a resting order, the quantity of it that traded and the id of the trade
+    Executed(u32, u64, u64),
+    // order id of a resting order and the quantity taken off it without trading
+    Cancelled(u32, u64),
+    // order id of an order a replace takes out of the book to send it through the book again
+    Replacing(u32),
+}
+
 #[derive(Debug, Clone)]
 struct InvalidOrderId;
 
@@ -74,24 +100,55 @@ struct OrderBook {
     pub asks: BTreeMap<u64, VecDeque<u32>>,
     pub market_bids: VecDeque<u32>,
     pub market_asks: VecDeque<u32>,
+    pub stop_bids: BTreeMap<u64, VecDeque<u32>>,
+    pub stop_asks: BTreeMap<u64, VecDeque<u32>>,
     orders: HashMap<u32, Order>,
+    last_trade_price: Option<u64>,
+    // number of price levels per side in the depth snapshots sent to the market data server
+    depth: usize,
+    // orders other than the incoming one that changed while matching it: filled resting orders,
+    // triggered stops and expired day orders. drained by the matching engine to notify their owners
+    touched_orders: Vec<u32>,
+    // messages for the owners of orders affected by matching, in the order they happened:
+    // an execution report per side of every fill and every self trade prevented.
+    // drained by the matching engine to send to the order owners
+    order_updates: Vec<OrderStatus>,
+    // every match and change to a resting order since the last update sent to the market data server,
+    // in the order they happened
+    market_events: Vec<MarketEvent>,
+    // anonymized reference of every resting order on the order by order feed, by order id
+    order_refs: HashMap<u32, u64>,
 }
 
 impl OrderBook {
-    fn new(symbol: &'static Symbol) -> OrderBook {
+    fn new(symbol: &'static Symbol, depth: usize) -> OrderBook {
         OrderBook {
             symbol: symbol,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             market_bids: VecDeque::new(),
             market_asks: VecDeque::new(),
+            stop_bids: BTreeMap::new(),
+            stop_asks: BTreeMap::new(),
             orders: HashMap::new(),
+            last_trade_price: None,
+            depth: depth,
+            touched_orders: Vec::new(),
+            order_updates: Vec::new(),
+            market_events: Vec::new(),
+            order_refs: HashMap::new(),
         }
     }
 
-    // order priority:
-    //  1. market > limit
-    //  2. timestamp
+    // matching rules (price-time priority):
+    //  1. an incoming order trades with the best priced opposite order first,
+    //     the lowest ask for buys and the highest bid for sells
+    //  2. orders resting at the same price trade oldest first
+    //  3. every trade happens at the resting order's price, except with waiting market orders (see 5)
+    //  4. limit orders only trade at their limit price or better, and the remainder rests in the book
+    //  5. market orders take any price, and the remainder waits in a market order queue.
+    //     incoming limit orders trade with waiting market orders once no opposite limit order is left at their
+    //     limit price or better, at the limit price
 
     pub fn print_book(&self) -> () {
         let ticker = self.symbol.ticker();
@@ -114,14 +171,6 @@ impl OrderBook {
                 println!("order: {:?}", self.orders.get(&order_id).unwrap());
             }
         }
-        println!("{} new market order bids: ", ticker);
-        for order_id in self.market_bids.iter() {
-            println!("order: {:?}", self.orders.get(&order_id).unwrap());
-        }
-        println!("{} new market order asks: ", ticker);
-        for order_id in self.market_asks.iter() {
-            println!("order: {:?}", self.orders.get(&order_id).unwrap());
-        }
     }
 
     pub fn status(&self, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
@@ -129,6 +178,20 @@ impl OrderBook {
         Ok (order.get_status_based_on_fill())
     }
 
+    /// returns every order update produced since the last call, in the order they happened
+    pub fn drain_order_updates(&mut self) -> Vec<OrderStatus> {
+        self.order_updates.drain(..).collect()
+    }
+
+    /// returns the ids of every order touched since the last call, each id once, in the order they were touched
+    pub fn drain_touched_orders(&mut self) -> Vec<u32> {
+        let mut seen = HashSet::new();
+        self.touched_orders
+            .drain(..)
+            .filter(|order_id| seen.insert(*order_id))
+            .collect()
+    }
+
     fn delete_empty_price_levels_generic(map: &mut BTreeMap<u64,VecDeque<u32>>) {
         let mut prices_to_delete = Vec::new();
         for (price, order_lst) in map.iter() {
@@ -144,6 +207,8 @@ impl OrderBook {
     pub fn delete_empty_price_levels(&mut self) {
         OrderBook::delete_empty_price_levels_generic(&mut self.bids);
         OrderBook::delete_empty_price_levels_generic(&mut self.asks);
+        OrderBook::delete_empty_price_levels_generic(&mut self.stop_bids);
+        OrderBook::delete_empty_price_levels_generic(&mut self.stop_asks);
     }
 
     fn delete_bid_price_level(&mut self, price: u64) {
@@ -162,6 +227,7 @@ impl OrderBook {
         let order = self.orders.get(&order_id).expect("invalid order id in remove()");
         match order.order_type {
             OrderType::Limit(price) => {
+                self.market_events.push(MarketEvent::Cancelled(order_id, order.remaining_quantity));
                 match order.side {
                     OrderSide::Buy => {
                         self.bids
@@ -187,7 +253,15 @@ impl OrderBook {
                     self.market_asks.retain(|x| *x != order_id);
                 }
             },
-            _ => {}
+            OrderType::Stop(price) | OrderType::StopLimit(price, _) => {
+                let stop_orders = match order.side {
+                    OrderSide::Buy => &mut self.stop_bids,
+                    OrderSide::Sell => &mut self.stop_asks,
+                };
+                if let Some(order_lst) = stop_orders.get_mut(&price) {
+                    order_lst.retain(|x| *x != order_id);
+                }
+            }
         };
         return ();
     }
@@ -196,7 +270,7 @@ impl OrderBook {
         println!("orders = {:?}", self.orders);
     }
 
-    pub fn cancel(&mut self, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
+    pub fn cancel(&mut self, order_id: u32, send: Sender<MarketData>) -> Result<OrderStatus, Box<dyn Error>> {
         self.print_orders();
         let order = self.orders.get_mut(&order_id).ok_or(InvalidOrderId)?;
         println!("cancelling order = {:?}", order);
@@ -204,14 +278,72 @@ impl OrderBook {
             return self.status(order_id);
         }
         order.is_canceled = true;
+        let top_level = self.get_top_level();
+        let depth = self.get_depth();
         let order = self.orders.get(&order_id).ok_or(InvalidOrderId)?;
         self.remove_order(order.id);        
 
         self.delete_empty_price_levels();
+        self.send_market_data_if_changed(top_level, depth, send);
         self.print_book();
         return self.status(order_id)
     }
 
+    /// changes the price and/or total quantity of an open order.
+    /// `price` replaces the price the order rests at: the limit price of limit orders and the stop price of stops.
+    /// stop limit orders keep their limit price, only their stop price can be changed.
+    /// a smaller quantity at the same price keeps the order's place in the queue,
+    /// while a new price or a larger quantity sends it to the back of the queue at its (new) price,
+    /// matching it against the book first if the new price crosses
+    pub fn replace(&mut self, order_id: u32, price: Option<u64>, quantity: Option<u64>, send: Sender<MarketData>) -> Result<OrderStatus, Box<dyn Error>> {
+        let order = self.orders.get(&order_id).ok_or(InvalidOrderId)?;
+        if order.is_canceled || order.is_fully_filled() {
+            return Ok(OrderStatus::Rejected(order_id, RejectReason::OrderNotOpen));
+        }
+
+        let filled = order.quantity - order.remaining_quantity;
+        let new_quantity = quantity.unwrap_or(order.quantity);
+        if new_quantity <= filled {
+            return Ok(OrderStatus::Rejected(order_id, RejectReason::QuantityBelowFilled));
+        }
+        let (old_price, new_order_type) = match (order.order_type, price) {
+            (OrderType::Limit(old), new) => (old, OrderType::Limit(new.unwrap_or(old))),
+            (OrderType::Stop(old), new) => (old, OrderType::Stop(new.unwrap_or(old))),
+            (OrderType::StopLimit(old, limit), new) => (old, OrderType::StopLimit(new.unwrap_or(old), limit)),
+            (OrderType::Market, _) => return Ok(OrderStatus::Rejected(order_id, RejectReason::MarketNotReplaceable)),
+        };
+        let new_price = price.unwrap_or(old_price);
+        let loses_priority = new_price != old_price || new_quantity > order.quantity;
+
+        let top_level = self.get_top_level();
+        let depth = self.get_depth();
+        if !loses_priority {
+            let order = self.orders.get_mut(&order_id).unwrap();
+            if let OrderType::Limit(_) = order.order_type {
+                let reduced_by = order.quantity - new_quantity;
+                if reduced_by > 0 {
+                    self.market_events.push(MarketEvent::Cancelled(order_id, reduced_by));
+                }
+            }
+            order.quantity = new_quantity;
+            order.remaining_quantity = new_quantity - filled;
+            self.send_market_data_if_changed(top_level, depth, send);
+            return Ok(OrderStatus::Replaced(order_id, new_price, new_quantity));
+        }
+
+        // take the order out and send it through the book again, keeping its id and fills
+        self.market_events.push(MarketEvent::Replacing(order_id));
+        self.remove_order(order_id);
+        self.delete_empty_price_levels();
+        let mut order = self.orders.remove(&order_id).unwrap();
+        order.quantity = new_quantity;
+        order.remaining_quantity = new_quantity - filled;
+        order.order_type = new_order_type;
+        // market data is compared against the book from before the order was taken out
+        self.add_order(order, top_level, depth, send)?;
+        Ok(OrderStatus::Replaced(order_id, new_price, new_quantity))
+    }
+
     fn get_top_level(&self) -> (u64, u64, u64, u64) {
         let (best_bid, best_bid_size) : (u64, u64) = match self.bids.len() != 0 {
             true => {
@@ -239,47 +371,313 @@ impl OrderBook {
 
         return (best_bid, best_bid_size, best_ask, best_ask_size);
     }
+
+    /// returns the best `depth` price levels of each side of the book, with the quantity left at each
+    fn get_depth(&self) -> DepthInfo {
+        let level = |(price, order_lst): (&u64, &VecDeque<u32>)| {
+            PriceLevel::new(*price, order_lst.iter().map(|o| self.orders[o].remaining_quantity).sum())
+        };
+        DepthInfo::new(
+            self.symbol,
+            self.bids.iter().rev().take(self.depth).map(level).collect(),
+            self.asks.iter().take(self.depth).map(level).collect(),
+        )
+    }
     //TODO: one problem we need to deal with is making appropiate variables mutable in Order struct
-    pub fn order(&mut self, old_order: &Order, send: Sender<PriceInfo>) -> Result<OrderStatus, Box<dyn Error>> {
+    pub fn order(&mut self, old_order: &Order, send: Sender<MarketData>) -> Result<OrderStatus, Box<dyn Error>> {
         // self.orders.insert(old_order.id, old_order.clone());
         // let order : &mut Order = self.orders.get_mut(&old_order.id).unwrap();
-        let mut order = old_order.clone();
-        let (best_bid, best_bid_size, best_ask, best_ask_size) = self.get_top_level();  
+        let top_level = self.get_top_level();
+        let depth = self.get_depth();
+        self.add_order(old_order.clone(), top_level, depth, send)
+    }
 
+    /// matches `order` against the book and rests what is left of it, sending the market data that changed since
+    /// `top_level` and `depth` were taken
+    fn add_order(&mut self, mut order: Order, top_level: (u64, u64, u64, u64), depth: DepthInfo, send: Sender<MarketData>) -> Result<OrderStatus, Box<dyn Error>> {
         println!("filling order...");
-        let order_status = match order.order_type {
-            OrderType::Market => self.market_order(&mut order),
-            OrderType::Limit(price) => self.limit_order(&mut order, price),
+        let mut order_status = match order.order_type {
             OrderType::Stop(price) => self.stop_order(&mut order, price),
+            OrderType::StopLimit(price, _) => self.stop_order(&mut order, price),
+            _ => self.match_order(&mut order),
         };
-        println!("inserting order id = {}", order.id);
-        self.orders.insert(order.id, order);
+        let order_id = order.id;
+        println!("inserting order id = {}", order_id);
+        self.orders.insert(order_id, order);
+
+        if self.cancel_unfilled_remainder(order_id) {
+            order_status = self.status(order_id)?;
+        }
+
+        // trades from this order may have moved the last price through resting stops
+        self.trigger_stop_orders();
 
         println!("done filling order!");
 
         self.delete_empty_price_levels();
+        self.send_market_data_if_changed(top_level, depth, send);
+
+        return Ok(order_status)
+    }
 
+    /// sends the trades and order events made since the last call to the market data server, followed by the new
+    /// top of the book and depth if they moved since `old_top_level` and `old_depth` were taken
+    fn send_market_data_if_changed(&mut self, old_top_level: (u64, u64, u64, u64), old_depth: DepthInfo, send: Sender<MarketData>) {
+        // trades come before the book changes they led to
+        self.publish_market_events(&send);
+
+        let (best_bid, best_bid_size, best_ask, best_ask_size) = old_top_level;
         let (new_best_bid, new_best_bid_size, new_best_ask, new_best_ask_size) = self.get_top_level();  
 
-        if new_best_bid > best_bid
-            || new_best_ask < best_ask
+        if new_best_bid != best_bid
+            || new_best_ask != best_ask
             || new_best_bid_size != best_bid_size
             || new_best_ask_size != best_ask_size
         {
-            send.send(PriceInfo::new(
+            publish(&send, MarketData::TopOfBook(PriceInfo::new(
                 self.symbol,
                 new_best_bid,
                 new_best_bid_size,
                 new_best_ask,
                 new_best_ask_size,
-            )).expect("[ERROR] failed to send price info to market data server");
+            )));
         }
 
-        return Ok(order_status)
+        let depth = self.get_depth();
+        if depth != old_depth {
+            publish(&send, MarketData::Depth(depth));
+        }
+    }
+
+    /// sends the market events made since the last call to the market data server, giving orders that come to rest
+    /// their references. an order taken out by a replace is published as replaced if it rests again,
+    /// and as cancelled otherwise
+    fn publish_market_events(&mut self, send: &Sender<MarketData>) {
+        // old reference and quantity of the orders taken out by a replace, by order id
+        let mut replacing: HashMap<u32, (u64, u64)> = HashMap::new();
+        let mut reduced_orders = Vec::new();
+        for market_event in std::mem::take(&mut self.market_events) {
+            let order_event = match market_event {
+                MarketEvent::Trade(trade) => {
+                    publish(send, MarketData::Trade(trade));
+                    continue;
+                }
+                MarketEvent::Added(order_id, side, price, quantity) => {
+                    let order_ref = ORDER_REF_COUNTER.fetch_add(1, Ordering::Relaxed);
+                    self.order_refs.insert(order_id, order_ref);
+                    match replacing.remove(&order_id) {
+                        Some((old_order_ref, _)) => OrderEvent::Replaced(old_order_ref, order_ref, price, quantity),
+                        None => OrderEvent::Added(order_ref, side, price, quantity),
+                    }
+                }
+                MarketEvent::Replacing(order_id) => {
+                    if let Some(order_ref) = self.order_refs.remove(&order_id) {
+                        replacing.insert(order_id, (order_ref, 0));
+                    }
+                    continue;
+                }
+                MarketEvent::Cancelled(order_id, quantity) if replacing.contains_key(&order_id) => {
+                    replacing.get_mut(&order_id).unwrap().1 = quantity;
+                    continue;
+                }
+                MarketEvent::Executed(order_id, quantity, trade_id) => {
+                    reduced_orders.push(order_id);
+                    match self.order_refs.get(&order_id) {
+                        Some(&order_ref) => OrderEvent::Executed(order_ref, quantity, trade_id),
+                        None => continue,
+                    }
+                }
+                MarketEvent::Cancelled(order_id, quantity) => {
+                    reduced_orders.push(order_id);
+                    match self.order_refs.get(&order_id) {
+                        Some(&order_ref) => OrderEvent::Cancelled(order_ref, quantity),
+                        None => continue,
+                    }
+                }
+            };
+            publish(send, MarketData::Order(OrderEventInfo::new(self.symbol, order_event)));
+        }
+
+        // replaced orders that traded away or were canceled instead of resting again
+        for (_, (order_ref, quantity)) in replacing {
+            publish(send, MarketData::Order(OrderEventInfo::new(self.symbol, OrderEvent::Cancelled(order_ref, quantity))));
+        }
+        // orders that are gone never rest again
+        for order_id in reduced_orders {
+            let order = &self.orders[&order_id];
+            if order.is_canceled || order.is_fully_filled() {
+                self.order_refs.remove(&order_id);
+            }
+        }
+    }
+
+    /// sends a market or limit order into the book, rejecting fill or kill orders
+    /// that can't be filled completely before any shares trade
+    fn match_order(&mut self, order: &mut Order) -> OrderStatus {
+        if order.time_in_force == TimeInForce::FillOrKill
+            && self.fillable_quantity(order) < order.remaining_quantity
+        {
+            order.is_canceled = true;
+            return OrderStatus::Rejected(order.id, RejectReason::NotEnoughLiquidity);
+        }
+
+        let order_status = match order.order_type {
+            OrderType::Limit(price) => self.limit_order(order, price),
+            _ => self.market_order(order),
+        };
+        // the newest fill sets the last trade price, which stops are triggered off
+        for update in self.order_updates.iter().rev() {
+            if let OrderStatus::Execution(report) = update {
+                self.last_trade_price = Some(report.price);
+                break;
+            }
+        }
+        order_status
+    }
+
+    /// returns the number of shares on the opposite side of the book that `order` is able to trade with.
+    /// shares from its own account don't count when self trade prevention is on
+    fn fillable_quantity(&self, order: &Order) -> u64 {
+        let (opposite_limit_orders, opposite_market_orders) = match order.side {
+            OrderSide::Buy => (&self.asks, &self.market_asks),
+            OrderSide::Sell => (&self.bids, &self.market_bids),
+        };
+        let remaining = |order_lst: &VecDeque<u32>| -> u64 {
+            order_lst
+                .iter()
+                .map(|o| self.orders.get(o).unwrap())
+                .filter(|o| order.self_trade_prevention.is_none() || o.account_id != order.account_id)
+                .map(|o| o.remaining_quantity)
+                .sum()
+        };
+
+        match order.order_type {
+            OrderType::Limit(price) => {
+                let limit_quantity: u64 = opposite_limit_orders
+                    .iter()
+                    .filter(|(opposite_price, _)| match order.side {
+                        OrderSide::Buy => **opposite_price <= price,
+                        OrderSide::Sell => **opposite_price >= price,
+                    })
+                    .map(|(_, order_lst)| remaining(order_lst))
+                    .sum();
+                limit_quantity + remaining(opposite_market_orders)
+            }
+            _ => opposite_limit_orders.values().map(remaining).sum(),
+        }
+    }
+
+    /// cancels whatever is left of an immediate or cancel / fill or kill order once it is done matching.
+    /// returns true if anything was canceled
+    fn cancel_unfilled_remainder(&mut self, order_id: u32) -> bool {
+        let order = self.orders.get_mut(&order_id).expect("invalid order id in cancel_unfilled_remainder()");
+        let is_immediate = matches!(order.time_in_force, TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill);
+        // stops only become immediate once they fire
+        let is_active = !matches!(order.order_type, OrderType::Stop(_) | OrderType::StopLimit(_, _));
+        if !is_immediate || !is_active || order.is_canceled || order.is_fully_filled() {
+            return false;
+        }
+
+        order.is_canceled = true;
+        self.remove_order(order_id);
+        true
+    }
+
+    /// cancels every day order still resting in the book, including stops that never fired
+    pub fn expire_day_orders(&mut self, send: Sender<MarketData>) {
+        let top_level = self.get_top_level();
+        let depth = self.get_depth();
+        let expired: Vec<u32> = self
+            .orders
+            .values()
+            .filter(|o| o.time_in_force == TimeInForce::Day && !o.is_canceled && !o.is_fully_filled())
+            .map(|o| o.id)
+            .collect();
+
+        for order_id in expired {
+            println!("expiring day order id = {}", order_id);
+            self.orders.get_mut(&order_id).unwrap().is_canceled = true;
+            self.remove_order(order_id);
+            self.touched_orders.push(order_id);
+        }
+
+        self.delete_empty_price_levels();
+        self.send_market_data_if_changed(top_level, depth, send);
+    }
+
+    /// returns true if a stop order on `side` at `stop_price` fires at the given last trade price
+    /// * buy stops fire once the market trades at or above the stop price
+    /// * sell stops fire once the market trades at or below the stop price
+    fn is_stop_triggered(side: OrderSide, stop_price: u64, last_trade_price: Option<u64>) -> bool {
+        match last_trade_price {
+            Some(last) => match side {
+                OrderSide::Buy => last >= stop_price,
+                OrderSide::Sell => last <= stop_price,
+            },
+            None => false,
+        }
     }
 
     pub fn stop_order(&mut self, order: &mut Order, price: u64) -> OrderStatus {
-        return OrderStatus::Waiting(order.id);
+        // the market may already be through the stop price, in which case it fires right away
+        if OrderBook::is_stop_triggered(order.side, price, self.last_trade_price) {
+            return self.fire_stop_order(order);
+        }
+
+        let stop_orders = match order.side {
+            OrderSide::Buy => &mut self.stop_bids,
+            OrderSide::Sell => &mut self.stop_asks,
+        };
+        OrderBook::list_limit_order(order, price, stop_orders);
+        OrderStatus::Waiting(order.id)
+    }
+
+    /// converts a triggered stop into the order it stands for and sends it to the book
+    /// * Stop - becomes a market order
+    /// * Stop Limit - becomes a limit order at its limit price
+    fn fire_stop_order(&mut self, order: &mut Order) -> OrderStatus {
+        match order.order_type {
+            OrderType::StopLimit(_, limit_price) => {
+                order.order_type = OrderType::Limit(limit_price);
+            }
+            _ => {
+                order.order_type = OrderType::Market;
+            }
+        };
+        self.match_order(order)
+    }
+
+    /// fires every resting stop order whose stop price has been reached by the last trade price.
+    /// triggered stops become market or limit orders, which may trade and trigger further stops,
+    /// so this keeps going until no more stops fire
+    fn trigger_stop_orders(&mut self) {
+        loop {
+            let last_trade_price = self.last_trade_price;
+            let mut triggered: Vec<u32> = Vec::new();
+            for (side, stop_orders) in [(OrderSide::Buy, &mut self.stop_bids), (OrderSide::Sell, &mut self.stop_asks)] {
+                stop_orders.retain(|stop_price, order_lst| {
+                    if OrderBook::is_stop_triggered(side, *stop_price, last_trade_price) {
+                        triggered.extend(order_lst.iter());
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+            if triggered.is_empty() {
+                break;
+            }
+
+            // order ids are handed out in arrival order, so this keeps time priority among stops
+            triggered.sort_unstable();
+            for order_id in triggered {
+                let mut order = self.orders.remove(&order_id).expect("invalid order id in trigger_stop_orders()");
+                self.fire_stop_order(&mut order);
+                self.orders.insert(order_id, order);
+                self.cancel_unfilled_remainder(order_id);
+                self.touched_orders.push(order_id);
+            }
+        }
     }
 
     pub fn limit_order(&mut self, order: &mut Order, price: u64) -> OrderStatus {
@@ -290,8 +688,11 @@ impl OrderBook {
                     price,
                     &mut self.bids,
                     &mut self.asks,
-                    &mut self.market_bids,
+                    &mut self.market_asks,
                     &mut self.orders,
+                    &mut self.touched_orders,
+                    &mut self.order_updates,
+                    &mut self.market_events,
                 )
             }
             OrderSide::Sell => {
@@ -300,8 +701,11 @@ impl OrderBook {
                     price,
                     &mut self.asks,
                     &mut self.bids,
-                    &mut self.market_asks,
+                    &mut self.market_bids,
                     &mut self.orders,
+                    &mut self.touched_orders,
+                    &mut self.order_updates,
+                    &mut self.market_events,
                 )
             }
         }
@@ -315,6 +719,9 @@ impl OrderBook {
                     &mut self.asks,
                     &mut self.market_bids,
                     &mut self.orders,
+                    &mut self.touched_orders,
+                    &mut self.order_updates,
+                    &mut self.market_events,
                 );
             }
             OrderSide::Sell => {
@@ -323,36 +730,173 @@ impl OrderBook {
                     &mut self.bids,
                     &mut self.market_asks,
                     &mut self.orders,
+                    &mut self.touched_orders,
+                    &mut self.order_updates,
+                    &mut self.market_events,
                 );
             }
         }
     }
 
+    /// fills `order` against the resting orders at a single price level, oldest first.
+    /// every fill happens at the resting price, and is recorded in `market_events` as a trade with `order` as the aggressor
+    /// and an execution of the resting order. fully filled resting orders are taken off the level. returns true if `order` has been fully filled
     fn fill_on_opposite_limit_orders_lst(
         order: &mut Order,
         price: u64,
         opposite_order_lst: &mut VecDeque<u32>,
         orders: &mut HashMap<u32, Order>,
+        touched_orders: &mut Vec<u32>,
+        order_updates: &mut Vec<OrderStatus>,
+        market_events: &mut Vec<MarketEvent>,
     ) -> bool {
-        let mut num_orders_to_remove = 0;
-        for id in opposite_order_lst.iter_mut() {
+        while let Some(id) = opposite_order_lst.front() {
             let opposite_order: &mut Order = orders.get_mut(id).unwrap();
-            let q_filled = cmp::min(order.remaining_quantity, opposite_order.quantity);
-            opposite_order.fill_shares(q_filled, price);
-            order.fill_shares(q_filled, price);
-            // if ask was filled
+
+            // the incoming order's mode decides what happens when it meets its own account
+            if opposite_order.account_id == order.account_id {
+                if let Some(mode) = order.self_trade_prevention {
+                    OrderBook::prevent_self_trade(order, opposite_order, mode, order_updates, market_events);
+                    touched_orders.push(opposite_order.id);
+                    if opposite_order.is_canceled {
+                        opposite_order_lst.pop_front();
+                    }
+                    if order.is_canceled {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let q_filled = cmp::min(order.remaining_quantity, opposite_order.remaining_quantity);
+            let timestamp = now_nanos();
+            order_updates.push(OrderStatus::Execution(opposite_order.fill_shares(
+                q_filled,
+                price,
+                EXECUTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                Liquidity::Maker,
+                timestamp,
+            )));
+            order_updates.push(OrderStatus::Execution(order.fill_shares(
+                q_filled,
+                price,
+                EXECUTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+                Liquidity::Taker,
+                timestamp,
+            )));
+            let trade_id = TRADE_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            market_events.push(MarketEvent::Trade(Trade::new(
+                order.symbol,
+                trade_id,
+                price,
+                q_filled,
+                order.side,
+                timestamp,
+            )));
+            market_events.push(MarketEvent::Executed(opposite_order.id, q_filled, trade_id));
+            touched_orders.push(opposite_order.id);
+            // if the resting order was filled
             if opposite_order.is_fully_filled() {
-                num_orders_to_remove += 1;
+                opposite_order_lst.pop_front();
             }
             // if current order has been filled
             if order.is_fully_filled() {
                 break;
             }
         }
-        for _ in 0..num_orders_to_remove {
-            opposite_order_lst.pop_front();
+        return order.is_fully_filled() || order.is_canceled;
+    }
+
+    /// stops `order` from trading with `opposite_order`, which belongs to the same account, as set out by `mode`
+    fn prevent_self_trade(
+        order: &mut Order,
+        opposite_order: &mut Order,
+        mode: SelfTradePrevention,
+        order_updates: &mut Vec<OrderStatus>,
+        market_events: &mut Vec<MarketEvent>,
+    ) {
+        match mode {
+            SelfTradePrevention::CancelNewest => {
+                let quantity = order.remaining_quantity;
+                OrderBook::cancel_self_trade_shares(order, quantity, order_updates);
+            }
+            SelfTradePrevention::CancelOldest => {
+                let quantity = opposite_order.remaining_quantity;
+                OrderBook::cancel_self_trade_shares(opposite_order, quantity, order_updates);
+                market_events.push(MarketEvent::Cancelled(opposite_order.id, quantity));
+            }
+            SelfTradePrevention::CancelBoth => {
+                let (quantity, opposite_quantity) = (order.remaining_quantity, opposite_order.remaining_quantity);
+                OrderBook::cancel_self_trade_shares(order, quantity, order_updates);
+                OrderBook::cancel_self_trade_shares(opposite_order, opposite_quantity, order_updates);
+                market_events.push(MarketEvent::Cancelled(opposite_order.id, opposite_quantity));
+            }
+            SelfTradePrevention::DecrementAndCancel => {
+                let quantity = cmp::min(order.remaining_quantity, opposite_order.remaining_quantity);
+                OrderBook::cancel_self_trade_shares(order, quantity, order_updates);
+                OrderBook::cancel_self_trade_shares(opposite_order, quantity, order_updates);
+                market_events.push(MarketEvent::Cancelled(opposite_order.id, quantity));
+            }
+        }
+    }
+
+    /// takes `quantity` open shares off `order` without trading them, canceling the order once none are left
+    fn cancel_self_trade_shares(order: &mut Order, quantity: u64, order_updates: &mut Vec<OrderStatus>) {
+        if quantity == order.remaining_quantity {
+            // canceled orders keep their remaining quantity, as with a regular cancel
+            order.is_canceled = true;
+        } else {
+            order.quantity -= quantity;
+            order.remaining_quantity -= quantity;
+        }
+        order_updates.push(OrderStatus::SelfTradePrevented(order.id, quantity));
+    }
+
+    /// fills `order` against the opposite side of the book in price-time priority.
+    /// levels are walked from the best price outwards (lowest ask first for buys, highest bid first for sells),
+    /// stopping at the first level that is worse than `limit_price`. market orders pass `None` and take any price.
+    /// price levels emptied by the fills are removed
+    fn fill_on_opposite_limit_orders(
+        order: &mut Order,
+        limit_price: Option<u64>,
+        opposite_limit_orders: &mut BTreeMap<u64, VecDeque<u32>>,
+        orders: &mut HashMap<u32, Order>,
+        touched_orders: &mut Vec<u32>,
+        order_updates: &mut Vec<OrderStatus>,
+        market_events: &mut Vec<MarketEvent>,
+    ) {
+        let opposite_prices: Vec<u64> = match order.side {
+            OrderSide::Buy => opposite_limit_orders.keys().cloned().collect(),
+            OrderSide::Sell => opposite_limit_orders.keys().rev().cloned().collect(),
+        };
+
+        for opposite_price in opposite_prices {
+            let crosses = match (order.side, limit_price) {
+                (_, None) => true,
+                (OrderSide::Buy, Some(price)) => opposite_price <= price,
+                (OrderSide::Sell, Some(price)) => opposite_price >= price,
+            };
+            if !crosses {
+                break;
+            }
+
+            let opposite_order_lst = opposite_limit_orders.get_mut(&opposite_price).unwrap();
+            let is_done = OrderBook::fill_on_opposite_limit_orders_lst(
+                order,
+                opposite_price,
+                opposite_order_lst,
+                orders,
+                touched_orders,
+                order_updates,
+                market_events,
+            );
+            if opposite_order_lst.is_empty() {
+                opposite_limit_orders.remove(&opposite_price);
+            }
+            if is_done {
+                break;
+            }
         }
-        return order.is_fully_filled();
     }
 
     fn list_limit_order(
@@ -369,154 +913,382 @@ impl OrderBook {
             .push_back(order.id);
     }
 
+    /// matches a limit order against every opposite order priced at or better than `price_per_share`, then against the
+    /// waiting opposite market orders at `price_per_share`, and lists whatever is left at the back of its own price level.
+    /// every opposite limit order left by then is priced worse than `price_per_share`, so the waiting market orders
+    /// never get a better price than the book offers the incoming order
+    #[allow(clippy::too_many_arguments)]
     fn limit_order_generic(
         order: &mut Order,
         price_per_share: u64,
         same_side_limit_orders: &mut BTreeMap<u64, VecDeque<u32>>,
         opposite_limit_orders: &mut BTreeMap<u64, VecDeque<u32>>,
-        market_orders: &mut VecDeque<u32>,
+        opposite_market_orders: &mut VecDeque<u32>,
         orders: &mut HashMap<u32, Order>,
+        touched_orders: &mut Vec<u32>,
+        order_updates: &mut Vec<OrderStatus>,
+        market_events: &mut Vec<MarketEvent>,
     ) -> OrderStatus {
-        // prioritizing market orders
-        let mut num_to_remove = 0;
-        for id in market_orders.iter() {
-            let m_order = orders.get_mut(id).unwrap();
-            let q_filled = cmp::min(order.remaining_quantity, m_order.quantity);
-            m_order.fill_shares(q_filled, price_per_share);
-            order.fill_shares(q_filled, price_per_share);
-
-            if m_order.is_fully_filled() {
-                num_to_remove += 1;
-            }
-            if order.is_fully_filled() {
-                break;
-            }
-        }
-        for _ in 0..num_to_remove {
-            market_orders.pop_front();
-        }
-        if order.is_fully_filled() {
+        OrderBook::fill_on_opposite_limit_orders(
+            order,
+            Some(price_per_share),
+            opposite_limit_orders,
+            orders,
+            touched_orders,
+            order_updates,
+            market_events,
+        );
+        if order.is_fully_filled() || order.is_canceled {
             return order.get_status_based_on_fill();
         }
 
-        for (opposite_price, opposite_order_lst) in opposite_limit_orders.iter_mut() {
-            if (order.side == OrderSide::Buy && *opposite_price > price_per_share)
-                || (order.side == OrderSide::Sell && *opposite_price < price_per_share)
-            {
-                break;
-            }
-            let is_fully_filled = OrderBook::fill_on_opposite_limit_orders_lst(
-                order,
-                price_per_share,
-                opposite_order_lst,
-                orders,
-            );
-            if is_fully_filled {
-                break;
-            }
-        }
+        OrderBook::fill_on_opposite_limit_orders_lst(
+            order,
+            price_per_share,
+            opposite_market_orders,
+            orders,
+            touched_orders,
+            order_updates,
+            market_events,
+        );
 
-        if !order.is_fully_filled() {
+        if !order.is_fully_filled() && !order.is_canceled {
             OrderBook::list_limit_order(order, price_per_share, same_side_limit_orders);
+            market_events.push(MarketEvent::Added(order.id, order.side, price_per_share, order.remaining_quantity));
         }
 
         order.get_status_based_on_fill()
     }
 
-    //TODO: remove key from BTree when no orders left at that price.
+    /// matches a market order against the opposite side of the book.
+    /// whatever can't be filled waits at the back of the market order queue for incoming limit orders
     fn market_order_generic(
         order: &mut Order,
         opposite_limit_orders: &mut BTreeMap<u64, VecDeque<u32>>,
         market_orders: &mut VecDeque<u32>,
         orders: &mut HashMap<u32, Order>,
+        touched_orders: &mut Vec<u32>,
+        order_updates: &mut Vec<OrderStatus>,
+        market_events: &mut Vec<MarketEvent>,
     ) -> OrderStatus {
-        if opposite_limit_orders.len() == 0 {
+        OrderBook::fill_on_opposite_limit_orders(
+            order,
+            None,
+            opposite_limit_orders,
+            orders,
+            touched_orders,
+            order_updates,
+            market_events,
+        );
+        if !order.is_fully_filled() && !order.is_canceled {
             market_orders.push_back(order.id);
-            return OrderStatus::Waiting(order.id);
-        } else {
-            for (price, opposite_order_lst) in opposite_limit_orders.iter_mut() {
-                let is_fully_filled = OrderBook::fill_on_opposite_limit_orders_lst(
-                    order,
-                    *price,
-                    opposite_order_lst,
-                    orders,
-                );
-                if is_fully_filled {
-                    break;
-                }
-            }
-            if !order.is_fully_filled() {
-                market_orders.push_back(order.id);
-            }
-            return order.get_status_based_on_fill();
         }
+        return order.get_status_based_on_fill();
     }
 }
 
 struct MatchingEngine {
     pub order_books: HashMap<&'static str, OrderBook>,
     order_id_to_symbol: HashMap<u32, &'static Symbol>,
-    market_data_send: Sender<PriceInfo>,
+    market_data_send: Sender<MarketData>,
+    // every fill is passed on to the accountant, which keeps balances and positions up to date
+    fill_send: Sender<Fill>,
+    // channels to the owners of every order still open in the book, used to report fills they didn't initiate
+    response_senders: HashMap<u32, Sender<StatusReport>>,
+    // id of the order every (account id, client order id) pair was sent with, so client order ids stay unique per account
+    client_order_ids: HashMap<(u32, u64), u32>,
+    // unix time (in seconds) of the next session close, when day orders expire
+    next_session_close: u64,
+    // self trade prevention mode for every account that has one, used by orders that don't set their own
+    account_self_trade_prevention: HashMap<u32, SelfTradePrevention>,
+}
+
+/// returns true if an order with this status can still be filled
+fn is_open(status: &OrderStatus) -> bool {
+    matches!(status, OrderStatus::Waiting(_) | OrderStatus::PartiallyFilled(_, _, _))
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// returns the current unix time in seconds
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("[ERROR] system clock is before unix epoch")
+        .as_secs()
+}
+
+/// returns the current unix time in nanoseconds
+fn now_nanos() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("[ERROR] system clock is before unix epoch");
+    now.as_secs() * 1_000_000_000 + now.subsec_nanos() as u64
+}
+
+/// returns the first unix time after `now` that falls `session_close` seconds past midnight UTC
+fn next_session_close_after(now: u64, session_close: u64) -> u64 {
+    let close = now - now % SECONDS_PER_DAY + session_close % SECONDS_PER_DAY;
+    if close > now {
+        close
+    } else {
+        close + SECONDS_PER_DAY
+    }
 }
 
 impl MatchingEngine {
-    fn new(market_data_send: Sender<PriceInfo>) -> MatchingEngine {
+    fn new(market_data_send: Sender<MarketData>, fill_send: Sender<Fill>, session_close: u64, depth: usize) -> MatchingEngine {
         let mut order_books: HashMap<&str, OrderBook> = HashMap::new();
         for (ticker, symbol) in SYMBOLS.iter() {
             println!("saving {:?} in order books", symbol);
-            order_books.insert(ticker, OrderBook::new(symbol));
+            order_books.insert(ticker, OrderBook::new(symbol, depth));
+        }
+
+        let mut account_self_trade_prevention: HashMap<u32, SelfTradePrevention> = HashMap::new();
+        for account in ACCOUNTS.values() {
+            if let Some(mode) = account.self_trade_prevention() {
+                account_self_trade_prevention.insert(account.id(), mode);
+            }
         }
 
         let m_engine = MatchingEngine {
             order_books: order_books,
             order_id_to_symbol: HashMap::new(),
             market_data_send: market_data_send,
+            fill_send: fill_send,
+            response_senders: HashMap::new(),
+            client_order_ids: HashMap::new(),
+            next_session_close: next_session_close_after(now_secs(), session_close),
+            account_self_trade_prevention: account_self_trade_prevention,
         };
         return m_engine;
     }
 
-    fn process_order(&mut self, order: Order) -> Result<OrderStatus, Box<dyn Error>> {
+    /// expires day orders in every book once the session close has passed
+    fn close_session_if_due(&mut self, now: u64) {
+        if now < self.next_session_close {
+            return;
+        }
+        println!("[INFO] session closed, expiring day orders");
+        let tickers: Vec<&'static str> = self.order_books.keys().cloned().collect();
+        for ticker in tickers {
+            self.order_books.get_mut(ticker).unwrap().expire_day_orders(self.market_data_send.clone());
+            self.notify_touched_orders(ticker);
+        }
+        self.next_session_close = next_session_close_after(now, self.next_session_close);
+    }
+
+    /// sends `status` to the owner of `order_id`, forgetting the owner's channel if it has been dropped
+    fn send_to_owner(&mut self, order_id: u32, status: OrderStatus) {
+        let report = StatusReport::new(status, self.client_order_id(order_id));
+        let is_delivered = match self.response_senders.get(&order_id) {
+            Some(sender) => sender.send(report).is_ok(),
+            None => return,
+        };
+        if !is_delivered {
+            println!("[ERROR] failed to send status of order id = {} to its owner", order_id);
+            self.response_senders.remove(&order_id);
+        }
+    }
+
+    /// tells the accountant about the fill in `report`
+    fn send_fill(&self, ticker: &str, report: &ExecutionReport) {
+        let order = &self.order_books[ticker].orders[&report.order_id];
+        let fill = Fill {
+            account_id: order.account_id,
+            symbol: order.symbol,
+            side: order.side,
+            price: report.price,
+            quantity: report.quantity,
+        };
+        if self.fill_send.send(fill).is_err() {
+            println!("[ERROR] failed to send fill for order {} to the accountant, it has stopped", report.order_id);
+        }
+    }
+
+    /// sends every order update (execution reports and prevented self trades) produced in `ticker`'s book,
+    /// followed by the current status of every touched order, to the owners of those orders.
+    /// every fill is also passed on to the accountant.
+    /// owners of orders that are done (filled or canceled) aren't notified again afterwards
+    fn notify_touched_orders(&mut self, ticker: &str) {
+        let (order_updates, touched_orders) = match self.order_books.get_mut(ticker) {
+            Some(order_book) => (order_book.drain_order_updates(), order_book.drain_touched_orders()),
+            None => return,
+        };
+
+        let mut notified_orders: Vec<u32> = Vec::new();
+        for update in order_updates {
+            if let OrderStatus::Execution(report) = update {
+                self.send_fill(ticker, &report);
+            }
+            self.send_to_owner(update.order_id(), update);
+            notified_orders.push(update.order_id());
+        }
+        for order_id in touched_orders {
+            if let Ok(status) = self.order_books.get(ticker).unwrap().status(order_id) {
+                self.send_to_owner(order_id, status);
+            }
+            notified_orders.push(order_id);
+        }
+
+        let order_book = self.order_books.get(ticker).unwrap();
+        for order_id in notified_orders {
+            let is_done = match order_book.status(order_id) {
+                Ok(status) => !is_open(&status),
+                Err(_) => true,
+            };
+            if is_done {
+                self.response_senders.remove(&order_id);
+            }
+        }
+    }
+
+    fn process_order(&mut self, mut order: Order, response_sender: Sender<StatusReport>) -> Result<OrderStatus, Box<dyn Error>> {
         // market orders are executed immediately if possible, otherwise added to queue
         // limit orders are added to queue and executed when the price is reached and its turn comes in queue
         // TODO: how to implement stop orders?
         println!("processing symbol for {:?}", order);
+        if let Some(client_order_id) = order.client_order_id {
+            if self.client_order_ids.contains_key(&(order.account_id, client_order_id)) {
+                return Ok(OrderStatus::Rejected(order.id, RejectReason::DuplicateClientOrderId));
+            }
+        }
+        // orders that don't set a self trade prevention mode use their account's
+        if order.self_trade_prevention.is_none() {
+            order.self_trade_prevention = self.account_self_trade_prevention.get(&order.account_id).cloned();
+        }
+        // the owner's channel is needed for the order's own execution reports, and while it rests in the book
+        self.response_senders.insert(order.id, response_sender);
         let ret = match self.order_books.get_mut(order.symbol.ticker()) {
             Some(order_book) => {
                 println!("inserting order {:?} into order book for {:?}", order, order.symbol);
-                Ok(order_book.order(&order, self.market_data_send.clone()))
+                order_book.order(&order, self.market_data_send.clone())
             },
-            None => Err("symbol does not exist in process_order()"),
+            None => Err(InvalidTicker.into()),
         };
         match ret {
-            Ok(_) => {
+            Ok(ref status) => {
                 self.order_id_to_symbol
                     .insert(order.id, order.symbol);
+                if let Some(client_order_id) = order.client_order_id {
+                    self.client_order_ids.insert((order.account_id, client_order_id), order.id);
+                }
+                // orders that are done without trading or preventing a self trade won't show up in
+                // any order update, so nothing will be sent to their owner from here on
+                let has_updates = self.order_books[order.symbol.ticker()]
+                    .order_updates
+                    .iter()
+                    .any(|update| update.order_id() == order.id);
+                if !is_open(status) && !has_updates {
+                    self.response_senders.remove(&order.id);
+                }
+            }
+            _ => {
+                self.response_senders.remove(&order.id);
             }
-            _ => {}
         }
 
-        return ret.unwrap();
+        ret
+    }
+
+    /// returns the id of the order `order` refers to. client order ids are only looked up among `account_id`'s orders
+    fn find_order(&self, account_id: u32, order: OrderRef) -> Result<u32, Box<dyn Error>> {
+        match order {
+            OrderRef::Exchange(order_id) => Ok(order_id),
+            OrderRef::Client(client_order_id) => Ok(*self.client_order_ids.get(&(account_id, client_order_id)).ok_or(InvalidOrderId)?),
+        }
+    }
+
+    /// returns the client order id `order_id` was sent with, if it has one
+    fn client_order_id(&self, order_id: u32) -> Option<u64> {
+        let ticker = self.order_id_to_symbol.get(&order_id)?.ticker();
+        self.order_books.get(ticker)?.orders.get(&order_id)?.client_order_id
+    }
+
+    /// wraps the response to a command from `account_id` about `order`. the client order id of another account's order
+    /// is never sent back, only the one the command named the order by
+    fn report(&self, account_id: u32, order: OrderRef, status: OrderStatus) -> StatusReport {
+        let client_order_id = match order {
+            OrderRef::Client(client_order_id) => Some(client_order_id),
+            OrderRef::Exchange(order_id) => match self.is_owner(account_id, order_id) {
+                Ok(true) => self.client_order_id(order_id),
+                _ => None,
+            },
+        };
+        StatusReport::new(status, client_order_id)
+    }
+
+    /// returns true if `order_id` was sent by `account_id`
+    fn is_owner(&self, account_id: u32, order_id: u32) -> Result<bool, Box<dyn Error>> {
+        let ticker = self.order_id_to_symbol.get(&order_id).ok_or(InvalidOrderId)?.ticker();
+        let order_book = self.order_books.get(ticker).ok_or(InvalidTicker)?;
+        let order = order_book.orders.get(&order_id).ok_or(InvalidOrderId)?;
+        Ok(order.account_id == account_id)
     }
 
-    fn status(&self, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
+    fn status(&self, account_id: u32, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
+        if !self.is_owner(account_id, order_id)? {
+            return Ok(OrderStatus::Rejected(order_id, RejectReason::NotOwner));
+        }
         let ticker = self.order_id_to_symbol.get(&order_id).ok_or(InvalidOrderId)?.ticker();
         let order_book = self.order_books.get(ticker).ok_or(InvalidTicker)?;
         order_book.status(order_id)
     }
 
-    fn cancel(&mut self, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
+    fn replace(&mut self, account_id: u32, order_id: u32, price: Option<u64>, quantity: Option<u64>) -> Result<OrderStatus, Box<dyn Error>> {
+        if !self.is_owner(account_id, order_id)? {
+            return Ok(OrderStatus::Rejected(order_id, RejectReason::NotOwner));
+        }
         let ticker = self.order_id_to_symbol.get(&order_id).ok_or(InvalidOrderId)?.ticker();
         let order_book = self.order_books.get_mut(ticker).ok_or(InvalidTicker)?;
-        order_book.cancel(order_id)
+        order_book.replace(order_id, price, quantity, self.market_data_send.clone())
+    }
+
+    fn cancel(&mut self, account_id: u32, order_id: u32) -> Result<OrderStatus, Box<dyn Error>> {
+        if !self.is_owner(account_id, order_id)? {
+            return Ok(OrderStatus::Rejected(order_id, RejectReason::NotOwner));
+        }
+        let ticker = self.order_id_to_symbol.get(&order_id).ok_or(InvalidOrderId)?.ticker();
+        let order_book = self.order_books.get_mut(ticker).ok_or(InvalidTicker)?;
+        let status = order_book.cancel(order_id, self.market_data_send.clone())?;
+        if !is_open(&status) {
+            self.response_senders.remove(&order_id);
+        }
+        Ok(status)
+    }
+}
+
+/// sends `market_data` to the market data server. a server that has stopped is only logged, like a client in `respond`
+fn publish(send: &Sender<MarketData>, market_data: MarketData) {
+    if send.send(market_data).is_err() {
+        println!("[ERROR] failed to send market data, the market data server has stopped");
     }
 }
 
-pub fn process_orders(market_data_send: Sender<PriceInfo>, recv: Receiver<Cmd>) {
+/// sends `status` back to the client that sent a command.
+/// a client that has gone away is only logged, it never takes the matching engine down with it
+fn respond(sender: &Sender<StatusReport>, report: StatusReport) {
+    if sender.send(report).is_err() {
+        println!("[ERROR] failed to send {:?} to client, it has disconnected", report.status);
+    }
+}
+
+/// runs the matching engine on commands from `recv`
+///
+/// # Arguments
+///
+/// * `market_data_send` - channel for top of book and depth updates to the market data server
+/// * `recv` - channel of commands coming from the gateway
+/// * `session_close` - time of day the session closes at, in seconds after midnight UTC
+/// * `depth` - number of price levels per side in the depth updates, 0 for none
+pub fn process_orders(market_data_send: Sender<MarketData>, fill_send: Sender<Fill>, recv: Receiver<Cmd>, session_close: u64, depth: usize) {
     // let order_book = self.order_books.get(order.symbol);
-    let mut matching_engine: MatchingEngine = MatchingEngine::new(market_data_send.clone());
+    let mut matching_engine: MatchingEngine = MatchingEngine::new(market_data_send.clone(), fill_send, session_close, depth);
     let mut ORDER_ID_COUNTER: u32 = 0 as u32;
     // TODO: handle errors
     loop {
+        matching_engine.close_session_if_due(now_secs());
+
         // let order_info = recv.recv();
         // matching_engine.process_order(Order {})
         for _ in 0..1000 {
@@ -524,33 +1296,919 @@ pub fn process_orders(market_data_send: Sender<PriceInfo>, recv: Receiver<Cmd>)
                 match cmd {
                     Cmd::Execute(order_info) => {
                         let (order, sender) = order_info.consume(ORDER_ID_COUNTER);
+                        let order_id = order.id;
+                        let client_order_id = order.client_order_id;
                         ORDER_ID_COUNTER += 1;
 
                         let ticker = order.symbol.ticker().clone();
-                        let status = matching_engine.process_order(order).unwrap();
-                        sender
-                            .send(status)
-                            .expect("[ERROR]: EXECUTE failed to send client status to client");
-                        matching_engine.order_books.get(ticker).unwrap().print_book();
+                        let status = matching_engine
+                            .process_order(order, sender.clone())
+                            .unwrap_or(OrderStatus::Rejected(order_id, RejectReason::UnknownTicker));
+                        // tell the resting side (and any triggered stops) about their fills, and the aggressor about
+                        // each of its own, before its final status
+                        matching_engine.notify_touched_orders(ticker);
+                        respond(&sender, StatusReport::new(status, client_order_id));
+                        if let Some(order_book) = matching_engine.order_books.get(ticker) {
+                            order_book.print_book();
+                        }
+                    }
+                    Cmd::Replace(replace_info) => {
+                        let (account_id, order_id, price, quantity, sender) = replace_info.consume();
+                        let status = matching_engine
+                            .replace(account_id, order_id, price, quantity)
+                            .unwrap_or(OrderStatus::Rejected(order_id, RejectReason::UnknownOrderId));
+                        // a new price can cross the book, so there may be fills to report first
+                        if let Some(symbol) = matching_engine.order_id_to_symbol.get(&order_id) {
+                            matching_engine.notify_touched_orders(symbol.ticker());
+                        }
+                        respond(&sender, matching_engine.report(account_id, OrderRef::Exchange(order_id), status));
                     }
                     Cmd::Status(status_info) => {
-                        let (account_id, order_id, sender) = status_info.consume();
-                        // TODO: need method for getting status
-                        let status = matching_engine.status(order_id).expect("[ERROR] failed to get status of order");
-                        sender
-                            .send(status)
-                            .expect("[ERROR]: STATUS failed to send client status to client");
+                        let (account_id, order, sender) = status_info.consume();
+                        // an unknown client order id has no order id to reject with
+                        let order_id = matching_engine.find_order(account_id, order).unwrap_or(NO_ORDER_ID);
+                        let status = matching_engine
+                            .status(account_id, order_id)
+                            .unwrap_or(OrderStatus::Rejected(order_id, RejectReason::UnknownOrderId));
+                        respond(&sender, matching_engine.report(account_id, order, status));
                     }
                     Cmd::Cancel(cancel_info) => {
-                        let (account_id, order_id, sender) = cancel_info.consume();
-                        // TODO: need method for canceling
-                        let status = matching_engine.cancel(order_id).unwrap();
-                        sender
-                            .send(status)
-                            .expect("[ERROR]: CANCEL failed to send client status to client");
+                        let (account_id, order, sender) = cancel_info.consume();
+                        let order_id = matching_engine.find_order(account_id, order).unwrap_or(NO_ORDER_ID);
+                        let status = matching_engine
+                            .cancel(account_id, order_id)
+                            .unwrap_or(OrderStatus::Rejected(order_id, RejectReason::UnknownOrderId));
+                        respond(&sender, matching_engine.report(account_id, order, status));
                     }
                 };
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::sync::mpsc::channel;
+
+    fn new_order(id: u32, order_type: OrderType, side: OrderSide, quantity: u64) -> Order {
+        new_order_tif(id, order_type, side, TimeInForce::GoodTillCancel, quantity)
+    }
+
+    fn new_order_tif(id: u32, order_type: OrderType, side: OrderSide, time_in_force: TimeInForce, quantity: u64) -> Order {
+        new_account_order(id, 0, order_type, side, time_in_force, None, quantity)
+    }
+
+    fn new_account_order(
+        id: u32,
+        account_id: u32,
+        order_type: OrderType,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        self_trade_prevention: Option<SelfTradePrevention>,
+        quantity: u64,
+    ) -> Order {
+        let (sender, _) = channel();
+        let symbol = SYMBOLS.get("GOOG").unwrap();
+        let (order, _) = OrderInfo::new(account_id, symbol, order_type, side, time_in_force, self_trade_prevention, quantity, None, sender).consume(id);
+        order
+    }
+
+    fn new_stp_order(id: u32, account_id: u32, price: u64, side: OrderSide, mode: Option<SelfTradePrevention>, quantity: u64) -> Order {
+        new_account_order(id, account_id, OrderType::Limit(price), side, TimeInForce::GoodTillCancel, mode, quantity)
+    }
+
+    fn new_engine() -> (MatchingEngine, Receiver<MarketData>, Receiver<Fill>) {
+        let (md_send, md_recv) = channel();
+        let (fill_send, fill_recv) = channel();
+        (MatchingEngine::new(md_send, fill_send, 0, 10), md_recv, fill_recv)
+    }
+
+    fn submit(book: &mut OrderBook, order: Order) -> OrderStatus {
+        let (send, _recv) = channel();
+        book.order(&order, send).unwrap()
+    }
+
+    fn cancel(book: &mut OrderBook, order_id: u32) -> OrderStatus {
+        let (send, _recv) = channel();
+        book.cancel(order_id, send).unwrap()
+    }
+
+    #[test]
+    fn test_stop_order_rests_until_triggered() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        let status = submit(&mut book, new_order(0, OrderType::Stop(105), OrderSide::Buy, 10));
+        assert_eq!(status, OrderStatus::Waiting(0));
+        assert_eq!(book.stop_bids.get(&105).unwrap().len(), 1);
+
+        // a trade below the stop price leaves the stop untouched
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Sell, 5));
+        submit(&mut book, new_order(2, OrderType::Limit(100), OrderSide::Buy, 5));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+
+        // resting liquidity for the stop once it fires
+        submit(&mut book, new_order(3, OrderType::Limit(110), OrderSide::Sell, 20));
+
+        // a trade at the stop price fires it as a market order
+        submit(&mut book, new_order(4, OrderType::Limit(105), OrderSide::Sell, 5));
+        submit(&mut book, new_order(5, OrderType::Limit(105), OrderSide::Buy, 5));
+        assert!(book.stop_bids.is_empty());
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Filled(0, 10 * 110));
+        assert_eq!(book.orders.get(&0).unwrap().order_type, OrderType::Market);
+    }
+
+    #[test]
+    fn test_stop_order_triggers_immediately_when_through_market() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Buy, 15));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Sell, 5));
+
+        let status = submit(&mut book, new_order(2, OrderType::Stop(101), OrderSide::Sell, 10));
+        assert_eq!(status, OrderStatus::Filled(2, 10 * 100));
+        assert!(book.stop_asks.is_empty());
+    }
+
+    #[test]
+    fn test_stop_limit_order_becomes_limit_when_triggered() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        let status = submit(&mut book, new_order(0, OrderType::StopLimit(105, 107), OrderSide::Buy, 10));
+        assert_eq!(status, OrderStatus::Waiting(0));
+        assert_eq!(book.stop_bids.get(&105).unwrap().len(), 1);
+
+        // only 4 shares are offered at or below the limit price
+        submit(&mut book, new_order(1, OrderType::Limit(107), OrderSide::Sell, 4));
+        submit(&mut book, new_order(2, OrderType::Limit(110), OrderSide::Sell, 20));
+
+        // trade at 105 fires the stop, which fills up to its limit and rests the remainder
+        submit(&mut book, new_order(3, OrderType::Limit(105), OrderSide::Sell, 5));
+        submit(&mut book, new_order(4, OrderType::Limit(105), OrderSide::Buy, 5));
+        assert!(book.stop_bids.is_empty());
+        assert_eq!(book.status(0).unwrap(), OrderStatus::PartiallyFilled(0, 4, 4 * 107));
+        assert_eq!(book.orders.get(&0).unwrap().order_type, OrderType::Limit(107));
+        assert_eq!(book.bids.get(&107).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_stop_order() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Stop(95), OrderSide::Sell, 10));
+        assert_eq!(cancel(&mut book, 0), OrderStatus::Canceled(0));
+        assert!(book.stop_asks.is_empty());
+
+        // the canceled stop never fires
+        submit(&mut book, new_order(1, OrderType::Limit(90), OrderSide::Buy, 5));
+        submit(&mut book, new_order(2, OrderType::Limit(90), OrderSide::Sell, 5));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Canceled(0));
+    }
+
+    #[test]
+    fn test_immediate_or_cancel_cancels_remainder() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Sell, 4));
+
+        let status = submit(&mut book, new_order_tif(1, OrderType::Limit(100), OrderSide::Buy, TimeInForce::ImmediateOrCancel, 10));
+        assert_eq!(status, OrderStatus::Canceled(1));
+        assert_eq!(book.orders.get(&1).unwrap().remaining_quantity, 6);
+        assert!(book.bids.is_empty());
+        assert!(book.asks.is_empty());
+
+        // nothing to trade with, so a market ioc never rests either
+        submit(&mut book, new_order_tif(2, OrderType::Market, OrderSide::Buy, TimeInForce::ImmediateOrCancel, 10));
+        assert_eq!(book.status(2).unwrap(), OrderStatus::Canceled(2));
+        assert!(book.market_bids.is_empty());
+    }
+
+    #[test]
+    fn test_fill_or_kill_rejects_without_touching_book() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Sell, 4));
+        submit(&mut book, new_order(1, OrderType::Limit(102), OrderSide::Sell, 4));
+
+        // only 4 shares at or below 101
+        let status = submit(&mut book, new_order_tif(2, OrderType::Limit(101), OrderSide::Buy, TimeInForce::FillOrKill, 5));
+        assert_eq!(status, OrderStatus::Rejected(2, RejectReason::NotEnoughLiquidity));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+        assert!(book.bids.is_empty());
+
+        let status = submit(&mut book, new_order_tif(3, OrderType::Limit(102), OrderSide::Buy, TimeInForce::FillOrKill, 8));
+        assert_eq!(status, OrderStatus::Filled(3, 4 * 100 + 4 * 102));
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_expire_day_orders() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order_tif(0, OrderType::Limit(100), OrderSide::Buy, TimeInForce::Day, 10));
+        submit(&mut book, new_order_tif(1, OrderType::Stop(90), OrderSide::Sell, TimeInForce::Day, 10));
+        submit(&mut book, new_order(2, OrderType::Limit(99), OrderSide::Buy, 10));
+
+        let (send, recv) = channel();
+        book.expire_day_orders(send);
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Canceled(0));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Canceled(1));
+        assert_eq!(book.status(2).unwrap(), OrderStatus::Waiting(2));
+        assert!(book.stop_asks.is_empty());
+        assert_eq!(book.bids.keys().collect::<Vec<_>>(), vec![&99]);
+
+        // the expired limit order left the book, and the best bid moved from 100 to 99
+        assert!(matches!(recv.try_recv().unwrap(), MarketData::Order(ref order_event_info) if matches!(order_event_info.event, OrderEvent::Cancelled(_, 10))));
+        match recv.try_recv().unwrap() {
+            MarketData::TopOfBook(price_info) => assert_eq!(price_info.best_bid, 99),
+            update => panic!("expected a top of book update, got {:?}", update),
+        }
+    }
+
+    #[test]
+    fn test_next_session_close_after() {
+        let close = 20 * 60 * 60;
+        let midnight = 10 * SECONDS_PER_DAY;
+        assert_eq!(next_session_close_after(midnight, close), midnight + close);
+        assert_eq!(next_session_close_after(midnight + close, close), midnight + SECONDS_PER_DAY + close);
+        assert_eq!(next_session_close_after(midnight + close + 1, close), midnight + SECONDS_PER_DAY + close);
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(99), OrderSide::Buy, 5));
+        submit(&mut book, new_order(1, OrderType::Limit(101), OrderSide::Buy, 5));
+        submit(&mut book, new_order(2, OrderType::Limit(101), OrderSide::Buy, 5));
+        submit(&mut book, new_order(3, OrderType::Limit(100), OrderSide::Buy, 5));
+
+        // best price first, then oldest first within the price, each at the resting price
+        let status = submit(&mut book, new_order(4, OrderType::Limit(99), OrderSide::Sell, 12));
+        assert_eq!(status, OrderStatus::Filled(4, 10 * 101 + 2 * 100));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Filled(1, 5 * 101));
+        assert_eq!(book.status(2).unwrap(), OrderStatus::Filled(2, 5 * 101));
+        assert_eq!(book.status(3).unwrap(), OrderStatus::PartiallyFilled(3, 2, 2 * 100));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+        assert!(!book.bids.contains_key(&101));
+    }
+
+    #[test]
+    fn test_partially_filled_resting_order_is_not_overfilled() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Sell, 10));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Buy, 6));
+
+        // only 4 shares are left on the resting order
+        let status = submit(&mut book, new_order(2, OrderType::Limit(100), OrderSide::Buy, 10));
+        assert_eq!(status, OrderStatus::PartiallyFilled(2, 4, 4 * 100));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Filled(0, 10 * 100));
+        assert_eq!(book.bids.get(&100).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_market_order_waits_for_liquidity() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Sell, 3));
+
+        let status = submit(&mut book, new_order(1, OrderType::Market, OrderSide::Buy, 5));
+        assert_eq!(status, OrderStatus::PartiallyFilled(1, 3, 3 * 100));
+        assert_eq!(book.market_bids, VecDeque::from(vec![1]));
+
+        // a later sell trades with the waiting market order first, at its limit price
+        submit(&mut book, new_order(2, OrderType::Limit(101), OrderSide::Sell, 5));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Filled(1, 3 * 100 + 2 * 101));
+        assert!(book.market_bids.is_empty());
+        assert_eq!(book.status(2).unwrap(), OrderStatus::PartiallyFilled(2, 2, 2 * 101));
+        assert_eq!(book.get_top_level(), (0, 0, 101, 3));
+
+        // canceling takes a market order out of the queue
+        submit(&mut book, new_order(3, OrderType::Market, OrderSide::Sell, 4));
+        submit(&mut book, new_order(4, OrderType::Market, OrderSide::Sell, 4));
+        assert_eq!(cancel(&mut book, 3), OrderStatus::Canceled(3));
+        assert_eq!(book.market_asks, VecDeque::from(vec![4]));
+    }
+
+    #[test]
+    fn test_better_priced_limit_orders_trade_before_waiting_market_orders() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Market, OrderSide::Buy, 5));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Buy, 5));
+
+        // the bid at 100 is a better price for the sell than its own limit of 90
+        let status = submit(&mut book, new_order(2, OrderType::Limit(90), OrderSide::Sell, 8));
+        assert_eq!(status, OrderStatus::Filled(2, 5 * 100 + 3 * 90));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Filled(1, 5 * 100));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::PartiallyFilled(0, 3, 3 * 90));
+        assert_eq!(book.market_bids, VecDeque::from(vec![0]));
+    }
+
+    fn replace(book: &mut OrderBook, order_id: u32, price: Option<u64>, quantity: Option<u64>) -> OrderStatus {
+        let (send, _recv) = channel();
+        book.replace(order_id, price, quantity, send).unwrap()
+    }
+
+    #[test]
+    fn test_replace_size_decrease_keeps_priority() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Buy, 10));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Buy, 10));
+
+        assert_eq!(replace(&mut book, 0, None, Some(5)), OrderStatus::Replaced(0, 100, 5));
+        assert_eq!(book.bids.get(&100).unwrap(), &VecDeque::from(vec![0, 1]));
+
+        submit(&mut book, new_order(2, OrderType::Limit(100), OrderSide::Sell, 5));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Filled(0, 5 * 100));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Waiting(1));
+    }
+
+    #[test]
+    fn test_replace_size_increase_loses_priority() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Buy, 10));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Buy, 10));
+
+        assert_eq!(replace(&mut book, 0, None, Some(20)), OrderStatus::Replaced(0, 100, 20));
+        assert_eq!(book.bids.get(&100).unwrap(), &VecDeque::from(vec![1, 0]));
+        assert_eq!(book.get_top_level(), (100, 30, 0, 0));
+    }
+
+    #[test]
+    fn test_replace_price_moves_and_can_cross() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(101), OrderSide::Sell, 4));
+        submit(&mut book, new_order(1, OrderType::Limit(98), OrderSide::Buy, 10));
+        submit(&mut book, new_order(2, OrderType::Limit(99), OrderSide::Buy, 10));
+
+        assert_eq!(replace(&mut book, 1, Some(99), None), OrderStatus::Replaced(1, 99, 10));
+        assert!(!book.bids.contains_key(&98));
+        assert_eq!(book.bids.get(&99).unwrap(), &VecDeque::from(vec![2, 1]));
+
+        // crossing the ask trades right away, at the resting price
+        replace(&mut book, 1, Some(101), None);
+        assert_eq!(book.status(1).unwrap(), OrderStatus::PartiallyFilled(1, 4, 4 * 101));
+        assert_eq!(book.bids.get(&101).unwrap(), &VecDeque::from(vec![1]));
+    }
+
+    #[test]
+    fn test_replace_away_from_the_top_updates_market_data() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 2);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Buy, 10));
+        submit(&mut book, new_order(1, OrderType::Limit(99), OrderSide::Buy, 5));
+
+        let (send, recv) = channel();
+        book.replace(0, Some(95), None, send).unwrap();
+        let updates: Vec<MarketData> = recv.try_iter().collect();
+        assert!(updates.iter().any(|update| matches!(update, MarketData::TopOfBook(price_info) if price_info.best_bid == 99 && price_info.bid_size == 5)));
+        assert!(updates.iter().any(|update| matches!(update, MarketData::Depth(depth) if depth.bids == vec![PriceLevel::new(99, 5), PriceLevel::new(95, 10)])));
+    }
+
+    #[test]
+    fn test_replace_rejects_closed_or_overfilled_orders() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Buy, 10));
+        submit(&mut book, new_order(1, OrderType::Limit(100), OrderSide::Sell, 6));
+
+        assert_eq!(replace(&mut book, 0, None, Some(6)), OrderStatus::Rejected(0, RejectReason::QuantityBelowFilled));
+        assert_eq!(replace(&mut book, 0, None, Some(7)), OrderStatus::Replaced(0, 100, 7));
+        assert_eq!(book.orders.get(&0).unwrap().remaining_quantity, 1);
+
+        cancel(&mut book, 0);
+        assert_eq!(replace(&mut book, 0, Some(101), None), OrderStatus::Rejected(0, RejectReason::OrderNotOpen));
+        assert!(book.replace(42, None, Some(1), channel().0).is_err());
+    }
+
+    #[test]
+    fn test_depth_updates() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 2);
+        let (send, recv) = channel();
+        book.order(&new_order(0, OrderType::Limit(100), OrderSide::Buy, 10), send.clone()).unwrap();
+        book.order(&new_order(1, OrderType::Limit(100), OrderSide::Buy, 5), send.clone()).unwrap();
+        book.order(&new_order(2, OrderType::Limit(99), OrderSide::Buy, 7), send.clone()).unwrap();
+        book.order(&new_order(3, OrderType::Limit(101), OrderSide::Sell, 4), send.clone()).unwrap();
+        let depth = book.get_depth();
+        assert_eq!(depth.bids, vec![PriceLevel::new(100, 15), PriceLevel::new(99, 7)]);
+        assert_eq!(depth.asks, vec![PriceLevel::new(101, 4)]);
+        assert!(matches!(recv.try_iter().last(), Some(MarketData::Depth(ref last)) if *last == depth));
+
+        // orders below the configured depth don't change it, and are only on the order by order feed
+        book.order(&new_order(4, OrderType::Limit(98), OrderSide::Buy, 1), send.clone()).unwrap();
+        assert!(matches!(recv.try_recv().unwrap(), MarketData::Order(_)));
+        assert!(recv.try_recv().is_err());
+
+        // canceling the second level brings the third one in, leaving the top of the book alone
+        book.cancel(2, send).unwrap();
+        assert!(matches!(recv.try_recv().unwrap(), MarketData::Order(_)));
+        match recv.try_recv().unwrap() {
+            MarketData::Depth(depth) => assert_eq!(depth.bids, vec![PriceLevel::new(100, 15), PriceLevel::new(98, 1)]),
+            update => panic!("expected a depth update, got {:?}", update),
+        }
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_trades_are_published() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(101), OrderSide::Sell, 4));
+        submit(&mut book, new_order(1, OrderType::Limit(102), OrderSide::Sell, 3));
+
+        let (send, recv) = channel();
+        book.order(&new_order(2, OrderType::Limit(102), OrderSide::Buy, 5), send).unwrap();
+        let updates: Vec<MarketData> = recv.try_iter().collect();
+        let trades: Vec<&Trade> = updates
+            .iter()
+            .filter_map(|update| match update {
+                MarketData::Trade(trade) => Some(trade),
+                _ => None,
+            })
+            .collect();
+        // one trade per match, at the resting price, with the incoming order as the aggressor,
+        // sent before the book changes they led to
+        assert_eq!(trades.len(), 2);
+        assert!(matches!(updates[0], MarketData::Trade(_)));
+        let book_changed_at = updates.iter().position(|update| matches!(update, MarketData::TopOfBook(_) | MarketData::Depth(_))).unwrap();
+        assert!(updates[book_changed_at..].iter().all(|update| !matches!(update, MarketData::Trade(_))));
+        assert_eq!((trades[0].get_symbol().ticker(), trades[0].price, trades[0].quantity, trades[0].aggressor_side), ("GOOG", 101, 4, OrderSide::Buy));
+        assert_eq!((trades[1].price, trades[1].quantity, trades[1].aggressor_side), (102, 1, OrderSide::Buy));
+        assert!(trades[1].trade_id > trades[0].trade_id);
+        assert!(trades[0].timestamp > 0);
+    }
+
+    fn order_events(recv: &Receiver<MarketData>) -> Vec<OrderEvent> {
+        recv.try_iter()
+            .filter_map(|update| match update {
+                MarketData::Order(order_event_info) => Some(order_event_info.event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_order_events_are_published() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        let (send, recv) = channel();
+        book.order(&new_order(0, OrderType::Limit(101), OrderSide::Sell, 4), send.clone()).unwrap();
+        book.order(&new_order(1, OrderType::Limit(101), OrderSide::Sell, 3), send.clone()).unwrap();
+        // stops don't rest in the book until they fire
+        book.order(&new_order(2, OrderType::Stop(99), OrderSide::Sell, 3), send.clone()).unwrap();
+        let events = order_events(&recv);
+        let (first, second) = match events[..] {
+            [OrderEvent::Added(first, OrderSide::Sell, 101, 4), OrderEvent::Added(second, OrderSide::Sell, 101, 3)] => (first, second),
+            _ => panic!("unexpected order events {:?}", events),
+        };
+        assert_ne!(first, second);
+
+        // resting orders are executed oldest first
+        book.order(&new_order(3, OrderType::Limit(101), OrderSide::Buy, 5), send.clone()).unwrap();
+        let events = order_events(&recv);
+        assert!(matches!(events[..], [OrderEvent::Executed(order_ref, 4, _), OrderEvent::Executed(second_ref, 1, _)] if order_ref == first && second_ref == second));
+
+        // a smaller quantity keeps the order's reference, a new price gives it a new one
+        book.replace(1, None, Some(2), send.clone()).unwrap();
+        assert_eq!(order_events(&recv), vec![OrderEvent::Cancelled(second, 1)]);
+        book.replace(1, Some(102), None, send.clone()).unwrap();
+        let third = match order_events(&recv)[..] {
+            [OrderEvent::Replaced(order_ref, third, 102, 1)] if order_ref == second => third,
+            ref events => panic!("unexpected order events {:?}", events),
+        };
+        book.order(&new_order(4, OrderType::Limit(100), OrderSide::Buy, 5), send.clone()).unwrap();
+        order_events(&recv);
+        book.replace(1, None, Some(3), send.clone()).unwrap();
+        let fourth = match order_events(&recv)[..] {
+            [OrderEvent::Replaced(order_ref, fourth, 102, 2)] if order_ref == third => fourth,
+            ref events => panic!("unexpected order events {:?}", events),
+        };
+        // a replace that trades away is a cancel
+        book.replace(1, Some(100), None, send.clone()).unwrap();
+        let events = order_events(&recv);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], OrderEvent::Executed(_, 2, _)));
+        assert_eq!(events[1], OrderEvent::Cancelled(fourth, 2));
+        book.cancel(4, send).unwrap();
+        assert!(matches!(order_events(&recv)[..], [OrderEvent::Cancelled(_, 3)]));
+    }
+
+    fn recv_execution(recv: &Receiver<StatusReport>) -> ExecutionReport {
+        match recv.try_recv().unwrap().status {
+            OrderStatus::Execution(report) => report,
+            status => panic!("expected an execution report, got {:?}", status),
+        }
+    }
+
+    #[test]
+    fn test_passive_side_is_notified_of_fills() {
+        let (mut engine, _md_recv, _fill_recv) = new_engine();
+        let (maker_send, maker_recv) = channel();
+        let (taker_send, taker_recv) = channel();
+
+        engine.process_order(new_order(0, OrderType::Limit(100), OrderSide::Sell, 10), maker_send).unwrap();
+        engine.process_order(new_order(1, OrderType::Limit(100), OrderSide::Buy, 4), taker_send.clone()).unwrap();
+        engine.notify_touched_orders("GOOG");
+        assert_eq!(recv_execution(&maker_recv).quantity, 4);
+        assert_eq!(maker_recv.try_recv().unwrap().status, OrderStatus::PartiallyFilled(0, 4, 4 * 100));
+        // the aggressor gets its own fill, but not a second status
+        assert_eq!(recv_execution(&taker_recv).order_id, 1);
+        assert!(taker_recv.try_recv().is_err());
+        assert!(!engine.response_senders.contains_key(&1));
+
+        engine.process_order(new_order(2, OrderType::Market, OrderSide::Buy, 6), taker_send).unwrap();
+        engine.notify_touched_orders("GOOG");
+        assert_eq!(recv_execution(&maker_recv).quantity, 6);
+        assert_eq!(maker_recv.try_recv().unwrap().status, OrderStatus::Filled(0, 10 * 100));
+        assert!(!engine.response_senders.contains_key(&0));
+    }
+
+    #[test]
+    fn test_engine_survives_unknown_tickers_and_stopped_servers() {
+        let (mut engine, md_recv, fill_recv) = new_engine();
+        let mut order = new_order(0, OrderType::Limit(100), OrderSide::Buy, 10);
+        order.symbol = Box::leak(Box::new(Symbol::new("ZZZZ".to_string())));
+        assert!(engine.process_order(order, channel().0).is_err());
+        assert!(!engine.response_senders.contains_key(&0));
+
+        // trading on after the market data server and the accountant have stopped
+        drop(md_recv);
+        drop(fill_recv);
+        engine.process_order(new_order(1, OrderType::Limit(100), OrderSide::Sell, 10), channel().0).unwrap();
+        let status = engine.process_order(new_order(2, OrderType::Limit(100), OrderSide::Buy, 4), channel().0).unwrap();
+        assert_eq!(status, OrderStatus::Filled(2, 4 * 100));
+        engine.notify_touched_orders("GOOG");
+    }
+
+    #[test]
+    fn test_only_owner_can_access_order() {
+        let (mut engine, _md_recv, _fill_recv) = new_engine();
+        let (owner_send, _owner_recv) = channel();
+        engine.process_order(new_stp_order(0, 1, 100, OrderSide::Buy, None, 10), owner_send).unwrap();
+
+        let rejected = OrderStatus::Rejected(0, RejectReason::NotOwner);
+        assert_eq!(engine.status(2, 0).unwrap(), rejected);
+        assert_eq!(engine.replace(2, 0, Some(101), None).unwrap(), rejected);
+        assert_eq!(engine.cancel(2, 0).unwrap(), rejected);
+        assert_eq!(engine.status(1, 0).unwrap(), OrderStatus::Waiting(0));
+        assert_eq!(engine.order_books["GOOG"].get_top_level(), (100, 10, 0, 0));
+
+        assert_eq!(engine.cancel(1, 0).unwrap(), OrderStatus::Canceled(0));
+        assert!(engine.status(2, 42).is_err());
+    }
+
+    #[test]
+    fn test_fills_are_sent_to_accountant() {
+        let (mut engine, _md_recv, fill_recv) = new_engine();
+        engine.process_order(new_stp_order(0, 1, 100, OrderSide::Sell, None, 10), channel().0).unwrap();
+        let (taker_send, _taker_recv) = channel();
+        engine.process_order(new_stp_order(1, 2, 101, OrderSide::Buy, None, 4), taker_send).unwrap();
+        engine.notify_touched_orders("GOOG");
+
+        let symbol = SYMBOLS.get("GOOG").unwrap();
+        let fills: Vec<Fill> = fill_recv.try_iter().collect();
+        assert_eq!(fills, vec![
+            Fill { account_id: 1, symbol: symbol, side: OrderSide::Sell, price: 100, quantity: 4 },
+            Fill { account_id: 2, symbol: symbol, side: OrderSide::Buy, price: 100, quantity: 4 },
+        ]);
+    }
+
+    #[test]
+    fn test_execution_report_per_fill() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_order(0, OrderType::Limit(100), OrderSide::Sell, 4));
+        submit(&mut book, new_order(1, OrderType::Limit(102), OrderSide::Sell, 4));
+        submit(&mut book, new_order(2, OrderType::Limit(102), OrderSide::Buy, 6));
+
+        let reports: Vec<ExecutionReport> = book
+            .drain_order_updates()
+            .into_iter()
+            .map(|update| match update {
+                OrderStatus::Execution(report) => report,
+                update => panic!("expected an execution report, got {:?}", update),
+            })
+            .collect();
+        assert_eq!(reports.len(), 4);
+        let (maker, taker) = (reports[2], reports[3]);
+        assert_eq!((maker.order_id, maker.liquidity), (1, Liquidity::Maker));
+        assert_eq!((maker.price, maker.quantity, maker.leaves_quantity, maker.cumulative_quantity), (102, 2, 2, 2));
+        assert_eq!((taker.order_id, taker.liquidity), (2, Liquidity::Taker));
+        assert_eq!((taker.price, taker.quantity, taker.leaves_quantity, taker.cumulative_quantity), (102, 2, 0, 6));
+        assert_eq!(taker.average_price, (4 * 100 + 2 * 102) / 6);
+        assert_eq!(maker.timestamp, taker.timestamp);
+
+        // every report has its own id
+        let mut execution_ids: Vec<u64> = reports.iter().map(|r| r.execution_id).collect();
+        execution_ids.dedup();
+        assert_eq!(execution_ids.len(), 4);
+        assert!(book.drain_order_updates().is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_cancel_newest() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_stp_order(0, 1, 100, OrderSide::Sell, None, 10));
+        let status = submit(&mut book, new_stp_order(1, 1, 100, OrderSide::Buy, Some(SelfTradePrevention::CancelNewest), 4));
+        assert_eq!(status, OrderStatus::Canceled(1));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+        assert_eq!(book.drain_order_updates(), vec![OrderStatus::SelfTradePrevented(1, 4)]);
+        assert_eq!(book.get_top_level(), (0, 0, 100, 10));
+
+        // without a mode the account trades with itself
+        let status = submit(&mut book, new_stp_order(2, 1, 100, OrderSide::Buy, None, 4));
+        assert_eq!(status, OrderStatus::Filled(2, 4 * 100));
+    }
+
+    #[test]
+    fn test_self_trade_cancel_oldest_keeps_matching() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_stp_order(0, 1, 100, OrderSide::Sell, None, 5));
+        submit(&mut book, new_stp_order(1, 2, 100, OrderSide::Sell, None, 5));
+        let status = submit(&mut book, new_stp_order(2, 1, 100, OrderSide::Buy, Some(SelfTradePrevention::CancelOldest), 8));
+        assert_eq!(status, OrderStatus::PartiallyFilled(2, 5, 5 * 100));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Canceled(0));
+        assert_eq!(book.status(1).unwrap(), OrderStatus::Filled(1, 5 * 100));
+        assert_eq!(book.get_top_level(), (100, 3, 0, 0));
+    }
+
+    #[test]
+    fn test_self_trade_cancel_both() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_stp_order(0, 1, 100, OrderSide::Sell, None, 5));
+        let status = submit(&mut book, new_stp_order(1, 1, 100, OrderSide::Buy, Some(SelfTradePrevention::CancelBoth), 3));
+        assert_eq!(status, OrderStatus::Canceled(1));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Canceled(0));
+        assert_eq!(
+            book.drain_order_updates(),
+            vec![OrderStatus::SelfTradePrevented(1, 3), OrderStatus::SelfTradePrevented(0, 5)]
+        );
+        assert!(book.asks.is_empty() && book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_decrement_and_cancel() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_stp_order(0, 1, 100, OrderSide::Sell, None, 10));
+        let status = submit(&mut book, new_stp_order(1, 1, 100, OrderSide::Buy, Some(SelfTradePrevention::DecrementAndCancel), 4));
+        assert_eq!(status, OrderStatus::Canceled(1));
+        let resting = book.orders.get(&0).unwrap();
+        assert_eq!((resting.quantity, resting.remaining_quantity), (6, 6));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+        assert_eq!(book.get_top_level(), (0, 0, 100, 6));
+    }
+
+    #[test]
+    fn test_fill_or_kill_ignores_own_shares_with_self_trade_prevention() {
+        let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+        submit(&mut book, new_stp_order(0, 1, 100, OrderSide::Sell, None, 5));
+        submit(&mut book, new_stp_order(1, 2, 100, OrderSide::Sell, None, 5));
+        let order = new_account_order(
+            2, 1, OrderType::Limit(100), OrderSide::Buy, TimeInForce::FillOrKill, Some(SelfTradePrevention::CancelOldest), 10,
+        );
+        assert_eq!(submit(&mut book, order), OrderStatus::Rejected(2, RejectReason::NotEnoughLiquidity));
+        assert_eq!(book.status(0).unwrap(), OrderStatus::Waiting(0));
+    }
+
+    #[test]
+    fn test_account_self_trade_prevention_default_and_owner_notified() {
+        let (mut engine, _md_recv, _fill_recv) = new_engine();
+        engine.account_self_trade_prevention.insert(1, SelfTradePrevention::CancelOldest);
+        let (maker_send, maker_recv) = channel();
+        let (taker_send, taker_recv) = channel();
+
+        engine.process_order(new_stp_order(0, 1, 100, OrderSide::Sell, None, 5), maker_send).unwrap();
+        let status = engine.process_order(new_stp_order(1, 1, 100, OrderSide::Buy, None, 5), taker_send).unwrap();
+        assert_eq!(status, OrderStatus::Waiting(1));
+        engine.notify_touched_orders("GOOG");
+        assert_eq!(maker_recv.try_recv().unwrap().status, OrderStatus::SelfTradePrevented(0, 5));
+        assert_eq!(maker_recv.try_recv().unwrap().status, OrderStatus::Canceled(0));
+        assert!(taker_recv.try_recv().is_err());
+        assert!(!engine.response_senders.contains_key(&0));
+    }
+
+    #[test]
+    fn test_triggered_stop_owner_is_notified() {
+        let (mut engine, _md_recv, _fill_recv) = new_engine();
+        let (stop_send, stop_recv) = channel();
+        let (other_send, _other_recv) = channel();
+
+        engine.process_order(new_order(0, OrderType::Stop(100), OrderSide::Buy, 5), stop_send).unwrap();
+        engine.process_order(new_order(1, OrderType::Limit(100), OrderSide::Sell, 15), other_send.clone()).unwrap();
+        engine.process_order(new_order(2, OrderType::Limit(100), OrderSide::Buy, 5), other_send).unwrap();
+        engine.notify_touched_orders("GOOG");
+        assert_eq!(recv_execution(&stop_recv).liquidity, Liquidity::Taker);
+        assert_eq!(stop_recv.try_recv().unwrap().status, OrderStatus::Filled(0, 5 * 100));
+    }
+
+    fn new_client_order(id: u32, account_id: u32, price: u64, side: OrderSide, client_order_id: u64) -> Order {
+        let mut order = new_stp_order(id, account_id, price, side, None, 10);
+        order.client_order_id = Some(client_order_id);
+        order
+    }
+
+    #[test]
+    fn test_client_order_ids() {
+        let (mut engine, _md_recv, _fill_recv) = new_engine();
+        let (maker_send, maker_recv) = channel();
+        engine.process_order(new_client_order(0, 1, 100, OrderSide::Sell, 7), maker_send).unwrap();
+
+        // an id can only be used once per account, but other accounts can use it too
+        let status = engine.process_order(new_client_order(1, 1, 101, OrderSide::Sell, 7), channel().0).unwrap();
+        assert_eq!(status, OrderStatus::Rejected(1, RejectReason::DuplicateClientOrderId));
+        let status = engine.process_order(new_client_order(2, 2, 101, OrderSide::Sell, 7), channel().0).unwrap();
+        assert_eq!(status, OrderStatus::Waiting(2));
+        assert_eq!(engine.find_order(1, OrderRef::Client(7)).unwrap(), 0);
+        assert_eq!(engine.find_order(2, OrderRef::Client(7)).unwrap(), 2);
+        assert!(engine.find_order(3, OrderRef::Client(7)).is_err());
+
+        // another account never learns the client order id of an order that isn't theirs
+        assert_eq!(engine.report(1, OrderRef::Exchange(0), OrderStatus::Waiting(0)).client_order_id, Some(7));
+        let rejected = OrderStatus::Rejected(0, RejectReason::NotOwner);
+        assert_eq!(engine.report(2, OrderRef::Exchange(0), rejected).client_order_id, None);
+
+        // fills of a resting order carry its client order id
+        engine.process_order(new_stp_order(3, 3, 100, OrderSide::Buy, None, 4), channel().0).unwrap();
+        engine.notify_touched_orders("GOOG");
+        let report = maker_recv.try_recv().unwrap();
+        assert!(matches!(report.status, OrderStatus::Execution(_)));
+        assert_eq!(report.client_order_id, Some(7));
+        assert_eq!(maker_recv.try_recv().unwrap(), StatusReport::new(OrderStatus::PartiallyFilled(0, 4, 4 * 100), Some(7)));
+    }
+
+    fn arb_order() -> impl Strategy<Value = (OrderType, OrderSide, TimeInForce, u64)> {
+        let order_type = prop_oneof![
+            Just(OrderType::Market),
+            (95u64..105).prop_map(OrderType::Limit),
+            (95u64..105).prop_map(OrderType::Stop),
+            (95u64..105, 95u64..105).prop_map(|(stop, limit)| OrderType::StopLimit(stop, limit)),
+        ];
+        let side = prop_oneof![Just(OrderSide::Buy), Just(OrderSide::Sell)];
+        let time_in_force = prop_oneof![
+            Just(TimeInForce::GoodTillCancel),
+            Just(TimeInForce::Day),
+            Just(TimeInForce::ImmediateOrCancel),
+            Just(TimeInForce::FillOrKill),
+        ];
+        (order_type, side, time_in_force, 1u64..50)
+    }
+
+    /// a market order with nothing to trade with, so it waits, next to a limit order on the same side.
+    /// incoming orders on the other side then have both a waiting market order and a resting limit to trade with
+    fn arb_waiting_market_order() -> impl Strategy<Value = Vec<(OrderType, OrderSide, TimeInForce, u64)>> {
+        let side = prop_oneof![Just(OrderSide::Buy), Just(OrderSide::Sell)];
+        (side, 1u64..50, 95u64..105, 1u64..50).prop_map(|(side, market_quantity, price, limit_quantity)| {
+            vec![
+                (OrderType::Market, side, TimeInForce::GoodTillCancel, market_quantity),
+                (OrderType::Limit(price), side, TimeInForce::GoodTillCancel, limit_quantity),
+            ]
+        })
+    }
+
+    /// returns (shares bought, cost of shares bought, shares sold, cost of shares sold) across every order in the book
+    fn filled_by_side(book: &OrderBook) -> (u64, u64, u64, u64) {
+        let mut totals = (0, 0, 0, 0);
+        for order in book.orders.values() {
+            let filled = order.quantity - order.remaining_quantity;
+            match order.side {
+                OrderSide::Buy => {
+                    totals.0 += filled;
+                    totals.1 += order.cost;
+                }
+                OrderSide::Sell => {
+                    totals.2 += filled;
+                    totals.3 += order.cost;
+                }
+            }
+        }
+        totals
+    }
+
+    fn resting_orders(book: &OrderBook) -> Vec<(u64, Order)> {
+        book.bids
+            .iter()
+            .chain(book.asks.iter())
+            .flat_map(|(price, order_lst)| order_lst.iter().map(move |id| (*price, book.orders.get(id).unwrap().clone())))
+            .collect()
+    }
+
+    #[derive(Debug, Clone)]
+    enum BookAction {
+        Submit(u32, Option<SelfTradePrevention>, (OrderType, OrderSide, TimeInForce, u64)),
+        Cancel(u32),
+        Replace(u32, Option<u64>, Option<u64>),
+        Expire,
+    }
+
+    fn arb_action() -> impl Strategy<Value = BookAction> {
+        let self_trade_prevention = prop::option::of(prop_oneof![
+            Just(SelfTradePrevention::CancelNewest),
+            Just(SelfTradePrevention::CancelOldest),
+            Just(SelfTradePrevention::CancelBoth),
+            Just(SelfTradePrevention::DecrementAndCancel),
+        ]);
+        prop_oneof![
+            6 => (0u32..3, self_trade_prevention, arb_order()).prop_map(|(account_id, mode, order)| BookAction::Submit(account_id, mode, order)),
+            2 => (0u32..50).prop_map(BookAction::Cancel),
+            2 => (0u32..50, prop::option::of(95u64..105), prop::option::of(1u64..60))
+                .prop_map(|(order_id, price, quantity)| BookAction::Replace(order_id, price, quantity)),
+            1 => Just(BookAction::Expire),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_order_events_rebuild_the_book(actions in prop::collection::vec(arb_action(), 1..100)) {
+            let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+            let mut rebuilt = RestingOrders::new(SYMBOLS.get("GOOG").unwrap());
+            let (send, recv) = channel();
+            for (id, action) in actions.into_iter().enumerate() {
+                // actions on orders that don't exist or can't change are rejected, and change nothing
+                match action {
+                    BookAction::Submit(account_id, mode, (order_type, side, time_in_force, quantity)) => {
+                        let order = new_account_order(id as u32, account_id, order_type, side, time_in_force, mode, quantity);
+                        let _ = book.order(&order, send.clone());
+                    }
+                    BookAction::Cancel(order_id) => {
+                        let _ = book.cancel(order_id, send.clone());
+                    }
+                    BookAction::Replace(order_id, price, quantity) => {
+                        let _ = book.replace(order_id, price, quantity, send.clone());
+                    }
+                    BookAction::Expire => book.expire_day_orders(send.clone()),
+                }
+                for event in order_events(&recv) {
+                    rebuilt.apply(&event);
+                }
+
+                // every resting order, in priority order, under its reference
+                let expected: Vec<OrderEvent> = book.bids
+                    .iter()
+                    .rev()
+                    .map(|level| (OrderSide::Buy, level))
+                    .chain(book.asks.iter().map(|level| (OrderSide::Sell, level)))
+                    .flat_map(|(side, (price, order_lst))| order_lst.iter().map(move |id| (side, *price, *id)))
+                    .map(|(side, price, id)| OrderEvent::Added(book.order_refs[&id], side, price, book.orders[&id].remaining_quantity))
+                    .collect();
+                prop_assert_eq!(rebuilt.events(), expected);
+            }
+        }
+
+        #[test]
+        fn prop_fills_conserve_quantity_and_respect_prices(
+            waiting in arb_waiting_market_order(),
+            orders in prop::collection::vec(arb_order(), 1..100),
+        ) {
+            let mut book = OrderBook::new(SYMBOLS.get("GOOG").unwrap(), 10);
+            for (id, (order_type, side, time_in_force, quantity)) in waiting.into_iter().chain(orders).enumerate() {
+                let resting_before = resting_orders(&book);
+                let filled_before = filled_by_side(&book);
+
+                book.drain_order_updates();
+                submit(&mut book, new_order_tif(id as u32, order_type, side, time_in_force, quantity));
+
+                // the incoming order takes the best price first, waiting market orders included
+                let taker_prices: Vec<u64> = book
+                    .drain_order_updates()
+                    .into_iter()
+                    .filter_map(|update| match update {
+                        OrderStatus::Execution(report) if report.order_id == id as u32 && report.liquidity == Liquidity::Taker => Some(report.price),
+                        _ => None,
+                    })
+                    .collect();
+                for prices in taker_prices.windows(2) {
+                    match side {
+                        OrderSide::Buy => prop_assert!(prices[0] <= prices[1]),
+                        OrderSide::Sell => prop_assert!(prices[0] >= prices[1]),
+                    }
+                }
+
+                // every share bought was sold, at the same total cost
+                let filled_after = filled_by_side(&book);
+                prop_assert_eq!(filled_after.0 - filled_before.0, filled_after.2 - filled_before.2);
+                prop_assert_eq!(filled_after.1 - filled_before.1, filled_after.3 - filled_before.3);
+
+                // resting orders only ever trade at their own price
+                for (price, before) in resting_before {
+                    let after = book.orders.get(&before.id).unwrap();
+                    let filled = before.remaining_quantity - after.remaining_quantity;
+                    prop_assert_eq!(after.cost - before.cost, filled * price);
+                }
+
+                for order in book.orders.values() {
+                    prop_assert!(order.remaining_quantity <= order.quantity);
+                    // limit orders never trade worse than their limit
+                    if let OrderType::Limit(price) = order.order_type {
+                        let filled = order.quantity - order.remaining_quantity;
+                        match order.side {
+                            OrderSide::Buy => prop_assert!(order.cost <= filled * price),
+                            OrderSide::Sell => prop_assert!(order.cost >= filled * price),
+                        }
+                    }
+                }
+
+                // only live limit orders rest in the book, at their own price, and the book is never crossed
+                for (price, order) in resting_orders(&book) {
+                    prop_assert_eq!(order.order_type, OrderType::Limit(price));
+                    prop_assert!(!order.is_canceled && !order.is_fully_filled());
+                }
+                // and only live market orders wait in the market order queues
+                for order_id in book.market_bids.iter().chain(book.market_asks.iter()) {
+                    let order = book.orders.get(order_id).unwrap();
+                    prop_assert_eq!(order.order_type, OrderType::Market);
+                    prop_assert!(!order.is_canceled && !order.is_fully_filled());
+                }
+                if let (Some(best_bid), Some(best_ask)) = (book.bids.keys().next_back(), book.asks.keys().next()) {
+                    prop_assert!(best_bid < best_ask);
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use getset::{Getters};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::sync::mpsc::{Sender};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
 
 ////////////
 // TRAITS //
 ////////////
 
-pub trait FromId {
-    fn from_id(id: u8) -> Self;
+pub trait FromId: Sized {
+    /// decodes the id a client sent, rejecting ids that don't exist
+    fn from_id(id: u8) -> Result<Self, RejectReason>;
 }
 
 ///////////
@@ -18,6 +23,7 @@ pub trait FromId {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CmdType {
     Execute,
+    Replace,
     Status,
     Cancel,
     Auth,
@@ -26,36 +32,38 @@ pub enum CmdType {
 }
 
 impl FromId for CmdType {
-    // Result<CmdType, &'static' str>
-    fn from_id(id: u8) -> CmdType {
+    fn from_id(id: u8) -> Result<CmdType, RejectReason> {
         match id {
-            0 => CmdType::Execute,
-            2 => CmdType::Status,
-            3 => CmdType::Cancel,
-            _ => panic!("command id does not exist")
+            0 => Ok(CmdType::Execute),
+            1 => Ok(CmdType::Replace),
+            2 => Ok(CmdType::Status),
+            3 => Ok(CmdType::Cancel),
+            4 => Ok(CmdType::Auth),
+            _ => Err(RejectReason::UnknownCommand)
         }
     }
 }
 
 pub enum Cmd {
     Execute(OrderInfo),
+    Replace(ReplaceInfo),
     Status(StatusInfo),
     Cancel(CancelInfo)
 }
 
 /// an order can either be a buy order or sell order
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderSide {
     Buy,
     Sell
 }
 
 impl FromId for OrderSide {
-    fn from_id(id: u8) -> OrderSide {
+    fn from_id(id: u8) -> Result<OrderSide, RejectReason> {
         match id {
-            0 => OrderSide::Buy,
-            1 => OrderSide::Sell,
-            _ => panic!("order side does not exist")
+            0 => Ok(OrderSide::Buy),
+            1 => Ok(OrderSide::Sell),
+            _ => Err(RejectReason::UnknownOrderSide)
         }
     }
 }
@@ -64,44 +72,331 @@ impl FromId for OrderSide {
 /// * Market Order - buy at market price
 /// * Limit Order - only buy if price meets threshold, specify the limit price
 /// * Stop Order - converts to market when threshold reached, specify the stop price
+/// * Stop Limit Order - converts to limit when threshold reached, specify the stop price and limit price
 /// * Cancel Order - cancels a sent order
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderType {
     Market,
     Limit(u64),
-    Stop(u64)
+    Stop(u64),
+    StopLimit(u64, u64)
 }
 
 impl FromId for OrderType {
-    fn from_id(id: u8) -> OrderType {
+    fn from_id(id: u8) -> Result<OrderType, RejectReason> {
         match id {
-            0 => OrderType::Market,
-            1 => OrderType::Limit(0 as u64),
-            2 => OrderType::Stop(0 as u64),
-            _ => panic!("order type does not exist")
+            0 => Ok(OrderType::Market),
+            1 => Ok(OrderType::Limit(0 as u64)),
+            2 => Ok(OrderType::Stop(0 as u64)),
+            3 => Ok(OrderType::StopLimit(0, 0)),
+            _ => Err(RejectReason::UnknownOrderType)
         }
     }
 }
 
-/// 4 main types of statuses
+/// how long an order stays active before it is canceled
+/// * Good Till Cancel - rests in the book until filled or canceled
+/// * Day - rests in the book until the session closes
+/// * Immediate Or Cancel - fills what it can on arrival, the rest is canceled
+/// * Fill Or Kill - fills completely on arrival or is rejected without trading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    GoodTillCancel,
+    Day,
+    ImmediateOrCancel,
+    FillOrKill
+}
+
+impl FromId for TimeInForce {
+    fn from_id(id: u8) -> Result<TimeInForce, RejectReason> {
+        match id {
+            0 => Ok(TimeInForce::GoodTillCancel),
+            1 => Ok(TimeInForce::Day),
+            2 => Ok(TimeInForce::ImmediateOrCancel),
+            3 => Ok(TimeInForce::FillOrKill),
+            _ => Err(RejectReason::UnknownTimeInForce)
+        }
+    }
+}
+
+/// what to do when an order would trade with another order from the same account
+/// * Cancel Newest - cancel the incoming order
+/// * Cancel Oldest - cancel the resting order
+/// * Cancel Both - cancel both orders
+/// * Decrement And Cancel - reduce both orders by the smaller quantity, which cancels the smaller order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum SelfTradePrevention {
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel
+}
+
+impl FromId for SelfTradePrevention {
+    fn from_id(id: u8) -> Result<SelfTradePrevention, RejectReason> {
+        match id {
+            1 => Ok(SelfTradePrevention::CancelNewest),
+            2 => Ok(SelfTradePrevention::CancelOldest),
+            3 => Ok(SelfTradePrevention::CancelBoth),
+            4 => Ok(SelfTradePrevention::DecrementAndCancel),
+            _ => Err(RejectReason::UnknownSelfTradePrevention)
+        }
+    }
+}
+
+/// why a command was rejected, from malformed client data to orders the matching engine won't accept.
+/// clients are sent the reason's code along with its message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    MessageTooShort,
+    MessageTooLong,
+    UnknownCommand,
+    UnknownOrderSide,
+    UnknownOrderType,
+    UnknownTimeInForce,
+    UnknownSelfTradePrevention,
+    UnknownTicker,
+    InvalidQuantity,
+    InvalidPrice,
+    AccountMismatch,
+    AlreadyLoggedIn,
+    UnknownOrderId,
+    NotOwner,
+    OrderNotOpen,
+    QuantityBelowFilled,
+    MarketNotReplaceable,
+    NotEnoughLiquidity,
+    InvalidJson,
+    DuplicateClientOrderId,
+    InvalidClientOrderId
+}
+
+impl RejectReason {
+    /// returns the number identifying this reason on the wire
+    pub fn code(&self) -> u8 {
+        match *self {
+            RejectReason::MessageTooShort => 1,
+            RejectReason::MessageTooLong => 2,
+            RejectReason::UnknownCommand => 3,
+            RejectReason::UnknownOrderSide => 4,
+            RejectReason::UnknownOrderType => 5,
+            RejectReason::UnknownTimeInForce => 6,
+            RejectReason::UnknownSelfTradePrevention => 7,
+            RejectReason::UnknownTicker => 8,
+            RejectReason::InvalidQuantity => 9,
+            RejectReason::InvalidPrice => 10,
+            RejectReason::AccountMismatch => 11,
+            RejectReason::AlreadyLoggedIn => 12,
+            RejectReason::UnknownOrderId => 13,
+            RejectReason::NotOwner => 14,
+            RejectReason::OrderNotOpen => 15,
+            RejectReason::QuantityBelowFilled => 16,
+            RejectReason::MarketNotReplaceable => 17,
+            RejectReason::NotEnoughLiquidity => 18,
+            RejectReason::InvalidJson => 19,
+            RejectReason::DuplicateClientOrderId => 20,
+            RejectReason::InvalidClientOrderId => 21
+        }
+    }
+
+    /// returns the reason with the given code, the reverse of `code`
+    pub fn from_code(code: u8) -> Option<RejectReason> {
+        match code {
+            1 => Some(RejectReason::MessageTooShort),
+            2 => Some(RejectReason::MessageTooLong),
+            3 => Some(RejectReason::UnknownCommand),
+            4 => Some(RejectReason::UnknownOrderSide),
+            5 => Some(RejectReason::UnknownOrderType),
+            6 => Some(RejectReason::UnknownTimeInForce),
+            7 => Some(RejectReason::UnknownSelfTradePrevention),
+            8 => Some(RejectReason::UnknownTicker),
+            9 => Some(RejectReason::InvalidQuantity),
+            10 => Some(RejectReason::InvalidPrice),
+            11 => Some(RejectReason::AccountMismatch),
+            12 => Some(RejectReason::AlreadyLoggedIn),
+            13 => Some(RejectReason::UnknownOrderId),
+            14 => Some(RejectReason::NotOwner),
+            15 => Some(RejectReason::OrderNotOpen),
+            16 => Some(RejectReason::QuantityBelowFilled),
+            17 => Some(RejectReason::MarketNotReplaceable),
+            18 => Some(RejectReason::NotEnoughLiquidity),
+            19 => Some(RejectReason::InvalidJson),
+            20 => Some(RejectReason::DuplicateClientOrderId),
+            21 => Some(RejectReason::InvalidClientOrderId),
+            _ => None
+        }
+    }
+
+    /// returns a human readable explanation of this reason
+    pub fn message(&self) -> &'static str {
+        match *self {
+            RejectReason::MessageTooShort => "message is too short for its command",
+            RejectReason::MessageTooLong => "message is longer than 1024 bytes",
+            RejectReason::UnknownCommand => "command does not exist",
+            RejectReason::UnknownOrderSide => "order side does not exist",
+            RejectReason::UnknownOrderType => "order type does not exist",
+            RejectReason::UnknownTimeInForce => "time in force does not exist",
+            RejectReason::UnknownSelfTradePrevention => "self trade prevention mode does not exist",
+            RejectReason::UnknownTicker => "ticker does not exist",
+            RejectReason::InvalidQuantity => "quantity must be between 1 and 1000000000",
+            RejectReason::InvalidPrice => "price must be at most 1000000000",
+            RejectReason::AccountMismatch => "account id does not match the logged in account",
+            RejectReason::AlreadyLoggedIn => "client is already logged in",
+            RejectReason::UnknownOrderId => "order id does not exist",
+            RejectReason::NotOwner => "order belongs to another account",
+            RejectReason::OrderNotOpen => "order is no longer open",
+            RejectReason::QuantityBelowFilled => "quantity must be above the filled quantity",
+            RejectReason::MarketNotReplaceable => "market orders can't be replaced",
+            RejectReason::NotEnoughLiquidity => "not enough liquidity to fill order",
+            RejectReason::InvalidJson => "message is not a valid json command",
+            RejectReason::DuplicateClientOrderId => "client order id is already used by another order of the account",
+            RejectReason::InvalidClientOrderId => "client order id must not be 0"
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for RejectReason {}
+
+// largest quantity and price (in thousandths of a dollar) an order can have, so the value of any order fits in an i64
+pub const MAX_QUANTITY: u64 = 1_000_000_000;
+pub const MAX_PRICE: u64 = 1_000_000_000;
+
+/// returns `price` as long as it isn't too large to trade
+pub fn check_price(price: u64) -> Result<u64, RejectReason> {
+    if price > MAX_PRICE {
+        return Err(RejectReason::InvalidPrice);
+    }
+    Ok(price)
+}
+
+/// returns `quantity` as long as an order can have that many shares
+pub fn check_quantity(quantity: u64) -> Result<u64, RejectReason> {
+    if quantity == 0 || quantity > MAX_QUANTITY {
+        return Err(RejectReason::InvalidQuantity);
+    }
+    Ok(quantity)
+}
+
+/// returns `client_order_id` as long as it isn't 0, which responses use for orders without one
+pub fn check_client_order_id(client_order_id: u64) -> Result<u64, RejectReason> {
+    if client_order_id == 0 {
+        return Err(RejectReason::InvalidClientOrderId);
+    }
+    Ok(client_order_id)
+}
+
+/// which side of a match an order was on
+/// * Maker - the order was resting in the book
+/// * Taker - the order was the incoming one that crossed the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Liquidity {
+    Maker,
+    Taker
+}
+
+/// main types of statuses
 /// * Filled - all of order was matched in exchange (# of shares/quantity): order_id, price
 /// * Partially Filled - part of order was matched in exchange: order_id, quantity, price
 /// * Waiting - order has not been filled and is in order book: order_id
-/// * Rejected - order was rejected for some reason, which will be specified: order_id, message
+/// * Rejected - order was rejected for some reason, which will be specified: order_id, reason
 /// * Canceled - order was canceled: order_id
+/// * Replaced - order price and/or quantity was changed: order_id, price, quantity
+/// * Self Trade Prevented - shares of the order were canceled instead of trading with the same account: order_id, quantity
+/// * Execution - a single fill of the order: execution report
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum OrderStatus {
     Filled(u32, u64),
     PartiallyFilled(u32, u64, u64),
     Waiting(u32),
-    Rejected(u32, &'static str),
-    Canceled(u32)
+    Rejected(u32, RejectReason),
+    Canceled(u32),
+    Replaced(u32, u64, u64),
+    SelfTradePrevented(u32, u64),
+    Execution(ExecutionReport)
+}
+
+impl OrderStatus {
+    /// returns the id of the order this status is about
+    pub fn order_id(&self) -> u32 {
+        match *self {
+            OrderStatus::Filled(order_id, _) => order_id,
+            OrderStatus::PartiallyFilled(order_id, _, _) => order_id,
+            OrderStatus::Waiting(order_id) => order_id,
+            OrderStatus::Rejected(order_id, _) => order_id,
+            OrderStatus::Canceled(order_id) => order_id,
+            OrderStatus::Replaced(order_id, _, _) => order_id,
+            OrderStatus::SelfTradePrevented(order_id, _) => order_id,
+            OrderStatus::Execution(report) => report.order_id
+        }
+    }
+}
+
+/// a status sent back to a client, along with the client order id of the order it's about, if it has one
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct StatusReport {
+    pub status: OrderStatus,
+    pub client_order_id: Option<u64>
+}
+
+impl StatusReport {
+    pub fn new(status: OrderStatus, client_order_id: Option<u64>) -> StatusReport {
+        StatusReport {
+            status: status,
+            client_order_id: client_order_id
+        }
+    }
+}
+
+/// the ways a status or cancel request can name the order it's for
+/// * Exchange - the order id given out by the exchange
+/// * Client - the client order id the order was sent with, which is unique per account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderRef {
+    Exchange(u32),
+    Client(u64)
+}
+
+impl OrderRef {
+    /// returns the client order id this refers to the order by, if it does
+    pub fn client_order_id(&self) -> Option<u64> {
+        match *self {
+            OrderRef::Exchange(_) => None,
+            OrderRef::Client(client_order_id) => Some(client_order_id)
+        }
+    }
 }
 
 /////////////
 // STRUCTS //
 /////////////
 
+/// a record of a single fill of an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionReport {
+    /// unique id of this report
+    pub execution_id: u64,
+    pub order_id: u32,
+    /// price the shares traded at
+    pub price: u64,
+    /// number of shares traded in this fill
+    pub quantity: u64,
+    /// number of shares still open on the order after this fill
+    pub leaves_quantity: u64,
+    /// number of shares traded on the order so far, including this fill
+    pub cumulative_quantity: u64,
+    /// average price over every fill of the order so far
+    pub average_price: u64,
+    pub liquidity: Liquidity,
+    /// time of the fill in nanoseconds since the unix epoch
+    pub timestamp: u64
+}
+
 #[derive(Getters, Clone, Hash, Eq, PartialEq, Debug)]
 pub struct Symbol {
     ticker: String,
@@ -119,41 +414,114 @@ impl Symbol {
     }
 }
 
-/// a struct containing important information about an account
-// TODO: add getter/setter methods
+/// a struct containing important information about an account.
+/// balances are in the same units as prices, i.e. dollars multiplied by 1000
+#[derive(Clone, Debug)]
 pub struct Account {
     positions: HashMap<String, i64>,
     username: String,
-    password: String,
-    initial: u64,
-    balance: u64,
-    id: u32
+    password_hash: String,
+    salt: String,
+    balance: i64,
+    id: u32,
+    self_trade_prevention: Option<SelfTradePrevention>
 }
 
 impl Account {
-    /// creates a new Account with the specified id and initial balance
+    /// creates a new Account with the specified id, credentials, initial balance and starting positions
     /// 
     /// # Arguments
     /// 
     /// * `id` - the unique account id for this account
+    /// * `username` - the name the account logs in with
+    /// * `password_hash` - hex encoded sha256 hash of `salt` followed by the password
+    /// * `salt` - the salt the password is hashed with
     /// * `initial` - the initial balance that this account started with
-    pub fn new(id: u32, initial: u64) -> Account {
+    /// * `positions` - the number of shares held in each symbol at the start, negative for short positions
+    /// * `self_trade_prevention` - the mode used for this account's orders that don't specify one
+    pub fn new(
+        id: u32,
+        username: String,
+        password_hash: String,
+        salt: String,
+        initial: u64,
+        positions: HashMap<String, i64>,
+        self_trade_prevention: Option<SelfTradePrevention>,
+    ) -> Account {
         Account {
-            positions: HashMap::new(),
-            username: String::from(""),
-            password: String::from(""),
-            initial: initial,
-            balance: 0,
-            id: id
+            positions: positions,
+            username: username,
+            password_hash: password_hash,
+            salt: salt,
+            balance: initial as i64,
+            id: id,
+            self_trade_prevention: self_trade_prevention
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// returns true if `password` hashed with this account's salt matches the stored hash
+    pub fn check_password(&self, password: &str) -> bool {
+        hash_password(&self.salt, password) == self.password_hash
+    }
+
+    pub fn balance(&self) -> i64 {
+        self.balance
+    }
+
+    /// returns the number of shares held in `ticker`, negative for short positions
+    pub fn position(&self, ticker: &str) -> i64 {
+        *self.positions.get(ticker).unwrap_or(&0)
+    }
+
+    /// moves cash and shares for one side of a trade: buys pay for the shares they get, sells are paid for the shares they give
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        let cost = (fill.price * fill.quantity) as i64;
+        let shares = fill.quantity as i64;
+        let position = self.positions.entry(fill.symbol.ticker().to_string()).or_insert(0);
+        match fill.side {
+            OrderSide::Buy => {
+                self.balance -= cost;
+                *position += shares;
+            }
+            OrderSide::Sell => {
+                self.balance += cost;
+                *position -= shares;
+            }
         }
     }
 
-    /// returns the profit/loss of the account
-    fn get_pl(self) -> u64 {
-        return self.balance - self.initial;
+    /// returns the self trade prevention mode used for this account's orders that don't specify one
+    pub fn self_trade_prevention(&self) -> Option<SelfTradePrevention> {
+        self.self_trade_prevention
     }
 }
 
+/// returns the hex encoded sha256 hash of `salt` followed by `password`, as stored in the accounts file
+pub fn hash_password(salt: &str, password: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(password.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// one side of a trade, sent from the matching engine to the accountant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+    pub account_id: u32,
+    pub symbol: &'static Symbol,
+    pub side: OrderSide,
+    pub price: u64,
+    pub quantity: u64
+}
+
 #[derive(Clone, Debug)]
 pub struct PriceInfo {
     symbol: &'static Symbol,
@@ -179,6 +547,249 @@ impl PriceInfo {
     }
 }
 
+/// the total quantity resting at a price on one side of the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: u64,
+    pub quantity: u64
+}
+
+impl PriceLevel {
+    pub fn new(price: u64, quantity: u64) -> PriceLevel {
+        PriceLevel {
+            price: price,
+            quantity: quantity
+        }
+    }
+}
+
+/// a change to the quantity resting at a price on one side of the book. a quantity of 0 removes the level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelUpdate {
+    pub side: OrderSide,
+    pub price: u64,
+    pub quantity: u64
+}
+
+impl LevelUpdate {
+    pub fn new(side: OrderSide, price: u64, quantity: u64) -> LevelUpdate {
+        LevelUpdate {
+            side: side,
+            price: price,
+            quantity: quantity
+        }
+    }
+}
+
+/// the best price levels of both sides of the book of a symbol, best price first.
+/// a side has fewer levels than the configured depth when the book doesn't hold that many prices
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthInfo {
+    symbol: &'static Symbol,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>
+}
+
+impl DepthInfo {
+    pub fn new(symbol: &'static Symbol, bids: Vec<PriceLevel>, asks: Vec<PriceLevel>) -> DepthInfo {
+        DepthInfo {
+            symbol: symbol,
+            bids: bids,
+            asks: asks
+        }
+    }
+
+    pub fn get_symbol(&self) -> &Symbol {
+        self.symbol
+    }
+}
+
+/// a match between an incoming order and a resting one, at the resting order's price
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trade {
+    symbol: &'static Symbol,
+    pub trade_id: u64,
+    pub price: u64,
+    pub quantity: u64,
+    // side of the incoming order, which took the liquidity
+    pub aggressor_side: OrderSide,
+    // nanoseconds since the unix epoch
+    pub timestamp: u64
+}
+
+impl Trade {
+    pub fn new(symbol: &'static Symbol, trade_id: u64, price: u64, quantity: u64, aggressor_side: OrderSide, timestamp: u64) -> Trade {
+        Trade {
+            symbol: symbol,
+            trade_id: trade_id,
+            price: price,
+            quantity: quantity,
+            aggressor_side: aggressor_side,
+            timestamp: timestamp
+        }
+    }
+
+    pub fn get_symbol(&self) -> &Symbol {
+        self.symbol
+    }
+}
+
+/// a change to an order resting in a book, as published on the order by order feed.
+/// orders go by an anonymized reference instead of their order id, and get a new one whenever a replace sends them
+/// to the back of the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderEvent {
+    // reference, side, price and quantity of an order that came to rest at the back of its price level
+    Added(u64, OrderSide, u64, u64),
+    // reference of a resting order, the quantity of it that traded at its price and the id of the trade
+    Executed(u64, u64, u64),
+    // reference of a resting order and the quantity taken off it, all that was left when the order is gone
+    Cancelled(u64, u64),
+    // reference of a resting order that's gone, and the new reference, price and quantity it rests under
+    // at the back of its price level, on the same side
+    Replaced(u64, u64, u64, u64),
+}
+
+/// an order by order event of a symbol
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderEventInfo {
+    symbol: &'static Symbol,
+    pub event: OrderEvent
+}
+
+impl OrderEventInfo {
+    pub fn new(symbol: &'static Symbol, event: OrderEvent) -> OrderEventInfo {
+        OrderEventInfo {
+            symbol: symbol,
+            event: event
+        }
+    }
+
+    pub fn get_symbol(&self) -> &Symbol {
+        self.symbol
+    }
+}
+
+/// an order in a `RestingOrders` book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestingOrder {
+    pub order_ref: u64,
+    pub quantity: u64
+}
+
+/// every order resting in the book of a symbol, in priority order, as rebuilt from order by order events
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestingOrders {
+    symbol: &'static Symbol,
+    pub bids: BTreeMap<u64, VecDeque<RestingOrder>>,
+    pub asks: BTreeMap<u64, VecDeque<RestingOrder>>,
+    // side and price of every order, by reference
+    locations: HashMap<u64, (OrderSide, u64)>
+}
+
+impl RestingOrders {
+    pub fn new(symbol: &'static Symbol) -> RestingOrders {
+        RestingOrders {
+            symbol: symbol,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            locations: HashMap::new()
+        }
+    }
+
+    pub fn get_symbol(&self) -> &Symbol {
+        self.symbol
+    }
+
+    /// applies an event to the book. events about orders that aren't in it are ignored
+    pub fn apply(&mut self, event: &OrderEvent) {
+        match *event {
+            OrderEvent::Added(order_ref, side, price, quantity) => self.add(order_ref, side, price, quantity),
+            OrderEvent::Executed(order_ref, quantity, _) | OrderEvent::Cancelled(order_ref, quantity) => self.take(order_ref, quantity),
+            OrderEvent::Replaced(order_ref, new_order_ref, price, quantity) => {
+                if let Some(&(side, _)) = self.locations.get(&order_ref) {
+                    self.take(order_ref, u64::MAX);
+                    self.add(new_order_ref, side, price, quantity);
+                }
+            }
+        }
+    }
+
+    /// returns the events that rebuild the book from empty: every order added in priority order,
+    /// bids then asks, best price first
+    pub fn events(&self) -> Vec<OrderEvent> {
+        let bids = self.bids.iter().rev().map(|level| (OrderSide::Buy, level));
+        let asks = self.asks.iter().map(|level| (OrderSide::Sell, level));
+        bids.chain(asks)
+            .flat_map(|(side, (price, order_lst))| {
+                order_lst.iter().map(move |order| OrderEvent::Added(order.order_ref, side, *price, order.quantity))
+            })
+            .collect()
+    }
+
+    fn levels(&mut self, side: OrderSide) -> &mut BTreeMap<u64, VecDeque<RestingOrder>> {
+        match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        }
+    }
+
+    fn add(&mut self, order_ref: u64, side: OrderSide, price: u64, quantity: u64) {
+        self.locations.insert(order_ref, (side, price));
+        self.levels(side).entry(price).or_default().push_back(RestingOrder {
+            order_ref: order_ref,
+            quantity: quantity
+        });
+    }
+
+    /// takes `quantity` off an order, taking the order out of the book once nothing is left
+    fn take(&mut self, order_ref: u64, quantity: u64) {
+        let (side, price) = match self.locations.get(&order_ref) {
+            Some(location) => *location,
+            None => return,
+        };
+        let levels = self.levels(side);
+        let order_lst = levels.get_mut(&price).expect("[ERROR] resting order without a price level");
+        let position = order_lst.iter().position(|order| order.order_ref == order_ref).expect("[ERROR] resting order not in its price level");
+        if quantity < order_lst[position].quantity {
+            order_lst[position].quantity -= quantity;
+            return;
+        }
+
+        order_lst.remove(position);
+        if order_lst.is_empty() {
+            levels.remove(&price);
+        }
+        self.locations.remove(&order_ref);
+    }
+}
+
+/// the feeds of the market data provider, which peers subscribe to per symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feed {
+    // best bid and ask of every symbol, with the quantity at each
+    TopOfBook,
+    // the best price levels of both sides of every book, with the total quantity at each
+    Depth,
+    // every trade of every symbol
+    Trades,
+    // every order resting in every book, one event per change
+    Orders,
+}
+
+/// an update to the market data of a symbol, sent by the matching engine to the market data provider
+/// and handed to market data clients
+#[derive(Clone, Debug)]
+pub enum MarketData {
+    TopOfBook(PriceInfo),
+    Depth(DepthInfo),
+    Trade(Trade),
+    Order(OrderEventInfo),
+    // every order resting in a book, handed out by clients when they start from a snapshot of the order by
+    // order feed, and never sent by the matching engine
+    Orders(RestingOrders),
+}
+
 /*
 pub struct SubscribeInfo {
     account_id: u32,
@@ -198,41 +809,65 @@ impl SubscribeInfo {
 
 pub struct StatusInfo {
     account_id: u32,
-    order_id: u32,
-    response_sender: Sender<OrderStatus>
+    order: OrderRef,
+    response_sender: Sender<StatusReport>
 }
 
 impl StatusInfo {
-    pub fn new(account_id: u32, order_id: u32, response_sender: Sender<OrderStatus>) -> StatusInfo {
+    pub fn new(account_id: u32, order: OrderRef, response_sender: Sender<StatusReport>) -> StatusInfo {
         StatusInfo {
             account_id: account_id,
-            order_id: order_id,
+            order: order,
             response_sender: response_sender
         }
     }
 
-    pub fn consume(self) -> (u32, u32, Sender<OrderStatus>) {
-        (self.account_id, self.order_id, self.response_sender)
+    pub fn consume(self) -> (u32, OrderRef, Sender<StatusReport>) {
+        (self.account_id, self.order, self.response_sender)
     }
 }
 
 pub struct CancelInfo {
     account_id: u32,
-    order_id: u32,
-    response_sender: Sender<OrderStatus>
+    order: OrderRef,
+    response_sender: Sender<StatusReport>
 }
 
 impl CancelInfo {
-    pub fn new(account_id: u32, order_id: u32, response_sender: Sender<OrderStatus>) -> CancelInfo {
+    pub fn new(account_id: u32, order: OrderRef, response_sender: Sender<StatusReport>) -> CancelInfo {
         CancelInfo {
+            account_id: account_id,
+            order: order,
+            response_sender: response_sender
+        }
+    }
+
+    pub fn consume(self) -> (u32, OrderRef, Sender<StatusReport>) {
+        (self.account_id, self.order, self.response_sender)
+    }
+}
+
+pub struct ReplaceInfo {
+    account_id: u32,
+    order_id: u32,
+    price: Option<u64>,
+    quantity: Option<u64>,
+    response_sender: Sender<StatusReport>
+}
+
+impl ReplaceInfo {
+    pub fn new(account_id: u32, order_id: u32, price: Option<u64>, quantity: Option<u64>, response_sender: Sender<StatusReport>) -> ReplaceInfo {
+        ReplaceInfo {
             account_id: account_id,
             order_id: order_id,
+            price: price,
+            quantity: quantity,
             response_sender: response_sender
         }
     }
 
-    pub fn consume(self) -> (u32, u32, Sender<OrderStatus>) {
-        (self.account_id, self.order_id, self.response_sender)
+    pub fn consume(self) -> (u32, u32, Option<u64>, Option<u64>, Sender<StatusReport>) {
+        (self.account_id, self.order_id, self.price, self.quantity, self.response_sender)
     }
 }
 
@@ -241,33 +876,43 @@ pub struct OrderInfo {
     symbol: &'static Symbol,
     order_type: OrderType,
     side: OrderSide,
+    time_in_force: TimeInForce,
+    self_trade_prevention: Option<SelfTradePrevention>,
     quantity: u64,
-    response_sender: Sender<OrderStatus>
+    client_order_id: Option<u64>,
+    response_sender: Sender<StatusReport>
 }
 
 impl OrderInfo {
-    pub fn new(account_id: u32,symbol: &'static Symbol,order_type: OrderType,order_side: OrderSide,quantity: u64,response_sender: Sender<OrderStatus>) -> OrderInfo {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(account_id: u32,symbol: &'static Symbol,order_type: OrderType,order_side: OrderSide,time_in_force: TimeInForce,self_trade_prevention: Option<SelfTradePrevention>,quantity: u64,client_order_id: Option<u64>,response_sender: Sender<StatusReport>) -> OrderInfo {
         OrderInfo {
             account_id: account_id,
             symbol: symbol,
             order_type: order_type,
             side: order_side,
+            time_in_force: time_in_force,
+            self_trade_prevention: self_trade_prevention,
             quantity: quantity,
+            client_order_id: client_order_id,
             response_sender: response_sender
         }
     }
 
-    pub fn consume(self, order_id: u32) -> (Order, Sender<OrderStatus>) {
+    pub fn consume(self, order_id: u32) -> (Order, Sender<StatusReport>) {
         (Order {
             id: order_id,
             account_id: self.account_id,
             symbol: self.symbol,
             order_type: self.order_type,
             side: self.side,
+            time_in_force: self.time_in_force,
+            self_trade_prevention: self.self_trade_prevention,
             quantity: self.quantity,
             remaining_quantity: self.quantity,
             cost: 0 as u64,
-            is_canceled: false
+            is_canceled: false,
+            client_order_id: self.client_order_id
         },
         self.response_sender)
     }
@@ -285,10 +930,15 @@ pub struct Order {
     pub symbol: &'static Symbol,
     pub order_type: OrderType,
     pub side: OrderSide,
+    pub time_in_force: TimeInForce,
+    // None means the order may trade with orders from the same account
+    pub self_trade_prevention: Option<SelfTradePrevention>,
     pub quantity: u64,
     pub remaining_quantity: u64,
     pub cost: u64,
-    pub is_canceled: bool
+    pub is_canceled: bool,
+    // id the client sent the order with, unique among the orders of its account
+    pub client_order_id: Option<u64>
 }
 
 impl Order {
@@ -328,12 +978,26 @@ impl Order {
         }
     }
 
-    pub fn fill_shares(&mut self, num_filled : u64, cost_per_share : u64) -> () {
+    /// fills `num_filled` shares of the order at `cost_per_share` and returns the report for the fill
+    pub fn fill_shares(&mut self, num_filled : u64, cost_per_share : u64, execution_id: u64, liquidity: Liquidity, timestamp: u64) -> ExecutionReport {
         if num_filled > self.remaining_quantity {
             panic!("can't fill shares > curr quantity");
         }
         self.remaining_quantity -= num_filled;
         self.cost += (num_filled as u64) * cost_per_share;
+
+        let cumulative_quantity = self.quantity - self.remaining_quantity;
+        ExecutionReport {
+            execution_id: execution_id,
+            order_id: self.id,
+            price: cost_per_share,
+            quantity: num_filled,
+            leaves_quantity: self.remaining_quantity,
+            cumulative_quantity: cumulative_quantity,
+            average_price: self.cost.checked_div(cumulative_quantity).unwrap_or(0),
+            liquidity: liquidity,
+            timestamp: timestamp
+        }
     }
 
     pub fn is_fully_filled(&self) -> bool {
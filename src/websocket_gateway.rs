@@ -0,0 +1,267 @@
+use std::error::Error;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::{Message, WebSocket};
+
+use gateway::{authenticate_json, NO_ORDER_ID};
+use json_protocol::{auth_response_to_json, json_to_cmd, price_info_to_json, status_to_json};
+use types::*;
+
+// WebSocket version of the json protocol, for browser clients. every text message is a single
+// json request or response, and once logged in the connection is also sent every market data update
+
+// how long to wait for a message before sending out statuses and market data
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// how long a new connection has for its handshake and login
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+// largest message a client can send, anything longer closes the connection
+const MAX_MESSAGE_SIZE: usize = 1024;
+
+pub struct WebSocketGateway {
+    ip_addr: &'static str,
+    port: u32,
+    order_channel: Sender<Cmd>,
+    subscribe_channel: Sender<Sender<PriceInfo>>,
+}
+
+impl WebSocketGateway {
+    /// creates a new WebSocketGateway
+    ///
+    /// # Parameters
+    ///
+    /// * `ip_addr` - the address to listen on
+    /// * `port` - the port to listen on
+    /// * `order_channel` - the channel commands are sent to the matching engine on
+    /// * `subscribe_channel` - the channel each connection subscribes to market data on
+    pub fn new(ip_addr: &'static str, port: u32, order_channel: Sender<Cmd>, subscribe_channel: Sender<Sender<PriceInfo>>) -> Self {
+        WebSocketGateway {
+            ip_addr: ip_addr,
+            port: port,
+            order_channel: order_channel,
+            subscribe_channel: subscribe_channel,
+        }
+    }
+
+    pub fn run(&self) {
+        let listener = TcpListener::bind(format!("{}:{}", self.ip_addr, self.port))
+            .expect("[ERROR] couldn't start websocket gateway");
+        println!("[INFO] websocket gateway started on {}:{}", self.ip_addr, self.port);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    let addr = match s.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            println!("[ERROR] websocket connection went away: {}", e);
+                            continue;
+                        }
+                    };
+                    println!("[INFO] new websocket connection: {}", addr);
+
+                    let order_channel = self.order_channel.clone();
+                    let subscribe_channel = self.subscribe_channel.clone();
+                    thread::Builder::new()
+                        .name(format!("websocket {}", addr))
+                        .spawn(move || match WebSocketClient::connect(s, order_channel, subscribe_channel) {
+                            Ok(mut client) => client.run(),
+                            Err(e) => println!("[ERROR] websocket connection from {} failed: {}", addr, e),
+                        })
+                        .expect("[ERROR] failed to create websocket client thread");
+                }
+                Err(e) => {
+                    println!("[ERROR] websocket connection failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// a logged in WebSocket connection
+struct WebSocketClient {
+    account_id: u32,
+    socket: WebSocket<TcpStream>,
+    order_channel: Sender<Cmd>,
+    // statuses of the client's commands and orders
+    sender: Sender<StatusReport>,
+    receiver: Receiver<StatusReport>,
+    market_data: Receiver<PriceInfo>,
+}
+
+impl WebSocketClient {
+    /// does the WebSocket handshake, then waits for the json login the connection has to start with
+    /// and subscribes it to market data
+    fn connect(stream: TcpStream, order_channel: Sender<Cmd>, subscribe_channel: Sender<Sender<PriceInfo>>) -> Result<WebSocketClient, Box<dyn Error>> {
+        stream.set_read_timeout(Some(LOGIN_TIMEOUT))?;
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE_SIZE),
+            max_frame_size: Some(MAX_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        let mut socket = tungstenite::accept_with_config(stream, Some(config)).map_err(|e| e.to_string())?;
+
+        let login = loop {
+            match socket.read()? {
+                Message::Text(text) => break text,
+                Message::Close(_) => return Err("connection closed before logging in".into()),
+                _ => {}
+            }
+        };
+        let account_id = match authenticate_json(&login) {
+            Ok(account_id) => account_id,
+            Err(e) => {
+                socket.send(Message::Text(auth_response_to_json(Err(&e.to_string()))))?;
+                socket.close(None)?;
+                socket.flush()?;
+                return Err(e);
+            }
+        };
+        socket.send(Message::Text(auth_response_to_json(Ok(account_id))))?;
+        socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        println!("[INFO] websocket client logged in with account id {}", account_id);
+
+        let (market_data_sender, market_data) = channel();
+        subscribe_channel
+            .send(market_data_sender)
+            .map_err(|_| "market data provider has stopped")?;
+        let (sender, receiver) = channel();
+        Ok(WebSocketClient {
+            account_id: account_id,
+            socket: socket,
+            order_channel: order_channel,
+            sender: sender,
+            receiver: receiver,
+            market_data: market_data,
+        })
+    }
+
+    fn run(&mut self) {
+        if let Err(e) = self.serve() {
+            println!("[ERROR] websocket client with account id {} failed: {}", self.account_id, e);
+        }
+        println!("[INFO] websocket client with account id {} disconnected", self.account_id);
+    }
+
+    fn serve(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => self.handle_request(&text)?,
+                Ok(Message::Binary(_)) => self.reject(RejectReason::InvalidJson),
+                // sends back the close frame tungstenite queued up
+                Ok(Message::Close(_)) => return Ok(self.socket.flush()?),
+                // pings are answered by tungstenite
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e @ tungstenite::Error::Capacity(_)) => {
+                    let _ = self.socket.send(Message::Text(status_to_json(&StatusReport::new(OrderStatus::Rejected(NO_ORDER_ID, RejectReason::MessageTooLong), None))));
+                    return Err(e.into());
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            while let Ok(report) = self.receiver.try_recv() {
+                self.socket.send(Message::Text(status_to_json(&report)))?;
+            }
+            while let Ok(price_info) = self.market_data.try_recv() {
+                self.socket.send(Message::Text(price_info_to_json(&price_info)))?;
+            }
+        }
+    }
+
+    fn handle_request(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        match json_to_cmd(text.trim(), self.account_id, self.sender.clone()) {
+            Ok(cmd) => self.order_channel.send(cmd).map_err(|_| "matching engine has stopped")?,
+            Err(reason) => self.reject(reason),
+        }
+        Ok(())
+    }
+
+    /// rejects a command that never made it to the matching engine
+    fn reject(&self, reason: RejectReason) {
+        println!("[ERROR] rejected websocket command from account {}: {}", self.account_id, reason);
+        // the receiver lives as long as the client, so this can't fail
+        let _ = self.sender.send(StatusReport::new(OrderStatus::Rejected(NO_ORDER_ID, reason), None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::SYMBOLS;
+    use serde_json::Value;
+    use tungstenite::client::client;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// connects a browser-like client to a new connection, with the test standing in for
+    /// the matching engine and the market data provider
+    fn connect() -> (WebSocket<TcpStream>, Receiver<Cmd>, Receiver<Sender<PriceInfo>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let (order_channel, orders) = channel();
+        let (subscribe_channel, subscriptions) = channel();
+        thread::spawn(move || {
+            let (s, _) = listener.accept().unwrap();
+            if let Ok(mut client) = WebSocketClient::connect(s, order_channel, subscribe_channel) {
+                client.run();
+            }
+        });
+        let (socket, _) = client("ws://127.0.0.1/", stream).unwrap();
+        (socket, orders, subscriptions)
+    }
+
+    fn send(socket: &mut WebSocket<TcpStream>, text: &str) {
+        socket.send(Message::Text(text.to_string())).unwrap();
+    }
+
+    fn receive(socket: &mut WebSocket<TcpStream>) -> Value {
+        match socket.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("expected a text message, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_login_is_checked() {
+        let (mut socket, _, _) = connect();
+        send(&mut socket, r#"{"username": "alice", "password": "wrong-password"}"#);
+        assert_eq!(receive(&mut socket)["status"], "AuthRejected");
+    }
+
+    #[test]
+    fn test_orders_and_market_data() {
+        let (mut socket, orders, subscriptions) = connect();
+        send(&mut socket, r#"{"username": "alice", "password": "alice-password"}"#);
+        assert_eq!(receive(&mut socket), json!({"status": "Authenticated", "account_id": 1}));
+
+        let market_data = subscriptions.recv_timeout(TIMEOUT).unwrap();
+        market_data.send(PriceInfo::new(SYMBOLS.get("GOOG").unwrap(), 100500, 10, 101000, 20)).unwrap();
+        let quote = receive(&mut socket);
+        assert_eq!((quote["market_data"].as_str(), quote["symbol"].as_str()), (Some("Quote"), Some("GOOG")));
+
+        send(
+            &mut socket,
+            r#"{"command": "Execute", "symbol": "GOOG", "side": "Buy", "order_type": "Market", "quantity": 10, "client_order_id": 11}"#,
+        );
+        match orders.recv_timeout(TIMEOUT).unwrap() {
+            Cmd::Execute(order_info) => {
+                let (order, sender) = order_info.consume(5);
+                assert_eq!((order.account_id, order.quantity, order.client_order_id), (1, 10, Some(11)));
+                sender.send(StatusReport::new(OrderStatus::Waiting(5), order.client_order_id)).unwrap();
+            }
+            _ => panic!("expected an order"),
+        }
+        assert_eq!(receive(&mut socket), json!({"status": "Waiting", "order_id": 5, "client_order_id": 11}));
+
+        send(&mut socket, "not json");
+        let rejected = receive(&mut socket);
+        assert_eq!((rejected["status"].as_str(), rejected["code"].as_u64()), (Some("Rejected"), Some(19)));
+        assert!(orders.try_recv().is_err());
+    }
+}